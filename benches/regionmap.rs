@@ -0,0 +1,60 @@
+//! benches/regionmap.rs: Compare the union-find and flood-fill labelers.
+//!
+//! Run with `cargo bench --bench regionmap`. Times both labelers on the
+//! test images, then on multi-megapixel boards tiled from `reso_logo.png`.
+
+use std::hint::{black_box};
+use std::time::{Duration, Instant};
+
+use reso::{ReselBoard, RegionMap, load_image_from_filename};
+
+/// Run `f` until at least `min_time` has passed, returning the mean time per run.
+/// One untimed run first, so both labelers start with a warmed-up allocator.
+fn time_per_run<T>(min_time: Duration, mut f: impl FnMut() -> T) -> Duration {
+  black_box(f());
+  let (start, mut runs) = (Instant::now(), 0);
+  while runs == 0 || start.elapsed() < min_time {
+    black_box(f());
+    runs += 1;
+  }
+  start.elapsed() / runs
+}
+
+/// Tile a board `nx` by `ny` times
+fn tile(rb: &ReselBoard, nx: usize, ny: usize) -> ReselBoard {
+  let board = (0..rb.width * nx).map(|x| {
+    (0..rb.height * ny).map(|y| rb.board[x % rb.width][y % rb.height]).collect()
+  }).collect();
+  ReselBoard::new(board).unwrap()
+}
+
+fn compare(name: &str, rb: &ReselBoard, min_time: Duration) {
+  let union_find = time_per_run(min_time, || RegionMap::union_find(rb));
+  let flood_fill = time_per_run(min_time, || RegionMap::flood_fill(rb));
+  println!(
+    "{:<40} {:>6}x{:<6} union_find {:>12.3?}  flood_fill {:>12.3?}  ({:.2}x)",
+    name, rb.width, rb.height, union_find, flood_fill,
+    flood_fill.as_secs_f64() / union_find.as_secs_f64()
+  );
+}
+
+fn main() {
+  let mut filenames: Vec<String> = std::fs::read_dir("./src/testing").unwrap()
+    .map(|entry| entry.unwrap().path().to_str().unwrap().to_string())
+    .filter(|filename| filename.ends_with(".png"))
+    .collect();
+  filenames.sort();
+
+  for filename in &filenames {
+    let rb = ReselBoard::from(load_image_from_filename(filename).unwrap());
+    compare(filename, &rb, Duration::from_millis(200));
+  }
+
+  let logo = ReselBoard::from(load_image_from_filename("./reso_logo.png").unwrap());
+  for tiles in [4, 8, 16] {
+    let rb = tile(&logo, tiles, tiles);
+    compare(&format!("reso_logo.png tiled {}x{}", tiles, tiles), &rb, Duration::from_secs(1));
+  }
+}
+
+// eof
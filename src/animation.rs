@@ -0,0 +1,273 @@
+//! animation.rs: Stream simulation frames into a single GIF or APNG.
+//!
+//! Writing one PNG per iteration floods the output directory. An
+//! AnimationWriter takes frames (usually from `ResoCircuit::get_image()`
+//! after `update_pixels()`) and writes them into one animated file.
+//!
+//! Frames can be upscaled by an integer factor, since Reso circuits are
+//! usually tiny pixel art. Upscaling uses nearest-neighbor, so resels stay
+//! crisp.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::animation::{AnimationFormat, AnimationWriter};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+//! let (width, height) = (rc.rb.width as u32, rc.rb.height as u32);
+//!
+//! let filename = std::env::temp_dir().join("reso_doctest_half_adder.gif");
+//! let mut writer = AnimationWriter::create(
+//!   filename.to_str().unwrap(), AnimationFormat::Gif,
+//!   width, height, 3, 100, 4
+//! ).unwrap();
+//!
+//! writer.write_frame(rc.get_image().unwrap()).unwrap();
+//! for _ in 0..2 {
+//!   rc.iterate();
+//!   rc.update_pixels();
+//!   writer.write_frame(rc.get_image().unwrap()).unwrap();
+//! }
+//! writer.finish().unwrap();
+//! ```
+
+use std::cell::{RefCell};
+use std::fs::{File};
+use std::io::{BufWriter, Write};
+use std::rc::{Rc};
+
+use image::{DynamicImage, RgbaImage, Delay, Frame};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{FilterType, resize};
+
+use crate::error::{ResoError};
+
+/// Which kind of animated file to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+  Gif,
+  Apng,
+}
+
+/// The file behind a GIF encoder, shared with the AnimationWriter.
+/// GifEncoder only writes the GIF trailer when it's dropped, ignoring any
+/// error, so this keeps the first error and lets `finish` flush the file.
+#[derive(Clone)]
+struct GifFile(Rc<RefCell<(BufWriter<File>, Option<std::io::Error>)>>);
+
+impl Write for GifFile {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let (file, error) = &mut *self.0.borrow_mut();
+    file.write(buf).inspect_err(|err| {
+      error.get_or_insert_with(|| std::io::Error::new(err.kind(), err.to_string()));
+    })
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    let (file, error) = &mut *self.0.borrow_mut();
+    file.flush().inspect_err(|err| {
+      error.get_or_insert_with(|| std::io::Error::new(err.kind(), err.to_string()));
+    })
+  }
+}
+
+/// The encoder behind an AnimationWriter
+enum Encoder {
+  Gif(GifEncoder<GifFile>, GifFile),
+  Apng(png::Writer<BufWriter<File>>),
+}
+
+/// Streams frames into a GIF or APNG file, one frame at a time.
+///
+/// Animations loop forever.
+pub struct AnimationWriter {
+  encoder:  Encoder,
+  delay_ms: u32,
+  upscale:  u32,
+}
+
+impl AnimationWriter {
+  /// Create `filename` and prepare to write frames to it.
+  ///
+  /// - `width`, `height`: Size of each frame before upscaling
+  /// - `num_frames`: How many frames will be written. APNG needs this up
+  ///   front, and `finish` fails if a different number was written.
+  /// - `delay_ms`: How long each frame is shown
+  /// - `upscale`: Integer factor to upscale each frame by (1 for none)
+  pub fn create(
+    filename: &str,
+    format: AnimationFormat,
+    width: u32, height: u32,
+    num_frames: u32,
+    delay_ms: u32,
+    upscale: u32,
+  ) -> Result<AnimationWriter, ResoError> {
+    let upscale = upscale.max(1);
+    let file = BufWriter::new(File::create(filename)?);
+
+    let encoder = match format {
+      AnimationFormat::Gif => {
+        let gif_file = GifFile(Rc::new(RefCell::new((file, None))));
+        let mut encoder = GifEncoder::new(gif_file.clone());
+        encoder.set_repeat(Repeat::Infinite)?;
+        Encoder::Gif(encoder, gif_file)
+      },
+      AnimationFormat::Apng => {
+        let mut encoder = png::Encoder::new(file, width * upscale, height * upscale);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(num_frames, 0)?; // 0 plays = loop forever
+        // APNG delays are u16 fractions of a second
+        encoder.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+        // Makes `finish` check all `num_frames` frames were written
+        encoder.validate_sequence(true);
+        Encoder::Apng(encoder.write_header()?)
+      },
+    };
+
+    Ok(AnimationWriter { encoder, delay_ms, upscale })
+  }
+
+  /// Upscale and append one frame to the animation
+  pub fn write_frame(&mut self, image: &DynamicImage) -> Result<(), ResoError> {
+    let frame = upscale_image(image, self.upscale);
+    match &mut self.encoder {
+      Encoder::Gif(encoder, _) => {
+        encoder.encode_frame(Frame::from_parts(
+          frame, 0, 0, Delay::from_numer_denom_ms(self.delay_ms, 1)
+        ))?;
+      },
+      Encoder::Apng(writer) => {
+        writer.write_image_data(frame.as_raw())?;
+      },
+    }
+    Ok(())
+  }
+
+  /// Finish writing the animation and flush it to disk
+  pub fn finish(self) -> Result<(), ResoError> {
+    match self.encoder {
+      Encoder::Gif(encoder, gif_file) => {
+        // Writes the trailer
+        drop(encoder);
+        let (file, error) = &mut *gif_file.0.borrow_mut();
+        if let Some(err) = error.take() {
+          return Err(err.into())
+        }
+        file.flush()?;
+      },
+      Encoder::Apng(writer) => { writer.finish()?; },
+    }
+    Ok(())
+  }
+}
+
+/// Upscale an image by an integer factor with nearest-neighbor, so each
+/// resel becomes a `scale` x `scale` block of pixels.
+pub fn upscale_image(image: &DynamicImage, scale: u32) -> RgbaImage {
+  let image = image.to_rgba8();
+  if scale <= 1 {
+    return image
+  }
+  resize(
+    &image,
+    image.width() * scale, image.height() * scale,
+    FilterType::Nearest
+  )
+}
+
+#[cfg(test)]
+mod animation_tests {
+  use super::*;
+  use crate::resocircuit::{ResoCircuit};
+  use image::{AnimationDecoder, GenericImageView};
+  use image::codecs::gif::{GifDecoder};
+  use image::codecs::png::{PngDecoder};
+
+  /// Simulate the half adder into an animation, returning the frames we wrote
+  fn write_half_adder(filename: &str, format: AnimationFormat) -> Vec<RgbaImage> {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+    let mut writer = AnimationWriter::create(
+      filename, format, rc.rb.width as u32, rc.rb.height as u32, 3, 50, 2
+    ).unwrap();
+
+    let mut expected = vec![upscale_image(rc.get_image().unwrap(), 2)];
+    writer.write_frame(rc.get_image().unwrap()).unwrap();
+    for _ in 0..2 {
+      rc.iterate();
+      rc.update_pixels();
+      writer.write_frame(rc.get_image().unwrap()).unwrap();
+      expected.push(upscale_image(rc.get_image().unwrap(), 2));
+    }
+    writer.finish().unwrap();
+    expected
+  }
+
+  #[test]
+  fn test_upscale_image() {
+    let image = DynamicImage::ImageRgba8(
+      RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8, 0, 0, 255]))
+    );
+    let upscaled = upscale_image(&image, 3);
+    assert_eq!(upscaled.dimensions(), (6, 3));
+    for (x, y, pixel) in upscaled.enumerate_pixels() {
+      assert_eq!(*pixel, image.get_pixel(x / 3, y / 3));
+    }
+    assert_eq!(upscale_image(&image, 1), image.to_rgba8());
+  }
+
+  #[test]
+  fn test_gif_roundtrip() {
+    let filename = std::env::temp_dir().join("reso_test_half_adder.gif");
+    let filename = filename.to_str().unwrap();
+    let expected = write_half_adder(filename, AnimationFormat::Gif);
+
+    let frames = GifDecoder::new(File::open(filename).unwrap()).unwrap()
+      .into_frames().collect_frames().unwrap();
+    assert_eq!(frames.len(), expected.len());
+    for (frame, expected) in frames.iter().zip(expected) {
+      assert_eq!(*frame.buffer(), expected);
+      assert_eq!(frame.delay().numer_denom_ms(), (50, 1));
+    }
+  }
+
+  #[test]
+  fn test_apng_roundtrip() {
+    let filename = std::env::temp_dir().join("reso_test_half_adder.apng");
+    let filename = filename.to_str().unwrap();
+    let expected = write_half_adder(filename, AnimationFormat::Apng);
+
+    let frames = PngDecoder::new(File::open(filename).unwrap()).unwrap()
+      .apng().into_frames().collect_frames().unwrap();
+    assert_eq!(frames.len(), expected.len());
+    for (frame, expected) in frames.iter().zip(expected) {
+      assert_eq!(*frame.buffer(), expected);
+    }
+  }
+
+  #[test]
+  fn test_apng_wrong_frame_count() {
+    let filename = std::env::temp_dir().join("reso_test_wrong_count.apng");
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+    let mut writer = AnimationWriter::create(
+      filename.to_str().unwrap(), AnimationFormat::Apng, 2, 2, 2, 100, 1
+    ).unwrap();
+    writer.write_frame(&image).unwrap();
+    assert!(writer.finish().is_err());
+  }
+
+  #[test]
+  #[cfg(target_os = "linux")]
+  fn test_gif_write_error() {
+    // Writes to /dev/full fail, which `finish` reports rather than dropping
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+    let mut writer = AnimationWriter::create(
+      "/dev/full", AnimationFormat::Gif, 2, 2, 1, 100, 1
+    ).unwrap();
+    writer.write_frame(&image).unwrap();
+    assert!(writer.finish().is_err());
+  }
+}
+
+// eof
@@ -0,0 +1,302 @@
+//! components.rs: A library of small, verified circuits to stamp onto boards.
+//!
+//! Each component is a text circuit with named ports on its edges, and a
+//! simulation test below checking it does what it says. Stamp one onto a
+//! board with `ReselBoard::stamp` (or `reso stamp`), then draw wires up to
+//! its ports. Leave a blank resel around it, so nothing else touches it.
+//!
+//! | Name          | Ports                      | Delay                            |
+//! | ------------- | -------------------------- | -------------------------------- |
+//! | `not`         | a -> out                   | 1 step                           |
+//! | `or`          | a, b -> out                | 1 step                           |
+//! | `nand`        | a, b -> out                | 1 step                           |
+//! | `sr_latch`    | s, r -> q                  | 1 step to set, 2 to reset        |
+//! | `d_flip_flop` | clk, d -> q                | 3 steps after clk rises          |
+//! | `clock`       | -> out                     | toggles every step               |
+//! | `half_adder`  | a, b -> sum, carry         | 1 step                           |
+//! | `full_adder`  | a, b, c -> sum, carry      | 2 steps                          |
+//!
+//! Components with constant-on (`L`) wires keep them on with an output
+//! touching an input that reads them, so they stay on without a port.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ReselBoard};
+//! use reso::components::{component};
+//! use image::{Rgba};
+//!
+//! let mut rb = ReselBoard::from_filename("./src/testing/reso_logo.png").unwrap();
+//! let nand = component("nand").unwrap();
+//! rb.stamp(0, 0, &nand.board().unwrap(), Rgba([0, 0, 0, 255])).unwrap();
+//! for spec in nand.ports_at(0, 0) {
+//!   println!("{:?} {} {},{}", spec.direction, spec.name, spec.x, spec.y);
+//! }
+//! ```
+
+use crate::error::{ResoError};
+use crate::ports::{PortDirection, PortSpec};
+use crate::reselboard::{ReselBoard, TextOptions, Topology};
+use crate::resocircuit::{ResoCircuit};
+
+use PortDirection::{Input, Output};
+
+/// A circuit in the component library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+  pub name: &'static str,
+  pub description: &'static str,
+  /// The circuit, as strict text (see resel.rs for the characters)
+  pub text: &'static str,
+  /// Ports, relative to the top-left corner
+  pub ports: &'static [(PortDirection, &'static str, usize, usize)],
+}
+
+/// Every component in the library
+pub const COMPONENTS: &[Component] = &[
+  Component {
+    name: "not",
+    description: "out = !a, 1 step later",
+    text: concat!(
+      "o+^=l\n",
+      " +L  \n",
+      " =L  \n",
+    ),
+    ports: &[(Input, "a", 0, 0), (Output, "out", 4, 0)],
+  },
+  Component {
+    name: "or",
+    description: "out = a | b, 1 step later",
+    text: concat!(
+      "o+  \n",
+      " +=l\n",
+      "o+  \n",
+    ),
+    ports: &[(Input, "a", 0, 0), (Input, "b", 0, 2), (Output, "out", 3, 1)],
+  },
+  Component {
+    name: "nand",
+    description: "out = !(a & b), 1 step later",
+    text: concat!(
+      "o++^  \n",
+      " =L^=l\n",
+      "s++^  \n",
+    ),
+    ports: &[(Input, "a", 0, 0), (Input, "b", 0, 2), (Output, "out", 5, 1)],
+  },
+  Component {
+    name: "sr_latch",
+    description: "q is set by s, 1 step later, and reset by r, 2 steps later. s wins while both are on",
+    text: concat!(
+      "s+=ooo\n",
+      "  &+  \n",
+      "   L  \n",
+      "   L  \n",
+      "o+^=  \n",
+      " +L   \n",
+      " =L   \n",
+    ),
+    ports: &[(Input, "s", 0, 0), (Input, "r", 0, 4), (Output, "q", 5, 0)],
+  },
+  Component {
+    name: "d_flip_flop",
+    description: "q = d as it was on the step clk rises, 3 steps later",
+    text: concat!(
+      " =L             \n",
+      " +L     =L  oooo\n",
+      "o+^=L   +L  +& o\n",
+      "o   +&=o+^=LL& o\n",
+      "ooooo  o     &=o\n",
+      "       o+=lll&  \n",
+      "            +&  \n",
+      "s+=s+=sssssss   \n",
+    ),
+    ports: &[(Input, "clk", 0, 2), (Input, "d", 0, 7), (Output, "q", 15, 1)],
+  },
+  Component {
+    name: "clock",
+    description: "out toggles every step",
+    text: concat!(
+      "O+=s\n",
+      "O  s\n",
+      "O=+s\n",
+    ),
+    ports: &[(Output, "out", 3, 1)],
+  },
+  Component {
+    name: "half_adder",
+    description: "sum = a ^ b and carry = a & b, 1 step later",
+    text: concat!(
+      " ^=l\n",
+      "o+  \n",
+      "s+  \n",
+      " &=l\n",
+    ),
+    ports: &[(Input, "a", 0, 1), (Input, "b", 0, 2), (Output, "sum", 3, 0), (Output, "carry", 3, 3)],
+  },
+  Component {
+    name: "full_adder",
+    description: "sum = a ^ b ^ c and carry = majority(a, b, c), 2 steps later",
+    text: concat!(
+      "llllll   \n",
+      "     l   \n",
+      " ^=oo+^=s\n",
+      "o+   &   \n",
+      "s+   =   \n",
+      " &====lll\n",
+    ),
+    ports: &[
+      (Input, "a", 0, 3), (Input, "b", 0, 4), (Input, "c", 0, 0),
+      (Output, "sum", 8, 2), (Output, "carry", 8, 5),
+    ],
+  },
+];
+
+/// Look up a component by name
+pub fn component(name: &str) -> Result<&'static Component, ResoError> {
+  COMPONENTS.iter()
+    .find(|component| component.name == name)
+    .ok_or_else(|| ResoError::UnknownComponent(name.to_string()))
+}
+
+impl Component {
+  /// The component as a board, to stamp or simulate
+  pub fn board(&self) -> Result<ReselBoard, ResoError> {
+    ReselBoard::from_text(self.text, &TextOptions::strict())
+  }
+
+  /// The ports, for the component stamped with its top-left corner at (x,y)
+  pub fn ports_at(&self, x: usize, y: usize) -> Vec<PortSpec> {
+    self.ports.iter().map(|&(direction, name, px, py)| PortSpec {
+      direction, name: name.to_string(), x: x + px, y: y + py
+    }).collect()
+  }
+
+  /// The component on its own, with no wrap-around and its ports attached
+  pub fn circuit(&self) -> Result<ResoCircuit, ResoError> {
+    let mut rc = ResoCircuit::compile(self.board()?.with_topology(Topology::Bounded))?;
+    for spec in self.ports_at(0, 0) {
+      rc.add_port(&spec)?;
+    }
+    Ok(rc)
+  }
+}
+
+#[cfg(test)]
+mod components_tests {
+  use super::*;
+
+  /// Hold the inputs and step `steps` times
+  fn settle(rc: &mut ResoCircuit, inputs: &[(&str, bool)], steps: usize) {
+    for (name, value) in inputs {
+      rc.set_input(name, *value).unwrap();
+    }
+    for _ in 0..steps {
+      rc.iterate();
+    }
+  }
+
+  #[test]
+  fn test_components_compile_cleanly() {
+    for component in COMPONENTS {
+      let rc = component.circuit().unwrap();
+      assert_eq!(rc.lint(), vec![], "{}", component.name);
+      assert_eq!(rc.ports.len(), component.ports.len(), "{}", component.name);
+    }
+    assert!(matches!(component("nope"), Err(ResoError::UnknownComponent(_))));
+  }
+
+  #[test]
+  fn test_not_or_nand() {
+    for a in [false, true] {
+      let mut rc = component("not").unwrap().circuit().unwrap();
+      settle(&mut rc, &[("a", a)], 1);
+      assert_eq!(rc.get_output("out").unwrap(), !a);
+
+      for b in [false, true] {
+        let mut rc = component("or").unwrap().circuit().unwrap();
+        settle(&mut rc, &[("a", a), ("b", b)], 1);
+        assert_eq!(rc.get_output("out").unwrap(), a | b);
+
+        let mut rc = component("nand").unwrap().circuit().unwrap();
+        settle(&mut rc, &[("a", a), ("b", b)], 1);
+        assert_eq!(rc.get_output("out").unwrap(), !(a & b));
+      }
+    }
+  }
+
+  #[test]
+  fn test_adders() {
+    let mut rc = component("half_adder").unwrap().circuit().unwrap();
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+      settle(&mut rc, &[("a", a), ("b", b)], 1);
+      assert_eq!(rc.get_output("sum").unwrap(), a ^ b);
+      assert_eq!(rc.get_output("carry").unwrap(), a & b);
+    }
+
+    let mut rc = component("full_adder").unwrap().circuit().unwrap();
+    for bits in 0..8 {
+      let (a, b, c) = (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0);
+      settle(&mut rc, &[("a", a), ("b", b), ("c", c)], 2);
+      assert_eq!(rc.get_output("sum").unwrap(), a ^ b ^ c, "{} {} {}", a, b, c);
+      assert_eq!(rc.get_output("carry").unwrap(), (a & b) | (c & (a ^ b)), "{} {} {}", a, b, c);
+    }
+  }
+
+  #[test]
+  fn test_clock() {
+    let mut rc = component("clock").unwrap().circuit().unwrap();
+    let mut last = rc.get_output("out").unwrap();
+    for _ in 0..6 {
+      rc.iterate();
+      assert_eq!(rc.get_output("out").unwrap(), !last);
+      last = !last;
+    }
+  }
+
+  #[test]
+  fn test_sr_latch() {
+    let mut rc = component("sr_latch").unwrap().circuit().unwrap();
+    // (s, r, q after the step)
+    for (s, r, q) in [
+      (false, false, false),
+      (true, false, true),
+      (false, false, true),
+      (false, true, true),
+      (false, false, false),
+      (false, false, false),
+      (true, true, true),
+      (false, false, false),
+    ] {
+      settle(&mut rc, &[("s", s), ("r", r)], 1);
+      assert_eq!(rc.get_output("q").unwrap(), q);
+    }
+  }
+
+  #[test]
+  fn test_d_flip_flop() {
+    let mut rc = component("d_flip_flop").unwrap().circuit().unwrap();
+    // (clk, d, q after the step). d is sampled on the step clk rises,
+    // and q follows 3 steps later, whatever d does in between.
+    for (clk, d, q) in [
+      (false, false, false),
+      (true, true, false),
+      (false, false, false),
+      (false, false, true),
+      (false, false, true),
+      (true, false, true),
+      (false, true, true),
+      (false, true, false),
+      (true, true, false),
+      (true, true, false),
+      (true, false, true),
+      (true, false, true),
+      (false, true, true),
+    ] {
+      settle(&mut rc, &[("clk", clk), ("d", d)], 1);
+      assert_eq!(rc.get_output("q").unwrap(), q);
+    }
+  }
+}
+
+// eof
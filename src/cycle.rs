@@ -0,0 +1,183 @@
+//! cycle.rs: Detect when a circuit settles or starts repeating itself.
+//!
+//! Between iterations, `wire_state` and `bus_state` are the entire state of
+//! a ResoCircuit. (The input, logic and output state is reset every
+//! iteration.) So once they repeat, the circuit is in a cycle and will repeat
+//! forever.
+//!
+//! - `pre_period`: The first step of the cycle. (Steps count from 0, the
+//!   initial state, so this is how many steps it takes to enter the cycle.)
+//! - `period`: How many steps until the state repeats. A period of 1 means
+//!   the circuit is stable, i.e. its state stopped changing.
+//!
+//! E.g. a clock that starts off and goes off/on/off/on... has pre_period 0
+//! and period 2.
+//!
+//! The CycleDetector hashes each (bit-packed) wire_state it sees, so it
+//! uses memory for every step until a cycle is found. Use a max-step limit.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+//! let cycle = rc.find_cycle(100).unwrap();
+//! assert!(cycle.is_stable());
+//! ```
+
+use std::collections::{HashMap};
+
+use crate::resocircuit::{ResoCircuit};
+
+/// A cycle found in the sequence of wire states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+  pub pre_period: usize,
+  pub period: usize,
+}
+
+impl Cycle {
+  /// True if the state stopped changing
+  pub fn is_stable(&self) -> bool {
+    self.period == 1
+  }
+}
+
+/// Remembers every wire_state it has seen, and at which step
+#[derive(Debug, Clone)]
+pub struct CycleDetector {
+  seen: HashMap<Vec<u64>, usize>,
+  step: usize,
+}
+
+impl CycleDetector {
+  /// Start detecting from the initial state, step 0
+  pub fn new(wire_state: &[bool]) -> CycleDetector {
+    let mut seen = HashMap::new();
+    seen.insert(pack_state(wire_state), 0);
+    CycleDetector { seen, step: 0 }
+  }
+
+  /// Record the state after one more iteration.
+  /// Returns the cycle if this state has been seen before.
+  pub fn observe(&mut self, wire_state: &[bool]) -> Option<Cycle> {
+    self.step += 1;
+    match self.seen.get(&pack_state(wire_state)) {
+      Some(&first_step) => Some(Cycle {
+        pre_period: first_step,
+        period: self.step - first_step,
+      }),
+      None => {
+        self.seen.insert(pack_state(wire_state), self.step);
+        None
+      }
+    }
+  }
+
+  /// How many iterations have been observed
+  pub fn step(&self) -> usize {
+    self.step
+  }
+}
+
+/// Pack a Vec<bool> into 64 bits per u64, for cheap hashing and storage
+pub fn pack_state(wire_state: &[bool]) -> Vec<u64> {
+  let mut packed = vec![0u64; wire_state.len().div_ceil(64)];
+  for (wi, &on) in wire_state.iter().enumerate() {
+    if on {
+      packed[wi / 64] |= 1 << (wi % 64);
+    }
+  }
+  packed
+}
+
+impl ResoCircuit {
+  /// The wire state followed by the bits of every bus, for the detector
+  fn state_bits(&self) -> Vec<bool> {
+    let mut bits = self.wire_state.clone();
+    for value in &self.bus_state {
+      bits.extend((0..8).map(|bit| value & (1 << bit) != 0));
+    }
+    bits
+  }
+
+  /// Iterate until the wire and bus state stops changing, up to `max_steps` times.
+  /// Returns how many iterations it took for the state to settle, i.e. the
+  /// state after that many iterations is the same as the state after one
+  /// more. (That one more iteration has also been run.)
+  /// Returns None if the state was still changing after `max_steps`.
+  pub fn run_until_stable(&mut self, max_steps: usize) -> Option<usize> {
+    let mut previous = (self.wire_state.clone(), self.bus_state.clone());
+    for step in 0..max_steps {
+      self.iterate();
+      if self.wire_state == previous.0 && self.bus_state == previous.1 {
+        return Some(step)
+      }
+      previous.0.clone_from(&self.wire_state);
+      previous.1.clone_from(&self.bus_state);
+    }
+    None
+  }
+
+  /// Iterate until a wire and bus state repeats, up to `max_steps` times.
+  /// Returns None if no cycle was found within `max_steps`.
+  pub fn find_cycle(&mut self, max_steps: usize) -> Option<Cycle> {
+    let mut detector = CycleDetector::new(&self.state_bits());
+    for _ in 0..max_steps {
+      self.iterate();
+      if let Some(cycle) = detector.observe(&self.state_bits()) {
+        return Some(cycle)
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod cycle_tests {
+  use super::*;
+
+  #[test]
+  fn test_pack_state() {
+    assert_eq!(pack_state(&[]), Vec::<u64>::new());
+    assert_eq!(pack_state(&[true, false, true]), vec![0b101]);
+
+    let mut wire_state = vec![false; 65];
+    wire_state[64] = true;
+    assert_eq!(pack_state(&wire_state), vec![0, 1]);
+  }
+
+  #[test]
+  fn test_detector() {
+    // States: A B C B C ...
+    let (a, b, c) = ([false, false], [true, false], [true, true]);
+    let mut detector = CycleDetector::new(&a);
+    assert_eq!(detector.observe(&b), None);
+    assert_eq!(detector.observe(&c), None);
+    assert_eq!(detector.observe(&b), Some(Cycle { pre_period: 1, period: 2 }));
+    assert_eq!(detector.step(), 3);
+  }
+
+  #[test]
+  fn test_halfadder_is_stable() {
+    // With nothing driving the inputs, the half adder goes quiet
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+    let cycle = rc.clone().find_cycle(100).unwrap();
+    assert!(cycle.is_stable());
+    assert_eq!(rc.run_until_stable(100), Some(cycle.pre_period));
+  }
+
+  #[test]
+  fn test_clock_cycle() {
+    // Two wires that copy each other through inputs and outputs.
+    // Starting on/off, they swap every step, so the period is 2.
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_clock.txt").unwrap();
+    let cycle = rc.clone().find_cycle(100).unwrap();
+    assert_eq!(cycle, Cycle { pre_period: 0, period: 2 });
+    assert!(!cycle.is_stable());
+    assert_eq!(rc.run_until_stable(100), None);
+  }
+}
+
+// eof
@@ -0,0 +1,168 @@
+//! error.rs: Errors returned by the Reso library.
+//!
+//! Loading and compiling a circuit can fail in a handful of ways: the file
+//! might not exist, the image might not decode, or a hand-built board might
+//! not be a proper grid. These are collected into `ResoError` so callers
+//! only have one error type to match on.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ReselBoard, ResoError};
+//!
+//! match ReselBoard::from_filename("this_does_not_exist.png") {
+//!   Ok(_)                   => panic!("How did this load?"),
+//!   Err(ResoError::Image(_)) => println!("Couldn't load the image"),
+//!   Err(err)                => println!("Something else went wrong: {}", err),
+//! }
+//! ```
+
+use std::fmt;
+
+/// Everything that can go wrong when loading or compiling a Reso circuit
+#[derive(Debug)]
+pub enum ResoError {
+  /// The image could not be opened or decoded
+  Image(image::ImageError),
+  /// Reading or writing a file failed
+  Io(std::io::Error),
+  /// Encoding an APNG failed
+  Png(png::EncodingError),
+  /// A board needs at least one resel
+  EmptyBoard,
+  /// A board must be a grid; column `x` had `len` resels instead of `height`
+  RaggedBoard { x: usize, len: usize, height: usize },
+  /// A line of a text circuit had `len` characters instead of `width`
+  RaggedLine { line: usize, len: usize, width: usize },
+  /// A text circuit had a character that isn't in the palette
+  UnknownChar { line: usize, column: usize, c: char },
+  /// A coordinate was outside the board
+  OutOfBounds { x: usize, y: usize },
+  /// A port was placed on a resel that isn't a wire
+  NotAWire { x: usize, y: usize },
+  /// Expected a bus region at these coordinates
+  NotABus { x: usize, y: usize },
+  /// Expected a subcircuit region at these coordinates
+  NotASubcircuit { x: usize, y: usize },
+  /// A ports file couldn't be parsed
+  PortFile { line: usize, message: String },
+  /// A subcircuits manifest couldn't be parsed
+  SubcircuitFile { line: usize, message: String },
+  /// A subcircuit couldn't be inlined, e.g. it's missing from the manifest
+  Subcircuit(String),
+  /// A GPIO stimulus couldn't be parsed
+  Stimulus { line: usize, message: String },
+  /// No port has this name
+  UnknownPort(String),
+  /// Tried to set an output port, or something like it
+  WrongPortDirection(String),
+  /// A ParallelCircuit lane past the last one. See parallel.rs
+  LaneOutOfRange(usize),
+  /// No component in the library has this name
+  UnknownComponent(String),
+  /// A boolean expression couldn't be parsed. See synth.rs
+  Expression { column: usize, message: String },
+  /// A synthesized circuit can't be checked, or doesn't match its expression
+  Synth(String),
+  /// A palette file couldn't be parsed
+  PaletteFile { line: usize, message: String },
+  /// A palette has too few colours, or two classes with the same colour
+  Palette(String),
+  /// An image can't be downscaled by the scale asked for
+  Scale(String),
+  /// A wire region has both on and off resels, under MixedWirePolicy::Error.
+  /// (x, y) is the region's first resel.
+  MixedWire { x: usize, y: usize, on: usize, off: usize },
+  /// A circuit can't be exported, e.g. a port name isn't a Verilog identifier
+  Export(String),
+  /// A compiled circuit or state file is corrupt or not what we expected
+  Format(String),
+  /// A compiled circuit or state file has a format version we can't read
+  UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ResoError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ResoError::Image(err) => write!(f, "image error: {}", err),
+      ResoError::Io(err)    => write!(f, "io error: {}", err),
+      ResoError::Png(err)   => write!(f, "png error: {}", err),
+      ResoError::EmptyBoard => write!(f, "board has no resels"),
+      ResoError::RaggedBoard { x, len, height } => write!(
+        f, "board is not a grid: column {} has {} resels, expected {}",
+        x, len, height
+      ),
+      ResoError::RaggedLine { line, len, width } => write!(
+        f, "text is not a grid: line {} has {} characters, expected {}",
+        line, len, width
+      ),
+      ResoError::UnknownChar { line, column, c } => write!(
+        f, "unknown character {:?} at line {}, column {}", c, line, column
+      ),
+      ResoError::OutOfBounds { x, y } => write!(f, "({},{}) is outside the board", x, y),
+      ResoError::NotAWire { x, y } => write!(f, "({},{}) is not a wire", x, y),
+      ResoError::NotABus { x, y } => write!(f, "({},{}) is not a bus", x, y),
+      ResoError::NotASubcircuit { x, y } => write!(f, "({},{}) is not a subcircuit", x, y),
+      ResoError::PortFile { line, message } => write!(
+        f, "ports file line {}: {}", line, message
+      ),
+      ResoError::SubcircuitFile { line, message } => write!(
+        f, "subcircuits file line {}: {}", line, message
+      ),
+      ResoError::Subcircuit(message) => write!(f, "bad subcircuit: {}", message),
+      ResoError::Stimulus { line, message } => write!(
+        f, "stimulus line {}: {}", line, message
+      ),
+      ResoError::UnknownPort(name) => write!(f, "no port named {:?}", name),
+      ResoError::WrongPortDirection(name) => write!(
+        f, "port {:?} has the wrong direction", name
+      ),
+      ResoError::LaneOutOfRange(lane) => write!(
+        f, "lane {} is out of range, there are only {}", lane, crate::parallel::LANES
+      ),
+      ResoError::UnknownComponent(name) => write!(f, "no component named {:?}", name),
+      ResoError::Expression { column, message } => write!(
+        f, "expression column {}: {}", column, message
+      ),
+      ResoError::Synth(message) => write!(f, "synthesis failed: {}", message),
+      ResoError::PaletteFile { line, message } => write!(
+        f, "palette file line {}: {}", line, message
+      ),
+      ResoError::Palette(message) => write!(f, "bad palette: {}", message),
+      ResoError::Scale(message) => write!(f, "bad scale: {}", message),
+      ResoError::MixedWire { x, y, on, off } => write!(
+        f, "wire region at ({},{}) has {} on and {} off resels", x, y, on, off
+      ),
+      ResoError::Export(message) => write!(f, "can't export: {}", message),
+      ResoError::Format(message) => write!(f, "bad file format: {}", message),
+      ResoError::UnsupportedVersion(version) => write!(
+        f, "unsupported file format version {}", version
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ResoError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ResoError::Image(err) => Some(err),
+      ResoError::Io(err)    => Some(err),
+      ResoError::Png(err)   => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<image::ImageError> for ResoError {
+  fn from(err: image::ImageError) -> Self { ResoError::Image(err) }
+}
+
+impl From<png::EncodingError> for ResoError {
+  fn from(err: png::EncodingError) -> Self { ResoError::Png(err) }
+}
+
+impl From<std::io::Error> for ResoError {
+  fn from(err: std::io::Error) -> Self { ResoError::Io(err) }
+}
+
+// eof
@@ -0,0 +1,381 @@
+//! event.rs: Event-driven simulation, only re-evaluating what changed.
+//!
+//! `ResoCircuit::iterate()` walks every input, logic, output and wire on
+//! every step. In a big circuit where only a few wires toggle each step,
+//! almost all of that work recomputes the same values.
+//!
+//! An EventEngine keeps the input, logic and output state between steps,
+//! and remembers which wires changed. Each step it only recomputes:
+//!
+//! | what    | when one of these changed  |
+//! | ------- | -------------------------- |
+//! | input   | its incident wires         |
+//! | logic   | its incident inputs        |
+//! | output  | its incident inputs/logics |
+//! | wire    | its incident outputs       |
+//!
+//! Following the IncidenceMap backwards needs the reverse incidences
+//! (e.g. wire -> inputs), which the engine builds once.
+//!
+//! Buses aren't event-driven: every step re-evaluates them all with
+//! `iterate_buses()`, as they never affect wires.
+//!
+//! The results are identical to `iterate()`. The engine doesn't own the
+//! circuit: it steps a ResoCircuit passed to `iterate`, so the circuit can
+//! be rendered, saved and so on as usual. Input ports set or released with
+//! `set_input`/`release_input` are noticed automatically. If anything else
+//! changes `rc.wire_state` between steps, call `invalidate()` so the next
+//! step re-evaluates everything.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::event::{EventEngine};
+//!
+//! let mut rc = ResoCircuit::from_filename("./reso_logo.png").unwrap();
+//! let mut expected = rc.clone();
+//! let mut engine = EventEngine::new(&rc);
+//! for _ in 0..8 {
+//!   engine.iterate(&mut rc);
+//!   expected.iterate();
+//! }
+//! assert_eq!(rc.wire_state, expected.wire_state);
+//! ```
+
+use crate::resel::{Resel};
+use crate::incidencemap::{IncidenceMap};
+use crate::resocircuit::{ResoCircuit};
+
+/// Invert an incidence list, e.g. input_inc_wires -> wire_inc_inputs.
+/// `num_targets` is how many of the things being pointed to there are.
+fn reverse_incidence(incidence: &[Vec<usize>], num_targets: usize) -> Vec<Vec<usize>> {
+  let mut reversed = vec![vec![]; num_targets];
+  for (source, targets) in incidence.iter().enumerate() {
+    for target in targets {
+      reversed[*target].push(source);
+    }
+  }
+  reversed
+}
+
+/// A list of dirty indices, without duplicates
+#[derive(Debug, Clone)]
+struct DirtySet {
+  indices: Vec<usize>,
+  is_dirty: Vec<bool>,
+}
+
+impl DirtySet {
+  fn new(size: usize) -> DirtySet {
+    DirtySet { indices: vec![], is_dirty: vec![false; size] }
+  }
+
+  fn insert(&mut self, index: usize) {
+    if !self.is_dirty[index] {
+      self.is_dirty[index] = true;
+      self.indices.push(index);
+    }
+  }
+
+  /// Mark every index dirty
+  fn fill(&mut self) {
+    self.indices = (0..self.is_dirty.len()).collect();
+    self.is_dirty.fill(true);
+  }
+
+  /// Take the dirty indices, leaving the set empty
+  fn take(&mut self) -> Vec<usize> {
+    for index in &self.indices {
+      self.is_dirty[*index] = false;
+    }
+    std::mem::take(&mut self.indices)
+  }
+}
+
+/// Steps a ResoCircuit, only re-evaluating regions whose inputs changed.
+/// See the module docs.
+#[derive(Debug, Clone)]
+pub struct EventEngine {
+  // Reverse incidences, to follow a change forward
+  wire_inc_inputs:   Vec<Vec<usize>>,
+  input_inc_logics:  Vec<Vec<usize>>,
+  input_inc_outputs: Vec<Vec<usize>>,
+  logic_inc_outputs: Vec<Vec<usize>>,
+  output_inc_wires:  Vec<Vec<usize>>,
+
+  /// `logic_is_and[li]` is true for AND, false for XOR
+  logic_is_and: Vec<bool>,
+
+  // State kept between steps. For inputs, the OR/AND/XOR over their wires
+  input_or:     Vec<bool>,
+  input_and:    Vec<bool>,
+  input_xor:    Vec<bool>,
+  logic_state:  Vec<bool>,
+  output_state: Vec<bool>,
+
+  /// Wires that changed since the last step
+  dirty_wires: DirtySet,
+  dirty_inputs: DirtySet,
+  dirty_logics: DirtySet,
+  dirty_outputs: DirtySet,
+  /// Wires changed from outside, which need re-deriving from their outputs
+  external_wires: Vec<usize>,
+
+  /// `held` of every port after the last step, to notice set/release
+  held_ports: Vec<Option<bool>>,
+  /// The value each wire is held at by an input port, if any
+  held_wires: Vec<Option<bool>>,
+
+  /// If true, the next step re-evaluates everything
+  invalid: bool,
+}
+
+impl EventEngine {
+  /// Prepare to step `rc`. The first step re-evaluates everything.
+  pub fn new(rc: &ResoCircuit) -> EventEngine {
+    let im: &IncidenceMap = &rc.im;
+    let (num_wires, num_inputs) = (im.wire_inc_outputs.len(), im.input_inc_wires.len());
+    let (num_logics, num_outputs) = (im.logic_inc_inputs.len(), im.output_inc_inputs.len());
+
+    EventEngine {
+      wire_inc_inputs:   reverse_incidence(&im.input_inc_wires, num_wires),
+      input_inc_logics:  reverse_incidence(&im.logic_inc_inputs, num_inputs),
+      input_inc_outputs: reverse_incidence(&im.output_inc_inputs, num_inputs),
+      logic_inc_outputs: reverse_incidence(&im.output_inc_logics, num_logics),
+      output_inc_wires:  reverse_incidence(&im.wire_inc_outputs, num_outputs),
+
+      logic_is_and: rc.rm.logic_regions.iter().map(
+        |ri| rc.rm.region_to_resel[*ri] == Resel::AND
+      ).collect(),
+
+      input_or:     vec![false; num_inputs],
+      input_and:    vec![false; num_inputs],
+      input_xor:    vec![false; num_inputs],
+      logic_state:  vec![false; num_logics],
+      output_state: vec![false; num_outputs],
+
+      dirty_wires:   DirtySet::new(num_wires),
+      dirty_inputs:  DirtySet::new(num_inputs),
+      dirty_logics:  DirtySet::new(num_logics),
+      dirty_outputs: DirtySet::new(num_outputs),
+
+      external_wires: vec![],
+
+      held_ports: vec![],
+      held_wires: vec![None; num_wires],
+      invalid: true,
+    }
+  }
+
+  /// Forget what's known, so the next step re-evaluates everything.
+  /// Call this after changing `rc.wire_state` directly.
+  pub fn invalidate(&mut self) {
+    self.invalid = true;
+  }
+
+  /// Mark one wire as changed outside the engine, e.g. after editing
+  /// `rc.wire_state[wi]`. Cheaper than `invalidate()`.
+  pub fn mark_wire(&mut self, wi: usize) {
+    self.external_wires.push(wi);
+  }
+
+  /// Notice input ports that were set or released since the last step
+  fn update_held_wires(&mut self, rc: &ResoCircuit) {
+    let held_ports: Vec<Option<bool>> = rc.ports.iter().map(|port| port.held).collect();
+    if held_ports == self.held_ports {
+      return
+    }
+    for port in &rc.ports {
+      self.held_wires[port.wire] = None;
+      self.external_wires.push(port.wire);
+    }
+    for port in &rc.ports {
+      if port.held.is_some() {
+        self.held_wires[port.wire] = port.held;
+      }
+    }
+    self.held_ports = held_ports;
+  }
+
+  /// Simulate one iteration of `rc`, exactly like `rc.iterate()`
+  pub fn iterate(&mut self, rc: &mut ResoCircuit) {
+    debug_assert_eq!(rc.wire_state.len(), self.wire_inc_inputs.len());
+
+    self.update_held_wires(rc);
+
+    // Wires to re-derive from their outputs even if no output changed,
+    // i.e. wires changed from outside
+    let mut rederive = std::mem::take(&mut self.external_wires);
+    if self.invalid {
+      self.invalid = false;
+      self.dirty_wires.take();
+      rederive = (0..rc.wire_state.len()).collect();
+      self.dirty_inputs.fill();
+      self.dirty_logics.fill();
+      self.dirty_outputs.fill();
+    } else {
+      for wi in rederive.iter().chain(&self.dirty_wires.take()) {
+        for ii in &self.wire_inc_inputs[*wi] {
+          self.dirty_inputs.insert(*ii);
+        }
+      }
+    }
+
+    // Inputs: OR/AND/XOR over incident wires
+    for ii in self.dirty_inputs.take() {
+      let inc_wires = &rc.im.input_inc_wires[ii];
+      let or  = inc_wires.iter().any(|wi| rc.wire_state[*wi]);
+      let and = inc_wires.iter().all(|wi| rc.wire_state[*wi]);
+      let xor = inc_wires.iter().fold(false, |acc, wi| acc ^ rc.wire_state[*wi]);
+
+      if or != self.input_or[ii] {
+        self.input_or[ii] = or;
+        for oi in &self.input_inc_outputs[ii] {
+          self.dirty_outputs.insert(*oi);
+        }
+      }
+      if and != self.input_and[ii] || xor != self.input_xor[ii] {
+        self.input_and[ii] = and;
+        self.input_xor[ii] = xor;
+        for li in &self.input_inc_logics[ii] {
+          self.dirty_logics.insert(*li);
+        }
+      }
+    }
+
+    // Logics: OR over incident inputs, of AND/XOR over their wires
+    for li in self.dirty_logics.take() {
+      let folded = if self.logic_is_and[li] { &self.input_and } else { &self.input_xor };
+      let on = rc.im.logic_inc_inputs[li].iter().any(|ii| folded[*ii]);
+      if on != self.logic_state[li] {
+        self.logic_state[li] = on;
+        for oi in &self.logic_inc_outputs[li] {
+          self.dirty_outputs.insert(*oi);
+        }
+      }
+    }
+
+    // Outputs: OR over incident inputs and logics
+    let mut changed_outputs = vec![];
+    for oi in self.dirty_outputs.take() {
+      let on = rc.im.output_inc_inputs[oi].iter().any(|ii| self.input_or[*ii])
+        || rc.im.output_inc_logics[oi].iter().any(|li| self.logic_state[*li]);
+      if on != self.output_state[oi] {
+        self.output_state[oi] = on;
+        changed_outputs.push(oi);
+      }
+    }
+
+    // Wires: OR over incident outputs, unless held by an input port
+    for oi in changed_outputs {
+      rederive.extend(&self.output_inc_wires[oi]);
+    }
+    for wi in rederive {
+      let on = self.held_wires[wi].unwrap_or_else(
+        || rc.im.wire_inc_outputs[wi].iter().any(|oi| self.output_state[*oi])
+      );
+      if on != rc.wire_state[wi] {
+        rc.wire_state[wi] = on;
+        self.dirty_wires.insert(wi);
+      }
+    }
+
+    rc.iterate_buses();
+  }
+}
+
+#[cfg(test)]
+mod event_tests {
+  use super::*;
+
+  /// Step `rc` with both engines, checking they agree every step
+  fn assert_same_as_iterate(rc: &ResoCircuit, steps: usize) {
+    let (mut rc_full, mut rc_event) = (rc.clone(), rc.clone());
+    let mut engine = EventEngine::new(&rc_event);
+    for step in 0..steps {
+      rc_full.iterate();
+      engine.iterate(&mut rc_event);
+      assert_eq!(rc_event.wire_state, rc_full.wire_state, "differs at step {}", step);
+    }
+  }
+
+  #[test]
+  fn test_reverse_incidence() {
+    assert_eq!(
+      reverse_incidence(&[vec![0, 1], vec![], vec![1]], 3),
+      vec![vec![0], vec![0, 2], vec![]]
+    );
+  }
+
+  #[test]
+  fn test_reso_logo() {
+    let rc = ResoCircuit::from_filename("./reso_logo.png").unwrap();
+    assert_same_as_iterate(&rc, 64);
+  }
+
+  #[test]
+  fn test_halfadders() {
+    for filename in [
+      "./src/testing/test_half_adder_01.png",
+      "./src/testing/test_half_adder.png",
+      "./src/testing/test_clock.txt",
+    ] {
+      let rc = ResoCircuit::from_filename(filename).unwrap();
+      assert_same_as_iterate(&rc, 8);
+    }
+  }
+
+  #[test]
+  fn test_ports_halfadder() {
+    // Set and release inputs between steps; both engines must agree
+    let mut rc_full = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc_full.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    let mut rc_event = rc_full.clone();
+    let mut engine = EventEngine::new(&rc_event);
+
+    for (a, b) in [(false, false), (true, false), (true, true), (false, true)] {
+      for rc in [&mut rc_full, &mut rc_event] {
+        rc.set_input("a", a).unwrap();
+        rc.set_input("b", b).unwrap();
+      }
+      for _ in 0..3 {
+        rc_full.iterate();
+        engine.iterate(&mut rc_event);
+        assert_eq!(rc_event.wire_state, rc_full.wire_state);
+      }
+      assert_eq!(rc_event.get_output("sum").unwrap(), a ^ b);
+      assert_eq!(rc_event.get_output("carry").unwrap(), a && b);
+    }
+
+    rc_full.release_input("a").unwrap();
+    rc_event.release_input("a").unwrap();
+    for _ in 0..3 {
+      rc_full.iterate();
+      engine.iterate(&mut rc_event);
+      assert_eq!(rc_event.wire_state, rc_full.wire_state);
+    }
+  }
+
+  #[test]
+  fn test_mark_wire() {
+    let mut rc_full = ResoCircuit::from_filename("./src/testing/test_clock.txt").unwrap();
+    let mut rc_event = rc_full.clone();
+    let mut engine = EventEngine::new(&rc_event);
+    engine.iterate(&mut rc_event);
+    rc_full.iterate();
+
+    // Flip a wire behind the engine's back
+    for rc in [&mut rc_full, &mut rc_event] {
+      rc.wire_state[0] = !rc.wire_state[0];
+    }
+    engine.mark_wire(0);
+    for _ in 0..4 {
+      rc_full.iterate();
+      engine.iterate(&mut rc_event);
+      assert_eq!(rc_event.wire_state, rc_full.wire_state);
+    }
+  }
+}
+
+// eof
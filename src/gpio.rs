@@ -0,0 +1,210 @@
+//! gpio.rs: GPIO wires, driven from a stimulus file and recorded.
+//!
+//! A GPIO wire (green, `g`/`G` in text; see resel.rs) is a wire that can be
+//! driven from outside, so test vectors don't mean hand-editing pixels.
+//! Every GPIO wire region gets an input port named `gpio0`, `gpio1`, ...,
+//! numbered in region order (scanning x, then y).
+//!
+//! A stimulus has one line per step, with a value for each GPIO in order:
+//!
+//! ```text
+//! # gpio0 gpio1 gpio2
+//! 1 0 x
+//! 1,1,x
+//! 01
+//! ```
+//!
+//! - `1` and `0` hold the GPIO on or off, like `set_input`.
+//! - `x` or `-` leave it undriven, so the circuit drives it like any wire.
+//!
+//! Values can be separated by commas or whitespace, or not at all. Missing
+//! values at the end of a line are `x`. Blank lines and anything after a `#`
+//! are ignored. Line `n` is applied before step `n`; once the stimulus runs
+//! out, the GPIOs stay as they were last driven.
+//!
+//! A recording has one line per step, with every GPIO's state after that
+//! step, e.g. `1,0,1`.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ReselBoard, ResoCircuit};
+//! use reso::gpio::{Stimulus, format_gpio_states};
+//! use reso::reselboard::{Topology};
+//!
+//! // gpio0 -> input -> output -> gpio1
+//! let rb = ReselBoard::from_text("g+=g", &Default::default()).unwrap();
+//! let mut rc = ResoCircuit::from(rb.with_topology(Topology::Bounded));
+//! let mut stimulus = Stimulus::new("1 x\n0 x\n".as_bytes(), rc.gpio_wires().len());
+//! while let Some(values) = stimulus.next_step().unwrap() {
+//!   rc.drive_gpios(&values).unwrap();
+//!   rc.iterate();
+//!   println!("{}", format_gpio_states(&rc.gpio_states()));
+//! }
+//! ```
+
+use std::fs::{File};
+use std::io::{BufRead, BufReader};
+
+use crate::error::{ResoError};
+use crate::ports::{Port, PortDirection};
+use crate::resocircuit::{ResoCircuit};
+
+/// The name of the port on GPIO `k`
+pub fn gpio_name(k: usize) -> String {
+  format!("gpio{}", k)
+}
+
+/// Parse one line of a stimulus: the value for each GPIO, None meaning not
+/// driven. Returns None for blank and comment lines.
+pub fn parse_stimulus_line(line: &str) -> Result<Option<Vec<Option<bool>>>, String> {
+  let line = line.split('#').next().unwrap_or("");
+  let mut values = vec![];
+  for c in line.chars() {
+    match c {
+      '1' => values.push(Some(true)),
+      '0' => values.push(Some(false)),
+      'x' | 'X' | '-' => values.push(None),
+      ',' => {},
+      c if c.is_whitespace() => {},
+      c => return Err(format!("expected 0, 1, or x, not {:?}", c)),
+    }
+  }
+  Ok(if line.trim().is_empty() { None } else { Some(values) })
+}
+
+/// Reads a stimulus one step at a time, so it can come from stdin
+pub struct Stimulus<R: BufRead> {
+  reader: R,
+  num_gpios: usize,
+  line_no: usize,
+}
+
+impl Stimulus<Box<dyn BufRead>> {
+  /// Open a stimulus file, or stdin for `-`
+  pub fn open(filename: &str, num_gpios: usize) -> Result<Self, ResoError> {
+    let reader: Box<dyn BufRead> = if filename == "-" {
+      Box::new(std::io::stdin().lock())
+    } else {
+      Box::new(BufReader::new(File::open(filename)?))
+    };
+    Ok(Stimulus::new(reader, num_gpios))
+  }
+}
+
+impl<R: BufRead> Stimulus<R> {
+  /// Read a stimulus for a circuit with `num_gpios` GPIOs
+  pub fn new(reader: R, num_gpios: usize) -> Self {
+    Stimulus { reader, num_gpios, line_no: 0 }
+  }
+
+  /// The values for the next step, padded to one per GPIO, or None once
+  /// the stimulus has run out
+  pub fn next_step(&mut self) -> Result<Option<Vec<Option<bool>>>, ResoError> {
+    let mut line = String::new();
+    loop {
+      line.clear();
+      if self.reader.read_line(&mut line)? == 0 {
+        return Ok(None)
+      }
+      self.line_no += 1;
+      let err = |message: String| ResoError::Stimulus { line: self.line_no, message };
+
+      let Some(mut values) = parse_stimulus_line(&line).map_err(err)? else { continue };
+      if values.len() > self.num_gpios {
+        return Err(err(format!(
+          "{} values, but the circuit has {} GPIOs", values.len(), self.num_gpios
+        )))
+      }
+      values.resize(self.num_gpios, None);
+      return Ok(Some(values))
+    }
+  }
+}
+
+/// Format GPIO states as a recording line, e.g. `1,0,1`
+pub fn format_gpio_states(states: &[bool]) -> String {
+  states.iter().map(|on| if *on { "1" } else { "0" }).collect::<Vec<_>>().join(",")
+}
+
+impl ResoCircuit {
+  /// The dense wire index of every GPIO wire, in region order
+  pub fn gpio_wires(&self) -> Vec<usize> {
+    self.rm.wire_regions.iter().enumerate()
+      .filter(|(_, ri)| self.rm.region_to_resel[**ri].is_gpio())
+      .map(|(wi, _)| wi)
+      .collect()
+  }
+
+  /// Add the `gpio<k>` input port on every GPIO wire
+  pub(crate) fn add_gpio_ports(&mut self) {
+    for (k, wire) in self.gpio_wires().into_iter().enumerate() {
+      self.ports.push(Port {
+        direction: PortDirection::Input,
+        name: gpio_name(k),
+        wire,
+        held: None,
+      });
+    }
+  }
+
+  /// Drive GPIO `k` with `values[k]`: hold it on or off, or release it for
+  /// None. GPIOs past the end of `values` are left alone.
+  pub fn drive_gpios(&mut self, values: &[Option<bool>]) -> Result<(), ResoError> {
+    for (k, value) in values.iter().enumerate() {
+      match value {
+        Some(on) => self.set_input(&gpio_name(k), *on)?,
+        None => self.release_input(&gpio_name(k))?,
+      }
+    }
+    Ok(())
+  }
+
+  /// The state of every GPIO wire, in order
+  pub fn gpio_states(&self) -> Vec<bool> {
+    self.gpio_wires().into_iter().map(|wi| self.wire_state[wi]).collect()
+  }
+}
+
+#[cfg(test)]
+mod gpio_tests {
+  use super::*;
+  use crate::reselboard::{ReselBoard, TextOptions, Topology};
+
+  #[test]
+  fn test_parse_stimulus_line() {
+    assert_eq!(parse_stimulus_line("1 0 x"), Ok(Some(vec![Some(true), Some(false), None])));
+    assert_eq!(parse_stimulus_line("1,-,0 # comment"), Ok(Some(vec![Some(true), None, Some(false)])));
+    assert_eq!(parse_stimulus_line("01"), Ok(Some(vec![Some(false), Some(true)])));
+    assert_eq!(parse_stimulus_line("   # just a comment"), Ok(None));
+    assert_eq!(parse_stimulus_line(""), Ok(None));
+    assert!(parse_stimulus_line("1 2").is_err());
+  }
+
+  #[test]
+  fn test_stimulus_and_recording() {
+    // gpio0 (on) -> input -> output -> gpio1
+    let rb = ReselBoard::from_text("G+=g", &TextOptions::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let mut rc = ResoCircuit::from(rb);
+    assert_eq!(rc.gpio_wires(), vec![0, 1]);
+    assert_eq!(rc.ports.iter().map(|port| port.name.as_str()).collect::<Vec<_>>(), ["gpio0", "gpio1"]);
+
+    let text = "# gpio0 gpio1\n0 x\n\n1\n-,-\n";
+    let mut stimulus = Stimulus::new(text.as_bytes(), 2);
+    let mut recording = vec![];
+    while let Some(values) = stimulus.next_step().unwrap() {
+      rc.drive_gpios(&values).unwrap();
+      rc.iterate();
+      recording.push(format_gpio_states(&rc.gpio_states()));
+    }
+    // Released, gpio0 has nothing driving it, but gpio1 still reads it
+    assert_eq!(recording, vec!["0,0", "1,1", "0,1"]);
+
+    let mut stimulus = Stimulus::new("1\n1 1 1\n".as_bytes(), 2);
+    assert!(stimulus.next_step().is_ok());
+    assert!(matches!(stimulus.next_step(), Err(ResoError::Stimulus { line: 2, .. })));
+  }
+}
+
+// eof
@@ -0,0 +1,310 @@
+//! graph.rs: Export the compiled logic graph as Graphviz DOT or JSON.
+//!
+//! The IncidenceMap is the real circuit: which wires feed which inputs, which
+//! inputs feed which logic and outputs, and so on. These exports make it
+//! visible, and usable from other tools.
+//!
+//! Both list every non-empty region as a node, and every incidence as an
+//! edge in the direction signals flow:
+//!
+//! ```text
+//! wire -> input -> logic -> output -> wire
+//!               \_________/
+//!
+//! bus -> bus_input -> logic -> bus_output -> bus
+//!                   \______________/
+//! ```
+//!
+//! Nodes and edges use region indices (as in the RegionMap), not the dense
+//! indices the IncidenceMap uses internally. Wires are coloured by their
+//! current state, in the board's palette, and buses by their current value.
+//!
+//! - DOT: Nodes are filled with their resel's colour and labelled with their
+//!   class, region index, port names, and bounding box. Render with e.g.
+//!   `dot -Tsvg circuit.dot -o circuit.svg`.
+//! - JSON: A netlist with the board size and topology, every region (class,
+//!   colour, bounding box, size, for wires whether they're on, and for buses
+//!   their value), every edge as a `[from, to]` pair, and the ports.
+//!
+//! Regions inlined from subcircuits aren't on the board, so they have no
+//! bounding box: DOT leaves it out, and JSON gives `"bbox": null`.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! let dot = rc.to_dot();
+//! assert!(dot.starts_with("digraph reso {"));
+//! let json = rc.to_json();
+//! assert!(json.contains("\"edges\""));
+//! ```
+
+use image::{Rgba};
+
+use crate::resel::{Resel};
+use crate::ports::{PortDirection};
+use crate::resocircuit::{ResoCircuit};
+
+/// The class of a resel, as named in the exports
+pub fn class_name(resel: Resel) -> &'static str {
+  match resel {
+    Resel::AND    => "and",
+    Resel::XOR    => "xor",
+    Resel::Input  => "input",
+    Resel::Output => "output",
+    Resel::Empty  => "empty",
+    Resel::Bus(_)    => "bus",
+    Resel::BusInput  => "bus_input",
+    Resel::BusOutput => "bus_output",
+    Resel::Subcircuit => "subcircuit",
+    _             => "wire",
+  }
+}
+
+fn hex_color(rgba: Rgba<u8>) -> String {
+  format!("#{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2])
+}
+
+/// Escape a string for a DOT or JSON string literal
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '"'  => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+impl ResoCircuit {
+  /// Every non-empty region's resel, with wires and buses in their current
+  /// state
+  fn node_resels(&self) -> Vec<(usize, Resel)> {
+    (1..self.rm.region_to_resel.len()).map(|ri| {
+      let resel = self.rm.region_to_resel[ri];
+      if resel.is_wire() {
+        (ri, self.wire_resel(self.rm.reverse_dense[ri]))
+      } else if resel.is_bus() {
+        (ri, self.bus_resel(self.rm.reverse_dense[ri]))
+      } else {
+        (ri, resel)
+      }
+    }).collect()
+  }
+
+  /// Every incidence as a (from, to) pair of region indices, in the
+  /// direction signals flow, sorted
+  pub fn graph_edges(&self) -> Vec<(usize, usize)> {
+    let (rm, im) = (&self.rm, &self.im);
+    let mut edges = vec![];
+    for (from_regions, to_regions, incidences) in [
+      (&rm.wire_regions,   &rm.input_regions,  &im.input_inc_wires),
+      (&rm.input_regions,  &rm.logic_regions,  &im.logic_inc_inputs),
+      (&rm.input_regions,  &rm.output_regions, &im.output_inc_inputs),
+      (&rm.logic_regions,  &rm.output_regions, &im.output_inc_logics),
+      (&rm.output_regions, &rm.wire_regions,   &im.wire_inc_outputs),
+      (&rm.bus_regions,        &rm.bus_input_regions,  &im.bus_input_inc_buses),
+      (&rm.bus_input_regions,  &rm.logic_regions,      &im.logic_inc_bus_inputs),
+      (&rm.bus_input_regions,  &rm.bus_output_regions, &im.bus_output_inc_bus_inputs),
+      (&rm.logic_regions,      &rm.bus_output_regions, &im.bus_output_inc_logics),
+      (&rm.bus_output_regions, &rm.bus_regions,        &im.bus_inc_bus_outputs),
+    ] {
+      for (to, incident) in incidences.iter().enumerate() {
+        for from in incident {
+          edges.push((from_regions[*from], to_regions[to]));
+        }
+      }
+    }
+    edges.sort();
+    edges
+  }
+
+  /// Names of the ports on a region, if it's a wire
+  fn port_names(&self, region: usize) -> Vec<&str> {
+    self.ports.iter()
+      .filter(|port| self.rm.wire_regions[port.wire] == region)
+      .map(|port| port.name.as_str())
+      .collect()
+  }
+
+  /// The compiled logic graph in Graphviz DOT. See `reso::graph`.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph reso {\n");
+    dot.push_str("  node [style=filled, fontname=\"monospace\"];\n");
+    for (ri, resel) in self.node_resels() {
+      let rgba = self.rb.palette.to_rgba(resel).unwrap();
+      let luma = (299 * rgba[0] as u32 + 587 * rgba[1] as u32 + 114 * rgba[2] as u32) / 1000;
+      let mut label = format!("{} {}", class_name(resel), ri);
+      for name in self.port_names(ri) {
+        label.push_str(&format!(" \"{}\"", name));
+      }
+      if self.rm.first_xy(ri).is_some() {
+        let (x0, y0, x1, y1) = self.rm.bounding_box(ri);
+        label.push_str(&format!("\n({},{})-({},{})", x0, y0, x1, y1));
+      }
+
+      dot.push_str(&format!(
+        "  r{} [label=\"{}\", shape={}, fillcolor=\"{}\", fontcolor=\"{}\"];\n",
+        ri, escape(&label),
+        if resel.is_wire() || resel.is_bus() { "ellipse" } else { "box" },
+        hex_color(rgba),
+        if luma < 128 { "white" } else { "black" },
+      ));
+    }
+    for (from, to) in self.graph_edges() {
+      dot.push_str(&format!("  r{} -> r{};\n", from, to));
+    }
+    dot.push_str("}\n");
+    dot
+  }
+
+  /// The compiled logic graph as a JSON netlist. See `reso::graph`.
+  pub fn to_json(&self) -> String {
+    let regions: Vec<String> = self.node_resels().into_iter().map(|(ri, resel)| {
+      let bbox = match self.rm.first_xy(ri) {
+        Some(_) => {
+          let (x0, y0, x1, y1) = self.rm.bounding_box(ri);
+          format!("[{}, {}, {}, {}]", x0, y0, x1, y1)
+        },
+        None => "null".to_string(),
+      };
+      let on = match (resel.wire_state(), resel.bus_value()) {
+        (Some(on), _) => format!(", \"on\": {}", on),
+        (_, Some(value)) => format!(", \"value\": {}", value),
+        _ => String::new(),
+      };
+      format!(
+        "    {{\"region\": {}, \"class\": \"{}\"{}, \"color\": \"{}\", \"bbox\": {}, \"size\": {}}}",
+        ri, class_name(resel), on, hex_color(self.rb.palette.to_rgba(resel).unwrap()),
+        bbox, self.rm.region_to_xys[ri].len()
+      )
+    }).collect();
+
+    let edges: Vec<String> = self.graph_edges().into_iter()
+      .map(|(from, to)| format!("    [{}, {}]", from, to))
+      .collect();
+
+    let ports: Vec<String> = self.ports.iter().map(|port| format!(
+      "    {{\"name\": \"{}\", \"direction\": \"{}\", \"region\": {}}}",
+      escape(&port.name),
+      if port.direction == PortDirection::Input { "input" } else { "output" },
+      self.rm.wire_regions[port.wire]
+    )).collect();
+
+    let list = |items: Vec<String>| if items.is_empty() {
+      "[]".to_string()
+    } else {
+      format!("[\n{}\n  ]", items.join(",\n"))
+    };
+    format!(
+      "{{\n  \"width\": {},\n  \"height\": {},\n  \"topology\": \"{}\",\n  \"regions\": {},\n  \"edges\": {},\n  \"ports\": {}\n}}\n",
+      self.rb.width, self.rb.height, self.rb.topology,
+      list(regions), list(edges), list(ports)
+    )
+  }
+}
+
+#[cfg(test)]
+mod graph_tests {
+  use super::*;
+  use crate::ports::{PortSpec};
+  use crate::reselboard::{ReselBoard, TextOptions, Topology};
+
+  /// wire -> input -> output -> wire, with a port on the first wire
+  fn buffer() -> ResoCircuit {
+    let rb = ReselBoard::from_text("o+=O", &TextOptions::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let mut rc = ResoCircuit::from(rb);
+    rc.add_port(&PortSpec {
+      direction: PortDirection::Input, name: "in \"a\"".to_string(), x: 0, y: 0
+    }).unwrap();
+    rc
+  }
+
+  #[test]
+  fn test_to_dot() {
+    assert_eq!(buffer().to_dot(), [
+      "digraph reso {",
+      "  node [style=filled, fontname=\"monospace\"];",
+      "  r1 [label=\"wire 1 \\\"in \\\"a\\\"\\\"\\n(0,0)-(0,0)\", shape=ellipse, fillcolor=\"#804000\", fontcolor=\"white\"];",
+      "  r2 [label=\"input 2\\n(1,0)-(1,0)\", shape=box, fillcolor=\"#400080\", fontcolor=\"white\"];",
+      "  r3 [label=\"output 3\\n(2,0)-(2,0)\", shape=box, fillcolor=\"#8000ff\", fontcolor=\"white\"];",
+      "  r4 [label=\"wire 4\\n(3,0)-(3,0)\", shape=ellipse, fillcolor=\"#ff8000\", fontcolor=\"black\"];",
+      "  r1 -> r2;",
+      "  r2 -> r3;",
+      "  r3 -> r4;",
+      "}",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn test_to_json() {
+    assert_eq!(buffer().to_json(), [
+      "{",
+      "  \"width\": 4,",
+      "  \"height\": 1,",
+      "  \"topology\": \"bounded\",",
+      "  \"regions\": [",
+      "    {\"region\": 1, \"class\": \"wire\", \"on\": false, \"color\": \"#804000\", \"bbox\": [0, 0, 0, 0], \"size\": 1},",
+      "    {\"region\": 2, \"class\": \"input\", \"color\": \"#400080\", \"bbox\": [1, 0, 1, 0], \"size\": 1},",
+      "    {\"region\": 3, \"class\": \"output\", \"color\": \"#8000ff\", \"bbox\": [2, 0, 2, 0], \"size\": 1},",
+      "    {\"region\": 4, \"class\": \"wire\", \"on\": true, \"color\": \"#ff8000\", \"bbox\": [3, 0, 3, 0], \"size\": 1}",
+      "  ],",
+      "  \"edges\": [",
+      "    [1, 2],",
+      "    [2, 3],",
+      "    [3, 4]",
+      "  ],",
+      "  \"ports\": [",
+      "    {\"name\": \"in \\\"a\\\"\", \"direction\": \"input\", \"region\": 1}",
+      "  ]",
+      "}",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn test_graph_edges_half_adder() {
+    // See the example in incidencemap.rs
+    let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.txt").unwrap();
+    let (rm, im) = (&rc.rm, &rc.im);
+    let num_incidences: usize = [
+      &im.input_inc_wires, &im.logic_inc_inputs, &im.output_inc_inputs,
+      &im.output_inc_logics, &im.wire_inc_outputs,
+    ].iter().map(|incidences| incidences.iter().map(Vec::len).sum::<usize>()).sum();
+
+    let edges = rc.graph_edges();
+    assert_eq!(edges.len(), num_incidences);
+    for (from, to) in edges {
+      let (from, to) = (rm.region_to_resel[from], rm.region_to_resel[to]);
+      assert!(matches!(
+        (class_name(from), class_name(to)),
+        ("wire", "input") | ("input", "and" | "xor" | "output")
+          | ("and" | "xor", "output") | ("output", "wire")
+      ));
+    }
+  }
+
+  #[test]
+  fn test_bus_graph() {
+    // bus -> bus_input -> and -> bus_output -> bus
+    let rb = ReselBoard::from_text("⠁{&}⠂", &TextOptions::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let rc = ResoCircuit::from(rb);
+    assert_eq!(rc.graph_edges(), vec![(1, 2), (2, 3), (3, 4), (4, 5)]);
+
+    let json = rc.to_json();
+    assert!(json.contains("{\"region\": 1, \"class\": \"bus\", \"value\": 1, \"color\": \"#018080\""));
+    assert!(json.contains("{\"region\": 2, \"class\": \"bus_input\", \"color\": \"#408080\""));
+    assert!(rc.to_dot().contains("  r5 [label=\"bus 5\\n(4,0)-(4,0)\", shape=ellipse, fillcolor=\"#008181\""));
+  }
+}
+
+// eof
@@ -9,6 +9,13 @@
 //!   outputs <- logic  
 //!   wires   <- output
 //! 
+//! and the same again for buses, which share the logic nodes:
+//!   bus inputs  <- buses
+//!   logic       <- bus inputs
+//!   bus outputs <- bus inputs
+//!   bus outputs <- logic
+//!   buses       <- bus outputs
+//! 
 //! This makes use of the dense indices provided by Regionmapper.
 //! 
 //! E.g. 
@@ -50,7 +57,6 @@
 //! - Then, ResoCircuit
 
 use crate::resel::{Resel};
-use crate::reselboard::{ReselBoard};
 use crate::regionmap::{RegionMap};
 
 /// IncidenceMap holds mapping of incident dense-region indices,
@@ -68,6 +74,12 @@ pub struct IncidenceMap {
   pub output_inc_inputs:  Vec<Vec<usize>>,
   pub output_inc_logics:  Vec<Vec<usize>>,
   pub wire_inc_outputs:   Vec<Vec<usize>>,
+
+  pub bus_input_inc_buses:       Vec<Vec<usize>>,
+  pub logic_inc_bus_inputs:      Vec<Vec<usize>>,
+  pub bus_output_inc_bus_inputs: Vec<Vec<usize>>,
+  pub bus_output_inc_logics:     Vec<Vec<usize>>,
+  pub bus_inc_bus_outputs:       Vec<Vec<usize>>,
 }
 
 /// Returns an IncidenceMap from a RegionMap
@@ -81,6 +93,11 @@ fn incidencemap_from_regionmap(
   let mut output_inc_inputs:  Vec<Vec<usize>> = vec![];
   let mut output_inc_logics:  Vec<Vec<usize>> = vec![];
   let mut wire_inc_outputs:   Vec<Vec<usize>> = vec![];
+  let mut bus_input_inc_buses:       Vec<Vec<usize>> = vec![];
+  let mut logic_inc_bus_inputs:      Vec<Vec<usize>> = vec![];
+  let mut bus_output_inc_bus_inputs: Vec<Vec<usize>> = vec![];
+  let mut bus_output_inc_logics:     Vec<Vec<usize>> = vec![];
+  let mut bus_inc_bus_outputs:       Vec<Vec<usize>> = vec![];
 
   // e.g. input_inc_wires[dense_input_index] -> list of dense wire indices
 
@@ -99,6 +116,16 @@ fn incidencemap_from_regionmap(
       Box::new(|y: Resel| y.is_logic()) as Box<dyn Fn(Resel) -> bool>),
     (&mut wire_inc_outputs,  &rm.wire_regions,  //|y| y.is_output()),
       Box::new(|y: Resel| y.is_output()) as Box<dyn Fn(Resel) -> bool>),
+    (&mut bus_input_inc_buses,       &rm.bus_input_regions,
+      Box::new(|y: Resel| y.is_bus()) as Box<dyn Fn(Resel) -> bool>),
+    (&mut logic_inc_bus_inputs,      &rm.logic_regions,
+      Box::new(|y: Resel| y.is_bus_input()) as Box<dyn Fn(Resel) -> bool>),
+    (&mut bus_output_inc_bus_inputs, &rm.bus_output_regions,
+      Box::new(|y: Resel| y.is_bus_input()) as Box<dyn Fn(Resel) -> bool>),
+    (&mut bus_output_inc_logics,     &rm.bus_output_regions,
+      Box::new(|y: Resel| y.is_logic()) as Box<dyn Fn(Resel) -> bool>),
+    (&mut bus_inc_bus_outputs,       &rm.bus_regions,
+      Box::new(|y: Resel| y.is_bus_output()) as Box<dyn Fn(Resel) -> bool>),
   ] {
     for (x_i, ri) in x_regions.iter().enumerate() {
       assert_eq!(x_i, x_inc_y.len());
@@ -120,6 +147,11 @@ fn incidencemap_from_regionmap(
     output_inc_inputs,
     output_inc_logics,
     wire_inc_outputs,
+    bus_input_inc_buses,
+    logic_inc_bus_inputs,
+    bus_output_inc_bus_inputs,
+    bus_output_inc_logics,
+    bus_inc_bus_outputs,
   }
 }
 
@@ -143,9 +175,9 @@ mod reselboard_tests {
 
   use super::*;
   use crate::reselboard::{
+    ReselBoard,
     load_image_from_filename,
   };
-  use crate::regionmap::{RegionMap};
 
   #[test]
   fn test_incident_map_on_half_adder() {
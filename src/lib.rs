@@ -0,0 +1,62 @@
+//! Reso: A visual pixel-art logic-circuit design language.
+//!
+//! This is the library half of Reso. The `reso` binary is a thin CLI over it.
+//!
+//! The pipeline, in the order things are built:
+//!
+//! | Module         | Type           | What it does                                   |
+//! | -------------- | -------------- | ---------------------------------------------- |
+//! | `resel`        | `Resel`        | Conversions between resels, pixels, and chars  |
+//! | `reselboard`   | `ReselBoard`   | A grid of resels, loaded from an image         |
+//! | `regionmap`    | `RegionMap`    | Contiguous regions (nodes) of a board          |
+//! | `incidencemap` | `IncidenceMap` | Incidences (edges) between regions             |
+//! | `resocircuit`  | `ResoCircuit`  | The executable logic graph                     |
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ReselBoard, ResoCircuit};
+//!
+//! let mut rc = ResoCircuit::from(
+//!   ReselBoard::from_filename("./src/testing/test_half_adder_01.png").unwrap()
+//! );
+//! rc.iterate();
+//! rc.update_pixels();
+//! ```
+
+pub mod error;
+pub mod resel;
+pub mod palette;
+pub mod reselboard;
+pub mod regionmap;
+pub mod incidencemap;
+pub mod resocircuit;
+pub mod animation;
+pub mod ports;
+pub mod cycle;
+pub mod serialize;
+pub mod parallel;
+pub mod event;
+pub mod lint;
+pub mod graph;
+pub mod verilog;
+pub mod vcd;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod gpio;
+pub mod subcircuit;
+pub mod components;
+pub mod synth;
+
+pub use error::{ResoError};
+pub use resel::{Resel};
+pub use reselboard::{
+  ReselBoard,
+  load_image_from_filename,
+  load_image_from_filename_string,
+};
+pub use regionmap::{RegionMap};
+pub use incidencemap::{IncidenceMap};
+pub use resocircuit::{ResoCircuit};
+
+// eof
@@ -0,0 +1,241 @@
+//! lint.rs: Report dangling and meaningless structures in a circuit.
+//!
+//! Reso compiles anything, so a stray pixel or a gate drawn one resel too
+//! far from its wire quietly does nothing. The linter walks the RegionMap
+//! and IncidenceMap and reports regions that can never do anything useful:
+//!
+//! | Region | Lint                       | Severity                          |
+//! | ------ | -------------------------- | --------------------------------- |
+//! | input  | touches no wire            | error if it feeds anything, else warning |
+//! | input  | feeds nothing              | warning                           |
+//! | logic  | no inputs, feeds an output | error                             |
+//! | logic  | no inputs                  | warning                           |
+//! | logic  | feeds no output            | warning                           |
+//! | output | feeds no wire              | warning                           |
+//! | output | nothing drives it          | warning                           |
+//! | wire   | both on and off resels     | warning                           |
+//!
+//! Logic fed by bus inputs or feeding bus outputs counts as having inputs
+//! or feeding an output. Buses themselves aren't linted, and neither are
+//! regions inlined from subcircuits, which aren't on the board.
+//!
+//! Each region gets at most one lint, the first in this table that applies.
+//! Lints are reported at the region's first resel (scanning x, then y), in
+//! region order.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::lint::{Severity};
+//!
+//! let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! let lints = rc.lint();
+//! assert!(lints.iter().all(|lint| lint.severity != Severity::Error));
+//! ```
+
+use std::fmt;
+
+use crate::regionmap::{RegionMap};
+use crate::incidencemap::{IncidenceMap};
+use crate::resocircuit::{ResoCircuit};
+
+/// How bad a lint is. Errors are almost certainly mistakes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error   => write!(f, "error"),
+    }
+  }
+}
+
+/// What's wrong with a region. See the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+  InputNoWire,
+  InputUnused,
+  LogicOutputNoInput,
+  LogicNoInput,
+  LogicNoOutput,
+  OutputNoWire,
+  OutputUndriven,
+  MixedWire,
+}
+
+impl LintKind {
+  pub fn message(&self) -> &'static str {
+    match self {
+      LintKind::InputNoWire        => "input touches no wire, so it is always off",
+      LintKind::InputUnused        => "input feeds no logic or output",
+      LintKind::LogicOutputNoInput => "logic feeds an output but touches no input, so it is always off",
+      LintKind::LogicNoInput       => "logic touches no input",
+      LintKind::LogicNoOutput      => "logic feeds no output",
+      LintKind::OutputNoWire       => "output feeds no wire",
+      LintKind::OutputUndriven     => "output touches no input or logic, so it is always off",
+      LintKind::MixedWire          => "wire is drawn partly on and partly off",
+    }
+  }
+}
+
+/// One problem with one region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lint {
+  pub kind: LintKind,
+  pub severity: Severity,
+  /// Region index, as in the RegionMap
+  pub region: usize,
+  /// The region's first resel
+  pub x: usize,
+  pub y: usize,
+}
+
+impl fmt::Display for Lint {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: ({},{}): {}", self.severity, self.x, self.y, self.kind.message())
+  }
+}
+
+/// Lint a compiled circuit. See the module docs.
+pub fn lint(rm: &RegionMap, im: &IncidenceMap) -> Vec<Lint> {
+  // How many logics/outputs read each input, outputs each logic, wires each output
+  let mut input_readers = vec![0; im.input_inc_wires.len()];
+  for ii in im.logic_inc_inputs.iter().chain(&im.output_inc_inputs).flatten() {
+    input_readers[*ii] += 1;
+  }
+  let mut logic_outputs = vec![0; im.logic_inc_inputs.len()];
+  for li in im.output_inc_logics.iter().chain(&im.bus_output_inc_logics).flatten() {
+    logic_outputs[*li] += 1;
+  }
+  let mut output_wires = vec![0; im.output_inc_inputs.len()];
+  for oi in im.wire_inc_outputs.iter().flatten() {
+    output_wires[*oi] += 1;
+  }
+
+  let mut found: Vec<(usize, LintKind, Severity)> = vec![];
+  for (ii, ri) in rm.input_regions.iter().enumerate() {
+    let lint = match (im.input_inc_wires[ii].is_empty(), input_readers[ii] == 0) {
+      (true, false) => Some((LintKind::InputNoWire, Severity::Error)),
+      (true, true)  => Some((LintKind::InputNoWire, Severity::Warning)),
+      (false, true) => Some((LintKind::InputUnused, Severity::Warning)),
+      (false, false) => None,
+    };
+    found.extend(lint.map(|(kind, severity)| (*ri, kind, severity)));
+  }
+  for (li, ri) in rm.logic_regions.iter().enumerate() {
+    let no_inputs = im.logic_inc_inputs[li].is_empty() && im.logic_inc_bus_inputs[li].is_empty();
+    let lint = match (no_inputs, logic_outputs[li] == 0) {
+      (true, false) => Some((LintKind::LogicOutputNoInput, Severity::Error)),
+      (true, true)  => Some((LintKind::LogicNoInput, Severity::Warning)),
+      (false, true) => Some((LintKind::LogicNoOutput, Severity::Warning)),
+      (false, false) => None,
+    };
+    found.extend(lint.map(|(kind, severity)| (*ri, kind, severity)));
+  }
+  for (oi, ri) in rm.output_regions.iter().enumerate() {
+    let undriven = im.output_inc_inputs[oi].is_empty() && im.output_inc_logics[oi].is_empty();
+    let lint = if output_wires[oi] == 0 {
+      Some((LintKind::OutputNoWire, Severity::Warning))
+    } else if undriven {
+      Some((LintKind::OutputUndriven, Severity::Warning))
+    } else {
+      None
+    };
+    found.extend(lint.map(|(kind, severity)| (*ri, kind, severity)));
+  }
+
+  for mixed in &rm.mixed_wires {
+    found.push((mixed.region, LintKind::MixedWire, Severity::Warning));
+  }
+
+  found.sort_by_key(|(ri, _, _)| *ri);
+  found.into_iter().filter_map(|(region, kind, severity)| {
+    let (x, y) = rm.first_xy(region)?;
+    Some(Lint { kind, severity, region, x, y })
+  }).collect()
+}
+
+impl ResoCircuit {
+  /// Lint this circuit. See `reso::lint`.
+  pub fn lint(&self) -> Vec<Lint> {
+    lint(&self.rm, &self.im)
+  }
+}
+
+#[cfg(test)]
+mod lint_tests {
+  use super::*;
+  use crate::reselboard::{ReselBoard, TextOptions, Topology};
+
+  #[test]
+  fn test_lint_half_adder_is_clean() {
+    for filename in [
+      "./src/testing/test_half_adder.png",
+      "./src/testing/test_half_adder.txt",
+    ] {
+      let rc = ResoCircuit::from_filename(filename).unwrap();
+      assert_eq!(rc.lint(), vec![], "{}", filename);
+    }
+  }
+
+  #[test]
+  fn test_lint_each_kind() {
+    let text = [
+      "o+=o", // fine: wire -> input -> output -> wire
+      "    ",
+      "+   ", // (0,2) lone input
+      "    ",
+      "&=s ", // (0,4) logic drives an output with no inputs
+      "    ",
+      "s+^ ", // (2,6) logic with no output
+      "    ",
+      "=   ", // (0,8) output with no wire
+      "    ",
+      "+^=o", // (0,10) input with no wire, feeding logic
+      "    ",
+      "&   ", // (0,12) lone logic
+      "    ",
+      "=o  ", // (0,14) output with nothing driving it
+      "    ",
+      "oO  ", // (0,16) wire both off and on
+    ].join("\n");
+    let rb = ReselBoard::from_text(&text, &TextOptions::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let rc = ResoCircuit::from(rb);
+
+    let found: Vec<(LintKind, Severity, usize, usize)> = rc.lint().iter()
+      .map(|lint| (lint.kind, lint.severity, lint.x, lint.y))
+      .collect();
+    assert_eq!(found, vec![
+      (LintKind::InputNoWire,        Severity::Warning, 0, 2),
+      (LintKind::LogicOutputNoInput, Severity::Error,   0, 4),
+      (LintKind::OutputNoWire,       Severity::Warning, 0, 8),
+      (LintKind::InputNoWire,        Severity::Error,   0, 10),
+      (LintKind::LogicNoInput,       Severity::Warning, 0, 12),
+      (LintKind::OutputUndriven,     Severity::Warning, 0, 14),
+      (LintKind::MixedWire,          Severity::Warning, 0, 16),
+      (LintKind::LogicNoOutput,      Severity::Warning, 2, 6),
+    ]);
+
+    assert_eq!(
+      rc.lint()[1].to_string(),
+      "error: (0,4): logic feeds an output but touches no input, so it is always off"
+    );
+  }
+
+  #[test]
+  fn test_lint_bus_logic() {
+    // Logic between a bus input and a bus output is in use
+    let rb = ReselBoard::from_text("⠁{&}⠂", &TextOptions::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    assert_eq!(ResoCircuit::from(rb).lint(), vec![]);
+  }
+}
+
+// eof
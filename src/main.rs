@@ -1,127 +1,1075 @@
-/// main.rs: Reso CLI
-/// 
-/// 
-
-use clap::{Parser};
-
-
-mod resel;
-#[allow(dead_code)]
-mod reselboard;
-#[allow(unused_parens)]
-mod regionmap;
-mod incidencemap;
-#[allow(unused_parens)]
-mod resocircuit;
-
-use image::{DynamicImage};
-use std::time::{SystemTime, UNIX_EPOCH, SystemTimeError, Duration};
-
-//use resel::{Resel};
-use reselboard::{
-  ReselBoard,
-  load_image_from_filename_string,
-};
-//use regionmap::{RegionMap};
-use resocircuit::{ResoCircuit};
-
-/// Simple program to greet a person
+//! main.rs: Reso CLI
+//! 
+//! A thin consumer of the `reso` library.
+//! 
+//! - `reso run`: Simulate a circuit. (Also what `reso` does with no subcommand.)
+//! - `reso compile`: Compile a circuit to a `.resoc` file, to load it faster later.
+//! - `reso tui`: View and step a circuit interactively in the terminal.
+//!   (With the `tui` feature, on by default.)
+//! - `reso stamp`: Place a component from the library onto a circuit.
+//! - `reso synth`: Synthesize a circuit from a boolean expression.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use std::io::{Write};
+use std::path::{Path};
+use std::process::{ExitCode};
+use std::time::{SystemTime, SystemTimeError, Duration};
+
+use reso::{ReselBoard, ResoCircuit, ResoError};
+use reso::animation::{AnimationFormat, AnimationWriter, upscale_image};
+use reso::lint::{Severity};
+use reso::vcd::{VcdWriter};
+use reso::gpio::{Stimulus, format_gpio_states};
+#[cfg(feature = "tui")]
+use reso::tui::{run_tui};
+use reso::palette::{AlphaMatching, ColorMatching, Palette};
+use reso::event::{EventEngine};
+use reso::resel::{rgba_from_hex};
+use reso::reselboard::{MixedWirePolicy, TextOptions, Topology, is_text_filename};
+use reso::serialize::{is_compiled_filename};
+use reso::components::{COMPONENTS, component};
+use reso::synth::{synthesize};
+use reso::ports::{parse_xy};
+
+/// println!, or eprintln! when stdout is taken, e.g. by `--gpio-out -`
+macro_rules! status {
+  ($to_stderr:expr, $($arg:tt)*) => {
+    if $to_stderr { eprintln!($($arg)*) } else { println!($($arg)*) }
+  };
+}
+
+/// Reso: A visual pixel-art logic-circuit design language
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    /// Input image to simulate, e.g. `reso_logo.png`
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate a circuit. This is the default with no subcommand.
+    Run(RunArgs),
+
+    /// Compile a circuit to a `.resoc` file, so `run` can skip compiling it
+    Compile(CompileArgs),
+
+    /// Report dangling and meaningless structures, e.g. an input touching no
+    /// wire. Exits non-zero on errors (or warnings, with --deny-warnings).
+    Lint(LintArgs),
+
+    /// Export the compiled logic graph as Graphviz DOT or a JSON netlist
+    Graph(GraphArgs),
+
+    /// Export a circuit as a Verilog module, one clock cycle per step
+    Verilog(VerilogArgs),
+
+    /// View a circuit in the terminal: step, run, pause, reset, toggle
+    /// wires, and inspect regions under the cursor
+    #[cfg(feature = "tui")]
+    Tui(TuiArgs),
+
+    /// Place a component from the library (e.g. `full_adder`) onto a
+    /// circuit, and print its ports where they ended up
+    Stamp(StampArgs),
+
+    /// Synthesize a circuit from a boolean expression, e.g. `(a & b) ^ c`,
+    /// and check it against every combination of inputs
+    Synth(SynthArgs),
+}
+
+/// Arguments for loading a circuit, shared by the subcommands
+#[derive(Args, Debug)]
+struct LoadArgs {
+    /// Circuit to load, e.g. `reso_logo.png`. `.txt` files are read as text
+    /// circuits, and `.resoc` files are loaded without recompiling.
+    #[arg(value_name = "INPUT", required_unless_present = "input", conflicts_with = "input")]
+    input_file: Option<String>,
+
+    /// Same as INPUT
+    #[arg(short, long)]
+    input: Option<String>,
+
+    /// Fail on ragged lines or unknown characters in text circuits
+    #[arg(long)]
+    strict_text: bool,
+
+    /// Ports file naming input/output wires, e.g. `half_adder.ports`.
+    #[arg(long)]
+    ports: Option<String>,
+
+    /// Subcircuits file saying which circuit goes in each subcircuit region
+    /// and how its ports connect, e.g. `cpu.subcircuits`. They're inlined
+    /// when compiling, so `.resoc` files already have theirs.
+    #[arg(long)]
+    subcircuits: Option<String>,
+
+    /// Which board edges wrap around: `torus` (default) wraps both,
+    /// `h-cylinder` only left/right, `v-cylinder` only top/bottom,
+    /// `bounded` neither. `.resoc` files keep the topology they were compiled with.
+    #[arg(long, value_parser = parse_topology)]
+    topology: Option<Topology>,
+
+    /// Palette file the circuit is drawn in (`.gpl`, `.hex`, or Paint.NET `.txt`),
+    /// in the order of the RESO-12 palette. Frames are drawn in it too.
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// Snap pixels within this RGB distance of a palette colour to it, for
+    /// lossy or anti-aliased images (e.g. 32). Prints how many were snapped.
+    #[arg(long, value_name = "DISTANCE")]
+    tolerance: Option<u32>,
+
+    /// Match pixels at least this opaque (0-255) on RGB alone, and treat more
+    /// transparent ones as empty. By default alpha must match exactly.
+    #[arg(long, value_name = "ALPHA")]
+    min_alpha: Option<u8>,
+
+    /// Collapse each N x N block of pixels into one resel, for circuits shared
+    /// upscaled (e.g. 8x). `auto` finds the largest scale the image is drawn at.
+    #[arg(long, value_parser = parse_scale)]
+    scale: Option<Scale>,
+
+    /// How to set wires drawn partly on and partly off: `first-pixel` (default),
+    /// `any-on`, `majority`, or `error` to refuse to load them. `reso lint` lists them.
+    #[arg(long, value_name = "POLICY", value_parser = parse_mixed_wire_policy)]
+    mixed_wires: Option<MixedWirePolicy>,
+}
+
+/// How much to downscale an image circuit by
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Scale {
+    Auto,
+    Factor(usize),
+}
+
+fn parse_scale(scale: &str) -> Result<Scale, String> {
+  match scale {
+    "auto" => Ok(Scale::Auto),
+    _ => match scale.parse() {
+      Ok(factor) if factor > 0 => Ok(Scale::Factor(factor)),
+      _ => Err(format!("expected `auto` or a positive integer, not `{}`", scale)),
+    },
+  }
+}
+
+fn parse_topology(name: &str) -> Result<Topology, String> {
+  name.parse()
+}
+
+fn parse_mixed_wire_policy(name: &str) -> Result<MixedWirePolicy, String> {
+  name.parse()
+}
+
+impl LoadArgs {
+  fn input(&self) -> &str {
+    self.input_file.as_deref().or(self.input.as_deref()).unwrap_or_default()
+  }
+
+  /// Tolerant colour matching, if asked for
+  fn color_matching(&self) -> Option<ColorMatching> {
+    if self.tolerance.is_none() && self.min_alpha.is_none() {
+      return None
+    }
+    Some(ColorMatching {
+      tolerance: self.tolerance.unwrap_or_default(),
+      alpha: self.min_alpha.map_or(AlphaMatching::Exact, AlphaMatching::Threshold),
+    })
+  }
+}
+
+#[derive(Args, Debug)]
+struct CompileArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Where to save the compiled circuit. Defaults to INPUT with a `.resoc` extension.
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct LintArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Also exit non-zero on warnings
+    #[arg(long)]
+    deny_warnings: bool,
+}
+
+#[derive(Args, Debug)]
+struct GraphArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Where to write the graph. Defaults to standard output.
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// `dot` or `json`. Defaults to `json` if --output ends in `.json`, else `dot`.
+    #[arg(long)]
+    format: Option<GraphFormat>,
+}
+
+#[derive(Args, Debug)]
+struct VerilogArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Where to write the module. Defaults to standard output.
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Module name. Defaults to INPUT's file name.
+    #[arg(long)]
+    module: Option<String>,
+
+    /// Also write a testbench checking the module against Reso's own
+    /// simulation, e.g. `half_adder_tb.v`
+    #[arg(long)]
+    testbench: Option<String>,
+
+    /// How many steps the testbench checks
+    #[arg(long, default_value_t = 16)]
+    steps: usize,
+
+    /// Hold an input port in the testbench, e.g. `--set a=1`. Can be repeated.
+    #[arg(long = "set", value_name = "NAME=0|1")]
+    set_inputs: Vec<String>,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args, Debug)]
+struct TuiArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Delay between steps while running, in milliseconds. `+`/`-` change it.
+    #[arg(long, default_value_t = 100)]
+    delay: u64,
+
+    /// Hold an input port from the start, e.g. `--set a=1`. Can be repeated.
+    #[arg(long = "set", value_name = "NAME=0|1")]
+    set_inputs: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct StampArgs {
+    /// Circuit to stamp onto. `.txt` files are read and saved as text.
+    #[arg(value_name = "INPUT", required_unless_present = "list")]
+    input: Option<String>,
+
+    /// Component to stamp, e.g. `full_adder`. See --list.
+    #[arg(long, required_unless_present = "list")]
+    component: Option<String>,
+
+    /// Where to put the component's top-left corner, e.g. `10,20`
+    #[arg(long, value_name = "X,Y", value_parser = parse_at, required_unless_present = "list")]
+    at: Option<(usize, usize)>,
+
+    /// Where to save the result. Defaults to overwriting INPUT.
     #[arg(short, long)]
-    input: String,
+    output: Option<String>,
+
+    /// Colour to draw the component's empty resels in images, e.g. `#ffffff`
+    #[arg(long, default_value = "#000000")]
+    empty_color: String,
+
+    /// Palette file the circuit is drawn in, as for `run --palette`. The
+    /// component is drawn in it too.
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// List the components and exit
+    #[arg(long)]
+    list: bool,
+}
+
+#[derive(Args, Debug)]
+struct SynthArgs {
+    /// Boolean expression over named variables with `!`, `&`, `^`, `|`, `0`, `1`
+    /// and parentheses, e.g. `(a & b) ^ c`. Name the output with `carry = ...`.
+    expr: String,
+
+    /// Where to save the circuit. `.txt` saves text. Its ports are saved
+    /// next to it, e.g. `synth.ports`.
+    #[arg(short, long, default_value = "synth.png")]
+    output: String,
+
+    /// Colour to draw empty resels, e.g. `#ffffff`
+    #[arg(long, default_value = "#000000")]
+    empty_color: String,
+
+    /// Don't simulate every combination of inputs to check the circuit
+    #[arg(long)]
+    no_verify: bool,
+}
+
+fn parse_at(xy: &str) -> Result<(usize, usize), String> {
+  parse_xy(xy).ok_or_else(|| format!("expected `x,y`, not `{}`", xy))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    #[command(flatten)]
+    load: LoadArgs,
 
     /// Output prefix to save frames, e.g. `out_` saves to `out_01.png`.
+    /// Text circuits save text frames, e.g. `out_01.txt`.
     #[arg(short, long)]
     output: Option<String>,
 
+    /// Format of saved frames. Defaults to `txt` for text circuits, else `png`.
+    #[arg(short, long, value_enum)]
+    format: Option<FrameFormat>,
+
+    /// Colour to draw empty resels when rendering a text circuit to png, e.g. `#ffffff`
+    #[arg(long, default_value = "#000000")]
+    empty_color: String,
+
+    /// Drive an input port, e.g. `--set a=1`. Can be repeated.
+    #[arg(long = "set", value_name = "NAME=0|1")]
+    set_inputs: Vec<String>,
+
     /// Number of simulation steps to run
     #[arg(short, long, default_value_t = 1)]
     numiter: usize,
 
+    /// Instead of --numiter, run until the wire state stops changing.
+    /// Fails if it is still changing after --max-steps.
+    #[arg(long, conflicts_with = "detect_cycle")]
+    until_stable: bool,
+
+    /// Instead of --numiter, run until the wire state repeats, and report
+    /// the period and pre-period of the cycle
+    #[arg(long)]
+    detect_cycle: bool,
+
+    /// Give up on --until-stable or --detect-cycle after this many steps
+    #[arg(long, default_value_t = 100_000)]
+    max_steps: usize,
+
+    /// Simulate with the event-driven engine, which only re-evaluates what
+    /// changed. Same results, faster for big, mostly idle circuits.
+    #[arg(long)]
+    event: bool,
+
+    /// Save every frame into one animated GIF, e.g. `out.gif`
+    #[arg(long)]
+    gif: Option<String>,
+
+    /// Save every frame into one animated PNG, e.g. `out.png`
+    #[arg(long)]
+    apng: Option<String>,
+
+    /// Delay between animation frames, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    delay: u32,
+
+    /// Integer factor to upscale animation frames by
+    #[arg(long, default_value_t = 1)]
+    upscale: u32,
+
+    /// After --scale, draw PNG frames and animations back at the original
+    /// scale (times --upscale for animations)
+    #[arg(long)]
+    keep_scale: bool,
+
+    /// Trace every wire's state at every step into a VCD file, e.g.
+    /// `trace.vcd` for GTKWave. Wires are named by port, or region and colour.
+    #[arg(long)]
+    vcd: Option<String>,
+
+    /// Drive the GPIO wires from a stimulus file (`-` for stdin), one line
+    /// per step, e.g. `1 0 x`. See `reso::gpio`.
+    #[arg(long, conflicts_with_all = ["until_stable", "detect_cycle"])]
+    stimulus: Option<String>,
+
+    /// Record the GPIO wires' states after every step to a file (`-` for
+    /// stdout), one line per step, e.g. `1,0,1`
+    #[arg(long)]
+    gpio_out: Option<String>,
+
+    /// Only add every Nth step to animations
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    skip: u64,
+
+    /// Load a state snapshot (`.resos`) before simulating, to resume a simulation
+    #[arg(long)]
+    state: Option<String>,
+
+    /// Save a state snapshot (`.resos`) after simulating
+    #[arg(long)]
+    save_state: Option<String>,
+
     /// Print to console
     #[arg(short, long)]
     verbose: bool,
 
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum FrameFormat {
+    Png,
+    Txt,
+}
+
+pub fn main() -> ExitCode {
+  let cli = Cli::parse();
+  match cli.command {
+    Some(Command::Run(args)) => run(args),
+    Some(Command::Compile(args)) => compile(args),
+    Some(Command::Lint(args)) => lint(args),
+    Some(Command::Graph(args)) => graph(args),
+    Some(Command::Verilog(args)) => verilog(args),
+    #[cfg(feature = "tui")]
+    Some(Command::Tui(args)) => tui(args),
+    Some(Command::Stamp(args)) => stamp(args),
+    Some(Command::Synth(args)) => synth(args),
+    None => run(cli.run),
+  }
+}
+
+/// Load (and compile, unless it's `.resoc`) a circuit and attach its ports
+fn load(args: &LoadArgs) -> Result<ResoCircuit, ResoError> {
+  let input = args.input();
+  let palette = match &args.palette {
+    Some(palette) => Some(Palette::from_filename(palette)?),
+    None => None,
+  };
+  let mut rc = if is_compiled_filename(input) {
+    let rc = ResoCircuit::from_compiled_filename(input)?;
+    if args.topology.is_some_and(|topology| topology != rc.rb.topology) {
+      return Err(ResoError::Format(format!(
+        "compiled with topology {}; recompile it to change topology", rc.rb.topology
+      )))
+    }
+    if palette.is_some_and(|palette| palette != rc.rb.palette) {
+      return Err(ResoError::Format(
+        "compiled with a different palette; recompile it to change palette".to_string()
+      ))
+    }
+    if args.color_matching().is_some() || args.scale.is_some() {
+      return Err(ResoError::Format(
+        "already compiled; --tolerance, --min-alpha and --scale only apply to images".to_string()
+      ))
+    }
+    if args.mixed_wires.is_some() {
+      return Err(ResoError::Format(
+        "already compiled; recompile it to change --mixed-wires".to_string()
+      ))
+    }
+    if args.subcircuits.is_some() {
+      return Err(ResoError::Format(
+        "already compiled; subcircuits are inlined when compiling".to_string()
+      ))
+    }
+    rc
+  } else {
+    let rb = if is_text_filename(input) && args.strict_text {
+      ReselBoard::from_text_filename(input, &TextOptions::strict())?
+    } else {
+      ReselBoard::from_filename(input)?
+    };
+    let mut rb = rb.with_topology(args.topology.unwrap_or_default())
+      .with_palette(palette.unwrap_or_default());
+    if let Some(matching) = args.color_matching() {
+      if let Some(report) = rb.snap_image(&matching) {
+        eprintln!("Colour matching {}: {}", input, report);
+      }
+    }
+    if let Some(scale) = args.scale {
+      let factor = match scale {
+        Scale::Auto => rb.detect_scale(),
+        Scale::Factor(factor) => factor,
+      };
+      rb = rb.with_scale(factor)?;
+      if factor > 1 {
+        eprintln!("Downscaled {} by {} to {}x{}", input, factor, rb.width, rb.height);
+      }
+    }
+    let mut rc = ResoCircuit::compile(
+      rb.with_mixed_wire_policy(args.mixed_wires.unwrap_or_default())
+    )?;
+    if let Some(policy) = args.mixed_wires {
+      for mixed in &rc.rm.mixed_wires {
+        eprintln!(
+          "Mixed wire at ({},{}): {} on, {} off, so {} by {}", mixed.x, mixed.y, mixed.on, mixed.off,
+          if rc.rm.region_to_resel[mixed.region].wire_state() == Some(true) { "on" } else { "off" },
+          policy
+        );
+      }
+    }
+    if let Some(subcircuits) = &args.subcircuits {
+      rc.load_subcircuits(subcircuits)?;
+    }
+    rc
+  };
+  if let Some(ports) = &args.ports {
+    rc.load_ports(ports)?;
+  }
+  Ok(rc)
+}
+
+fn compile(args: CompileArgs) -> ExitCode {
+  let input = args.load.input();
+  let rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+
+  let output = args.output.unwrap_or_else(
+    || Path::new(input).with_extension("resoc").to_string_lossy().into_owned()
+  );
+  if let Err(err) = rc.save_compiled(&output) {
+    eprintln!("Could not save {}: {}", output, err);
+    return ExitCode::FAILURE
+  }
+  ExitCode::SUCCESS
+}
+
 // Record start and end timing
-pub fn main() {
-  let args = Args::parse();
+fn run(args: RunArgs) -> ExitCode {
+  let input = args.load.input();
+  // With `--gpio-out -`, stdout is just the GPIO states
+  let to_stderr = args.gpio_out.as_deref() == Some("-");
   if args.verbose {
-    println!("Compiling {}", args.input)
+    status!(to_stderr, "Compiling {}", input)
   }
   let start_compile = SystemTime::now();
-  let mut rc = ResoCircuit::from(
-    ReselBoard::from(
-      load_image_from_filename_string(
-        args.input.clone()
-      ).unwrap()
-    )
-  );
+  let is_text = is_text_filename(input);
+  let mut rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
   if args.verbose {
-    print_duration(SystemTime::now().duration_since(start_compile));
+    print_duration(SystemTime::now().duration_since(start_compile), to_stderr);
+  }
+
+  if let Some(state) = &args.state {
+    if let Err(err) = rc.load_state(state) {
+      eprintln!("Could not load state {}: {}", state, err);
+      return ExitCode::FAILURE
+    }
+  }
+  if let Err(err) = apply_set_inputs(&mut rc, &args.set_inputs) {
+    eprintln!("{}", err);
+    return ExitCode::FAILURE
   }
 
+  let format = args.format.unwrap_or(
+    if is_text { FrameFormat::Txt } else { FrameFormat::Png }
+  );
+  let needs_image = (args.output.is_some() && format == FrameFormat::Png)
+    || args.gif.is_some() || args.apng.is_some();
+  if needs_image && rc.get_image().is_none() {
+    match rgba_from_hex(&args.empty_color) {
+      Some(empty) => rc.render_image(empty),
+      None => {
+        eprintln!("Could not parse --empty-color {}", args.empty_color);
+        return ExitCode::FAILURE
+      }
+    }
+  }
+
+  // Find how many steps to run. For --until-stable and --detect-cycle,
+  // that means running a copy of the circuit first.
+  let numiter = if args.until_stable {
+    match rc.clone().run_until_stable(args.max_steps) {
+      Some(steps) => {
+        status!(to_stderr, "Stable after {} steps", steps);
+        steps
+      },
+      None => {
+        eprintln!("Not stable within {} steps", args.max_steps);
+        return ExitCode::FAILURE
+      }
+    }
+  } else if args.detect_cycle {
+    match rc.clone().find_cycle(args.max_steps) {
+      Some(cycle) if cycle.is_stable() => {
+        status!(to_stderr, "Stable after {} steps", cycle.pre_period);
+        cycle.pre_period
+      },
+      Some(cycle) => {
+        status!(
+          to_stderr, "Cycle with period {} after a pre-period of {} steps",
+          cycle.period, cycle.pre_period
+        );
+        cycle.pre_period + cycle.period
+      },
+      None => {
+        eprintln!("No repeated state within {} steps", args.max_steps);
+        return ExitCode::FAILURE
+      }
+    }
+  } else {
+    args.numiter
+  };
+
+  // --keep-scale draws frames back at the scale the image was drawn at
+  let frame_scale = if args.keep_scale { rc.rb.scale as u32 } else { 1 };
+
+  // Animations hold the input image as frame 0, then every `skip`th step
+  let skip = args.skip as usize;
+  let num_frames = 1 + numiter / skip;
+  let mut animations: Vec<(&String, AnimationWriter)> = vec![];
+  for (filename, animation_format) in [
+    (&args.gif, AnimationFormat::Gif),
+    (&args.apng, AnimationFormat::Apng),
+  ] {
+    let Some(filename) = filename else { continue };
+    let created = AnimationWriter::create(
+      filename, animation_format,
+      rc.rb.width as u32, rc.rb.height as u32,
+      num_frames as u32, args.delay, args.upscale * frame_scale
+    ).and_then(|mut writer| {
+      writer.write_frame(rc.get_image().unwrap())?;
+      Ok(writer)
+    });
+    match created {
+      Ok(writer) => animations.push((filename, writer)),
+      Err(err) => {
+        eprintln!("Could not write {}: {}", filename, err);
+        return ExitCode::FAILURE
+      }
+    }
+  }
+
+  let mut vcd = match &args.vcd {
+    Some(filename) => match VcdWriter::create(filename, &rc)
+      .and_then(|mut vcd| { vcd.record(0, &rc)?; Ok(vcd) })
+    {
+      Ok(vcd) => Some(vcd),
+      Err(err) => {
+        eprintln!("Could not write {}: {}", filename, err);
+        return ExitCode::FAILURE
+      }
+    },
+    None => None,
+  };
+
+  let num_gpios = rc.gpio_wires().len();
+  let mut stimulus = match &args.stimulus {
+    Some(filename) => match Stimulus::open(filename, num_gpios) {
+      Ok(stimulus) => Some(stimulus),
+      Err(err) => {
+        eprintln!("Could not read {}: {}", filename, err);
+        return ExitCode::FAILURE
+      }
+    },
+    None => None,
+  };
+  let mut gpio_out: Option<Box<dyn Write>> = match args.gpio_out.as_deref() {
+    Some("-") => Some(Box::new(std::io::stdout())),
+    Some(filename) => match std::fs::File::create(filename) {
+      Ok(file) => Some(Box::new(std::io::BufWriter::new(file))),
+      Err(err) => {
+        eprintln!("Could not write {}: {}", filename, err);
+        return ExitCode::FAILURE
+      }
+    },
+    None => None,
+  };
 
   let mut tt_interpolated: String;
   let start_time = SystemTime::now();
 
   if args.verbose {
-    println!("Simulating {} iterations on {}", args.numiter, &args.input);
+    status!(to_stderr, "Simulating {} iterations on {}", numiter, input);
   }
 
+  let mut engine = args.event.then(|| EventEngine::new(&rc));
+
   // Index from 1 to N, inclusive. "0" is the input image
-  for tt in 1..(args.numiter+1) {
+  for tt in 1..(numiter+1) {
     tt_interpolated = format!(
-      "{:0width$}", tt, width=args.numiter.to_string().len()
+      "{:0width$}", tt, width=numiter.to_string().len()
     );
 
     if (tt % 100) == 0 && args.verbose {
-      println!("Step {} of {}", tt_interpolated, args.numiter);
+      status!(to_stderr, "Step {} of {}", tt_interpolated, numiter);
+    }
+    if let Some(stimulus) = &mut stimulus {
+      let driven = stimulus.next_step().and_then(|values| match values {
+        Some(values) => rc.drive_gpios(&values),
+        None => Ok(()),
+      });
+      if let Err(err) = driven {
+        eprintln!("Could not drive GPIOs from {}: {}", args.stimulus.as_deref().unwrap(), err);
+        return ExitCode::FAILURE
+      }
+    }
+
+    match &mut engine {
+      Some(engine) => engine.iterate(&mut rc),
+      None => rc.iterate(),
+    }
+
+    if let Some(out) = &mut gpio_out {
+      if let Err(err) = writeln!(out, "{}", format_gpio_states(&rc.gpio_states())) {
+        eprintln!("Could not write {}: {}", args.gpio_out.as_deref().unwrap(), err);
+        return ExitCode::FAILURE
+      }
     }
-    rc.iterate();
 
-    if args.output.is_some() {
+    if let Some(vcd) = &mut vcd {
+      if let Err(err) = vcd.record(tt, &rc) {
+        eprintln!("Could not write {}: {}", args.vcd.as_deref().unwrap(), err);
+        return ExitCode::FAILURE
+      }
+    }
+
+    if let Some(output) = &args.output {
+      let saved = if format == FrameFormat::Txt {
+        rc.update_board();
+        let filename = format!("{}{}.txt", output, tt_interpolated);
+        rc.rb.save_text(&filename).map_err(|err| (filename, err.to_string()))
+      } else {
+        rc.update_pixels();
+        let filename = format!("{}{}.png", output, tt_interpolated);
+        let image = rc.get_image().unwrap();
+        let saved = if frame_scale > 1 {
+          upscale_image(image, frame_scale).save(&filename)
+        } else {
+          image.save(&filename)
+        };
+        saved.map_err(|err| (filename, err.to_string()))
+      };
+      if let Err((filename, err)) = saved {
+        eprintln!("Could not save {}: {}", filename, err);
+        return ExitCode::FAILURE
+      }
+    }
+
+    if !animations.is_empty() && tt % skip == 0 {
       rc.update_pixels();
-      rc.get_image().unwrap().save(
-        format!(
-          "{}{}.png",
-          args.output.as_ref().unwrap_or(&String::from("output_")),
-          tt_interpolated
-        )
-      );
-    }   
+      for (filename, writer) in animations.iter_mut() {
+        if let Err(err) = writer.write_frame(rc.get_image().unwrap()) {
+          eprintln!("Could not write {}: {}", filename, err);
+          return ExitCode::FAILURE
+        }
+      }
+    }
+  }
+
+  for (filename, writer) in animations {
+    if let Err(err) = writer.finish() {
+      eprintln!("Could not write {}: {}", filename, err);
+      return ExitCode::FAILURE
+    }
+  }
+  if let Some(mut out) = gpio_out {
+    if let Err(err) = out.flush() {
+      eprintln!("Could not write {}: {}", args.gpio_out.as_deref().unwrap(), err);
+      return ExitCode::FAILURE
+    }
+  }
+  if let Some(vcd) = vcd {
+    if let Err(err) = vcd.finish() {
+      eprintln!("Could not write {}: {}", args.vcd.as_deref().unwrap(), err);
+      return ExitCode::FAILURE
+    }
   }
 
   // Print time
   if args.verbose {
-    print_duration(SystemTime::now().duration_since(start_time));
+    print_duration(SystemTime::now().duration_since(start_time), to_stderr);
   }
+
+  if let Some(save_state) = &args.save_state {
+    if let Err(err) = rc.save_state(save_state) {
+      eprintln!("Could not save state {}: {}", save_state, err);
+      return ExitCode::FAILURE
+    }
+  }
+
+  for (name, value) in rc.outputs() {
+    status!(to_stderr, "{}={}", name, value as u8);
+  }
+  ExitCode::SUCCESS
+}
+
+fn lint(args: LintArgs) -> ExitCode {
+  let input = args.load.input();
+  let rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+
+  let lints = rc.lint();
+  for lint in &lints {
+    println!("{}: {}", input, lint);
+  }
+  let errors = lints.iter().filter(|lint| lint.severity == Severity::Error).count();
+  let warnings = lints.len() - errors;
+  println!("{}: {} errors, {} warnings", input, errors, warnings);
+
+  if errors > 0 || (args.deny_warnings && warnings > 0) {
+    ExitCode::FAILURE
+  } else {
+    ExitCode::SUCCESS
+  }
+}
+
+fn graph(args: GraphArgs) -> ExitCode {
+  let input = args.load.input();
+  let rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+
+  let is_json_filename = args.output.as_deref()
+    .is_some_and(|output| Path::new(output).extension().is_some_and(|ext| ext == "json"));
+  let format = args.format.unwrap_or(
+    if is_json_filename { GraphFormat::Json } else { GraphFormat::Dot }
+  );
+  let graph = match format {
+    GraphFormat::Dot => rc.to_dot(),
+    GraphFormat::Json => rc.to_json(),
+  };
+
+  match &args.output {
+    Some(output) => if let Err(err) = std::fs::write(output, graph) {
+      eprintln!("Could not save {}: {}", output, err);
+      return ExitCode::FAILURE
+    },
+    None => print!("{}", graph),
+  }
+  ExitCode::SUCCESS
+}
+
+/// Apply `--set NAME=0|1` arguments
+fn apply_set_inputs(rc: &mut ResoCircuit, set_inputs: &[String]) -> Result<(), String> {
+  for set_input in set_inputs {
+    let set = match set_input.split_once('=') {
+      Some((name, "1")) => rc.set_input(name, true),
+      Some((name, "0")) => rc.set_input(name, false),
+      _ => return Err(format!("Could not parse --set {}, expected NAME=0 or NAME=1", set_input)),
+    };
+    set.map_err(|err| format!("Could not --set {}: {}", set_input, err))?;
+  }
+  Ok(())
+}
+
+fn verilog(args: VerilogArgs) -> ExitCode {
+  let input = args.load.input();
+  let mut rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+  if let Err(err) = apply_set_inputs(&mut rc, &args.set_inputs) {
+    eprintln!("{}", err);
+    return ExitCode::FAILURE
+  }
+
+  // Default module name: the file name, as an identifier
+  let module = args.module.clone().unwrap_or_else(|| {
+    let stem = Path::new(input).file_stem().unwrap_or_default().to_string_lossy();
+    let name: String = stem.chars()
+      .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+      .collect();
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) { name } else { format!("reso_{}", name) }
+  });
+
+  let verilog = match rc.to_verilog(&module) {
+    Ok(verilog) => verilog,
+    Err(err) => {
+      eprintln!("Could not export {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+  match &args.output {
+    Some(output) => if let Err(err) = std::fs::write(output, verilog) {
+      eprintln!("Could not save {}: {}", output, err);
+      return ExitCode::FAILURE
+    },
+    None => print!("{}", verilog),
+  }
+
+  if let Some(testbench) = &args.testbench {
+    let saved = rc.to_verilog_testbench(&module, args.steps)
+      .and_then(|bench| Ok(std::fs::write(testbench, bench)?));
+    if let Err(err) = saved {
+      eprintln!("Could not save {}: {}", testbench, err);
+      return ExitCode::FAILURE
+    }
+  }
+  ExitCode::SUCCESS
+}
+
+#[cfg(feature = "tui")]
+fn tui(args: TuiArgs) -> ExitCode {
+  let input = args.load.input();
+  let mut rc = match load(&args.load) {
+    Ok(rc) => rc,
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+  if let Err(err) = apply_set_inputs(&mut rc, &args.set_inputs) {
+    eprintln!("{}", err);
+    return ExitCode::FAILURE
+  }
+
+  if let Err(err) = run_tui(rc, Duration::from_millis(args.delay)) {
+    eprintln!("Terminal error: {}", err);
+    return ExitCode::FAILURE
+  }
+  ExitCode::SUCCESS
+}
+
+fn stamp(args: StampArgs) -> ExitCode {
+  if args.list {
+    for component in COMPONENTS {
+      println!("{:12} {}", component.name, component.description);
+    }
+    return ExitCode::SUCCESS
+  }
+  let (Some(input), Some(name), Some((x, y))) = (&args.input, &args.component, args.at) else {
+    unreachable!("clap requires INPUT, --component and --at without --list")
+  };
+
+  let component = match component(name) {
+    Ok(component) => component,
+    Err(err) => {
+      eprintln!("{}. Try --list", err);
+      return ExitCode::FAILURE
+    }
+  };
+  let Some(empty) = rgba_from_hex(&args.empty_color) else {
+    eprintln!("Could not parse --empty-color {}", args.empty_color);
+    return ExitCode::FAILURE
+  };
+  let palette = match args.palette.as_deref().map(Palette::from_filename).transpose() {
+    Ok(palette) => palette.unwrap_or_default(),
+    Err(err) => {
+      eprintln!("Could not load palette {}: {}", args.palette.as_deref().unwrap(), err);
+      return ExitCode::FAILURE
+    }
+  };
+  let mut rb = match ReselBoard::from_filename(input) {
+    Ok(rb) => rb.with_palette(palette),
+    Err(err) => {
+      eprintln!("Could not load {}: {}", input, err);
+      return ExitCode::FAILURE
+    }
+  };
+  let stamped = component.board().and_then(|board| rb.stamp(x, y, &board, empty));
+  if let Err(err) = stamped {
+    eprintln!("Could not stamp {} at {},{}: {}", component.name, x, y, err);
+    return ExitCode::FAILURE
+  }
+
+  let output = args.output.as_deref().unwrap_or(input);
+  let saved = if is_text_filename(output) {
+    rb.save_text(output)
+  } else {
+    let image = rb.image.take().unwrap_or_else(|| rb.to_image(empty));
+    image.save(output).map_err(ResoError::from)
+  };
+  if let Err(err) = saved {
+    eprintln!("Could not save {}: {}", output, err);
+    return ExitCode::FAILURE
+  }
+
+  // In ports file format, to paste into the circuit's ports file
+  for spec in component.ports_at(x, y) {
+    println!("{}", spec);
+  }
+  ExitCode::SUCCESS
+}
+
+fn synth(args: SynthArgs) -> ExitCode {
+  let synthesis = match synthesize(&args.expr) {
+    Ok(synthesis) => synthesis,
+    Err(err) => {
+      eprintln!("Could not synthesize {:?}: {}", args.expr, err);
+      return ExitCode::FAILURE
+    }
+  };
+  let Some(empty) = rgba_from_hex(&args.empty_color) else {
+    eprintln!("Could not parse --empty-color {}", args.empty_color);
+    return ExitCode::FAILURE
+  };
+  if !args.no_verify {
+    match synthesis.verify() {
+      Ok(combinations) => println!("Checked all {} combinations of inputs", combinations),
+      Err(err) => {
+        eprintln!("Could not check {:?}: {}", args.expr, err);
+        return ExitCode::FAILURE
+      }
+    }
+  }
+
+  let output = &args.output;
+  let saved = if is_text_filename(output) {
+    synthesis.board.save_text(output)
+  } else {
+    synthesis.board.to_image(empty).save(output).map_err(ResoError::from)
+  };
+  let ports = Path::new(output).with_extension("ports").to_string_lossy().into_owned();
+  let ports_text: String = synthesis.ports.iter().map(|spec| format!("{}\n", spec)).collect();
+  let saved = saved.and_then(|_| std::fs::write(&ports, ports_text).map_err(ResoError::from));
+  if let Err(err) = saved {
+    eprintln!("Could not save {}: {}", output, err);
+    return ExitCode::FAILURE
+  }
+  println!(
+    "Saved {}x{} circuit to {}, with ports in {}",
+    synthesis.board.width, synthesis.board.height, output, ports
+  );
+  ExitCode::SUCCESS
 }
 
-fn print_duration(duration: Result<Duration, SystemTimeError>) {
+fn print_duration(duration: Result<Duration, SystemTimeError>, to_stderr: bool) {
   match duration {
     Ok(duration) => {
       if duration.as_secs() >= 10 {
-        println!("Done in {} s", duration.as_secs());
+        status!(to_stderr, "Done in {} s", duration.as_secs());
       } else if duration.as_millis() >= 10 {
-        println!("Done in {}ms", duration.as_millis());
+        status!(to_stderr, "Done in {}ms", duration.as_millis());
       } else if duration.as_micros() >= 10 {
-        println!("Done in {}us", duration.as_micros());
+        status!(to_stderr, "Done in {}us", duration.as_micros());
       } else if duration.as_nanos() >= 10 {
-        println!("Done in {}ns", duration.as_nanos());
+        status!(to_stderr, "Done in {}ns", duration.as_nanos());
       } else if duration.is_zero() {
-        println!("Done instantly. Wow! This should be impossible.");
+        status!(to_stderr, "Done instantly. Wow! This should be impossible.");
       } else {
-        println!("Done in almost an insant.");
+        status!(to_stderr, "Done in almost an insant.");
       }
     },
     Err(_) => {
-      println!("Done in a time machine. Our timer says you went back in time.");
-      println!("How did you do that??");
+      status!(to_stderr, "Done in a time machine. Our timer says you went back in time.");
+      status!(to_stderr, "How did you do that??");
     }
   }
 }
@@ -0,0 +1,435 @@
+//! palette.rs: Custom palettes mapping colours to resel classes.
+//!
+//! Reso circuits are normally drawn in RESO-12, and `Resel::from(Rgba)`
+//! only knows those colours. A Palette lets a circuit be drawn in any ten
+//! colours instead, e.g. to hide a circuit in pixel art with its own palette.
+//!
+//! A palette is an ordered list of colours, in the same order as
+//! PALETTE_RESEL: orange off, orange on, sapphire off, sapphire on, lime
+//! off, lime on, AND, XOR, input, output. Any colours after the first ten
+//! are ignored, so the RESO-12 palette files (which end in white and black)
+//! load as-is. Buses, GPIO wires and subcircuits keep their own colours (see
+//! resel.rs) in any palette, unless a palette colour takes one. Every other colour is Resel::Empty.
+//!
+//! Palettes are loaded from the same formats as `book/src/palettes/`:
+//!
+//! | Extension | Format                                                 |
+//! | --------- | ------------------------------------------------------ |
+//! | `.gpl`    | GIMP palette: `GIMP Palette` header, then `R G B name` |
+//! | `.hex`    | One `rrggbb` per line                                  |
+//! | `.txt`    | Paint.NET: `;` comments, then one `aarrggbb` per line  |
+//!
+//! By default a pixel must be exactly a palette colour, alpha included.
+//! For lossy or anti-aliased images (e.g. JPEG screenshots), ColorMatching
+//! can instead snap each pixel to the nearest palette colour within some
+//! RGB distance, and decide by alpha which pixels count at all. A
+//! SnapReport counts how many pixels were snapped.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::palette::{Palette};
+//! use reso::resel::{Resel};
+//! use image::{Rgba};
+//!
+//! let palette = Palette::from_filename("./book/src/palettes/reso-12.gpl").unwrap();
+//! assert_eq!(palette, Palette::default());
+//! assert_eq!(palette.to_resel(Rgba([0, 255, 128, 255])), Resel::XOR);
+//! ```
+
+use image::{Rgba};
+use std::path::{Path};
+
+use crate::error::{ResoError};
+use crate::resel::{Resel, PALETTE_RESEL, PALETTE_RGBA, rgba_from_hex};
+
+/// How many colours a palette needs: one per resel class, except Empty
+pub const PALETTE_LEN: usize = 10;
+
+/// Colours for each non-empty resel class, in PALETTE_RESEL order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+  pub colors: [Rgba<u8>; PALETTE_LEN],
+}
+
+impl Default for Palette {
+  /// RESO-12
+  fn default() -> Palette {
+    let mut colors = [Rgba([0, 0, 0, 255]); PALETTE_LEN];
+    colors.copy_from_slice(&PALETTE_RGBA[..PALETTE_LEN]);
+    Palette { colors }
+  }
+}
+
+/// How to treat the alpha channel when matching colours
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMatching {
+  /// Alpha must equal the palette colour's alpha (255 for RESO-12)
+  #[default]
+  Exact,
+  /// Pixels at least this opaque match on RGB alone.
+  /// More transparent pixels are Resel::Empty.
+  Threshold(u8),
+}
+
+/// How closely a pixel must match a palette colour. The default is exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColorMatching {
+  /// Largest Euclidean RGB distance from a palette colour that still snaps
+  /// to it, e.g. 32. Pixels snap to the nearest colour. 0 means exact.
+  pub tolerance: u32,
+  pub alpha: AlphaMatching,
+}
+
+/// How one pixel matched the palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMatch {
+  /// Exactly a palette colour
+  Exact(Resel),
+  /// Not exactly a palette colour, but close enough to snap to one
+  Snapped(Resel),
+  /// Not a palette colour, so Resel::Empty
+  Unmatched,
+}
+
+impl ColorMatch {
+  pub fn resel(self) -> Resel {
+    match self {
+      ColorMatch::Exact(resel) | ColorMatch::Snapped(resel) => resel,
+      ColorMatch::Unmatched => Resel::Empty,
+    }
+  }
+}
+
+/// How many pixels of an image matched the palette, and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SnapReport {
+  pub exact: usize,
+  pub snapped: usize,
+  pub unmatched: usize,
+}
+
+impl SnapReport {
+  /// Count one more pixel
+  pub fn record(&mut self, matched: ColorMatch) {
+    match matched {
+      ColorMatch::Exact(_)   => self.exact += 1,
+      ColorMatch::Snapped(_) => self.snapped += 1,
+      ColorMatch::Unmatched  => self.unmatched += 1,
+    }
+  }
+}
+
+impl std::fmt::Display for SnapReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f, "{} pixels exact, {} snapped, {} unmatched (empty)",
+      self.exact, self.snapped, self.unmatched
+    )
+  }
+}
+
+/// The formats a palette file can be in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+  Gpl,
+  Hex,
+  PaintNet,
+}
+
+/// The bus, GPIO or subcircuit resel drawn in `rgba`, or Resel::Empty
+fn reserved_from_rgba(rgba: Rgba<u8>) -> Resel {
+  match Resel::from(rgba) {
+    resel if resel.is_reserved() => resel,
+    _ => Resel::Empty,
+  }
+}
+
+impl PaletteFormat {
+  /// Guess the format from a file extension
+  pub fn from_filename(filename: &str) -> Option<PaletteFormat> {
+    let ext = Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+      "gpl" => Some(PaletteFormat::Gpl),
+      "hex" => Some(PaletteFormat::Hex),
+      "txt" => Some(PaletteFormat::PaintNet),
+      _ => None,
+    }
+  }
+}
+
+impl Palette {
+  /// Make a palette from a list of colours in PALETTE_RESEL order.
+  /// Only the first PALETTE_LEN colours are used. They must differ, and
+  /// can't be a bus, GPIO or subcircuit colour, which are fixed.
+  pub fn new(colors: &[Rgba<u8>]) -> Result<Palette, ResoError> {
+    if colors.len() < PALETTE_LEN {
+      return Err(ResoError::Palette(format!(
+        "{} colours, but a palette needs at least {}", colors.len(), PALETTE_LEN
+      )))
+    }
+    let mut palette = Palette::default();
+    palette.colors.copy_from_slice(&colors[..PALETTE_LEN]);
+    for (idx, color) in palette.colors.iter().enumerate() {
+      if let Some(first) = palette.colors[..idx].iter().position(|c| c == color) {
+        return Err(ResoError::Palette(format!(
+          "{:?} and {:?} have the same colour", PALETTE_RESEL[first], PALETTE_RESEL[idx]
+        )))
+      }
+      let reserved = reserved_from_rgba(*color);
+      if reserved != Resel::Empty {
+        return Err(ResoError::Palette(format!(
+          "{:?} has the same colour as {:?}", PALETTE_RESEL[idx], reserved
+        )))
+      }
+    }
+    Ok(palette)
+  }
+
+  /// The resel drawn in `rgba`, or Resel::Empty if it's not in the
+  /// palette (or a bus, GPIO or subcircuit colour)
+  pub fn to_resel(&self, rgba: Rgba<u8>) -> Resel {
+    self.colors.iter().position(|&color| color == rgba)
+      .map_or_else(|| reserved_from_rgba(rgba), |idx| PALETTE_RESEL[idx])
+  }
+
+  /// Match `rgba` to the palette, snapping to the nearest colour if
+  /// `matching` allows
+  pub fn match_color(&self, rgba: Rgba<u8>, matching: &ColorMatching) -> ColorMatch {
+    if let Some(idx) = self.colors.iter().position(|&color| color == rgba) {
+      return ColorMatch::Exact(PALETTE_RESEL[idx])
+    }
+    // Reserved colours are exact or nothing: snapping would change bus
+    // values
+    let reserved = reserved_from_rgba(rgba);
+    if reserved != Resel::Empty {
+      return ColorMatch::Exact(reserved)
+    }
+    if let AlphaMatching::Threshold(min_alpha) = matching.alpha {
+      if rgba[3] < min_alpha {
+        return ColorMatch::Unmatched
+      }
+    }
+
+    let distance_squared = |color: &Rgba<u8>| -> u64 {
+      (0..3).map(|c| (color[c] as i64 - rgba[c] as i64).pow(2) as u64).sum()
+    };
+    self.colors.iter().enumerate()
+      .filter(|(_, color)| match matching.alpha {
+        AlphaMatching::Exact => color[3] == rgba[3],
+        AlphaMatching::Threshold(_) => true,
+      })
+      .map(|(idx, color)| (distance_squared(color), idx))
+      .min()
+      .filter(|(distance_squared, _)| *distance_squared <= (matching.tolerance as u64).pow(2))
+      .map_or(ColorMatch::Unmatched, |(_, idx)| ColorMatch::Snapped(PALETTE_RESEL[idx]))
+  }
+
+  /// The colour to draw `resel` in, or None for Resel::Empty
+  pub fn to_rgba(&self, resel: Resel) -> Option<Rgba<u8>> {
+    if resel.is_reserved() {
+      return Some(resel.into())
+    }
+    PALETTE_RESEL[..PALETTE_LEN].iter().position(|&r| r == resel)
+      .map(|idx| self.colors[idx])
+  }
+
+  /// Parse the text of a palette file.
+  /// Line numbers in errors count from 1.
+  pub fn parse(text: &str, format: PaletteFormat) -> Result<Palette, ResoError> {
+    let mut colors = vec![];
+
+    for (line_idx, line) in text.lines().enumerate() {
+      let line_no = line_idx + 1;
+      let err = |message: &str| ResoError::PaletteFile {
+        line: line_no, message: message.to_string()
+      };
+      let line = line.trim();
+
+      let color = match format {
+        PaletteFormat::Gpl => {
+          if line_no == 1 {
+            if line != "GIMP Palette" {
+              return Err(err("expected `GIMP Palette`"))
+            }
+            continue
+          }
+          // Skip comments and `Name:`/`Columns:` headers
+          if line.is_empty() || line.starts_with('#') || line.contains(':') {
+            continue
+          }
+          let channels: Vec<u8> = line.split_whitespace().take(3)
+            .map(|channel| channel.parse().map_err(|_| err("expected `R G B`")))
+            .collect::<Result<_, _>>()?;
+          if channels.len() != 3 {
+            return Err(err("expected `R G B`"))
+          }
+          Rgba([channels[0], channels[1], channels[2], 255])
+        },
+        PaletteFormat::Hex => {
+          if line.is_empty() {
+            continue
+          }
+          rgba_from_hex(line).ok_or_else(|| err("expected `rrggbb`"))?
+        },
+        PaletteFormat::PaintNet => {
+          if line.is_empty() || line.starts_with(';') {
+            continue
+          }
+          // aarrggbb -> rrggbbaa
+          if line.len() != 8 || !line.is_ascii() {
+            return Err(err("expected `aarrggbb`"))
+          }
+          rgba_from_hex(&format!("{}{}", &line[2..], &line[..2]))
+            .ok_or_else(|| err("expected `aarrggbb`"))?
+        },
+      };
+      colors.push(color);
+    }
+
+    Palette::new(&colors)
+  }
+
+  /// Load a palette file, guessing the format from the extension
+  pub fn from_filename(filename: &str) -> Result<Palette, ResoError> {
+    let format = PaletteFormat::from_filename(filename).ok_or_else(|| ResoError::Palette(
+      format!("unknown palette format for {}; expected .gpl, .hex, or .txt", filename)
+    ))?;
+    Palette::parse(&std::fs::read_to_string(filename)?, format)
+  }
+}
+
+#[cfg(test)]
+mod palette_tests {
+  use super::*;
+
+  #[test]
+  fn test_book_palettes_are_reso_12() {
+    for filename in [
+      "./book/src/palettes/reso-12.gpl",
+      "./book/src/palettes/reso-12.hex",
+      "./book/src/palettes/reso-12.txt",
+    ] {
+      assert_eq!(Palette::from_filename(filename).unwrap(), Palette::default(), "{}", filename);
+    }
+  }
+
+  #[test]
+  fn test_default_matches_resel_from() {
+    let palette = Palette::default();
+    for (resel, rgba) in PALETTE_RESEL.iter().zip(PALETTE_RGBA) {
+      if *resel != Resel::Empty {
+        assert_eq!(palette.to_resel(rgba), *resel);
+        assert_eq!(palette.to_rgba(*resel), Some(rgba));
+      }
+    }
+    assert_eq!(palette.to_rgba(Resel::Empty), None);
+    assert_eq!(palette.to_resel(Rgba([1, 2, 3, 255])), Resel::Empty);
+  }
+
+  #[test]
+  fn test_recoloured_half_adder() {
+    // Redraw the half adder with every colour inverted, then check it
+    // compiles and simulates the same, and is drawn back in the new colours
+    use crate::reselboard::{ReselBoard};
+    use crate::resocircuit::{ResoCircuit};
+    use image::{DynamicImage};
+
+    let invert = |rgba: Rgba<u8>| Rgba([255 - rgba[0], 255 - rgba[1], 255 - rgba[2], rgba[3]]);
+    let palette = Palette::new(&Palette::default().colors.map(invert)).unwrap();
+
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+    let mut image = rc.get_image().unwrap().to_rgba8();
+    for pixel in image.pixels_mut() {
+      *pixel = invert(*pixel);
+    }
+    let rb = ReselBoard::from(DynamicImage::ImageRgba8(image));
+    assert_ne!(rb.board, rc.rb.board);
+    let mut rc_inverted = ResoCircuit::from(rb.with_palette(palette));
+    assert_eq!(rc_inverted.rb.board, rc.rb.board);
+
+    for _ in 0..4 {
+      rc.iterate();
+      rc.update_pixels();
+      rc_inverted.iterate();
+      rc_inverted.update_pixels();
+      for (pixel, inverted) in rc.get_image().unwrap().to_rgba8().pixels()
+        .zip(rc_inverted.get_image().unwrap().to_rgba8().pixels())
+      {
+        assert_eq!(invert(*pixel), *inverted);
+      }
+    }
+  }
+
+  #[test]
+  fn test_match_color() {
+    let palette = Palette::default();
+    let (exact, tolerant) = (
+      ColorMatching::default(),
+      ColorMatching { tolerance: 16, alpha: AlphaMatching::Threshold(128) },
+    );
+
+    // Bright teal (XOR) is (0, 255, 128)
+    for (rgba, matching, expected) in [
+      (Rgba([0, 255, 128, 255]), exact,    ColorMatch::Exact(Resel::XOR)),
+      (Rgba([0, 255, 128, 255]), tolerant, ColorMatch::Exact(Resel::XOR)),
+      (Rgba([3, 250, 130, 255]), exact,    ColorMatch::Unmatched),
+      (Rgba([3, 250, 130, 255]), tolerant, ColorMatch::Snapped(Resel::XOR)),
+      (Rgba([0, 255, 128, 200]), exact,    ColorMatch::Unmatched),
+      (Rgba([0, 255, 128, 200]), tolerant, ColorMatch::Snapped(Resel::XOR)),
+      (Rgba([0, 255, 128, 100]), tolerant, ColorMatch::Unmatched),
+      (Rgba([40, 200, 100, 255]), tolerant, ColorMatch::Unmatched),
+      (Rgba([255, 255, 255, 255]), tolerant, ColorMatch::Unmatched),
+      (Rgba([0, 200, 200, 255]), tolerant, ColorMatch::Exact(Resel::Bus(144))),
+    ] {
+      assert_eq!(palette.match_color(rgba, &matching), expected, "{:?}", rgba);
+    }
+
+    // Exact alpha with a tolerance still only snaps to the same alpha
+    let rgb_only = ColorMatching { tolerance: 16, alpha: AlphaMatching::Exact };
+    assert_eq!(palette.match_color(Rgba([3, 250, 130, 255]), &rgb_only), ColorMatch::Snapped(Resel::XOR));
+    assert_eq!(palette.match_color(Rgba([3, 250, 130, 254]), &rgb_only), ColorMatch::Unmatched);
+
+    // A huge tolerance snaps everything, rather than overflowing
+    let anything = ColorMatching { tolerance: u32::MAX, alpha: AlphaMatching::Exact };
+    assert!(matches!(palette.match_color(Rgba([255, 255, 255, 255]), &anything), ColorMatch::Snapped(_)));
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let ten_hex = "000001\n000002\n000003\n000004\n000005\n000006\n000007\n000008\n000009\n00000a\n";
+    assert!(Palette::parse(ten_hex, PaletteFormat::Hex).is_ok());
+
+    for (text, format, bad_line) in [
+      ("JASC-PAL\n", PaletteFormat::Gpl, 1),
+      ("GIMP Palette\n1 2\n", PaletteFormat::Gpl, 2),
+      ("000001\nnope\n", PaletteFormat::Hex, 2),
+      (";comment\nff0000\n", PaletteFormat::PaintNet, 2),
+    ] {
+      match Palette::parse(text, format) {
+        Err(ResoError::PaletteFile { line, .. }) => assert_eq!(line, bad_line),
+        other => panic!("Expected PaletteFile error, got {:?}", other),
+      }
+    }
+
+    assert!(matches!(Palette::parse("000001\n", PaletteFormat::Hex), Err(ResoError::Palette(_))));
+    let duplicate = ten_hex.replace("000002", "000001");
+    assert!(matches!(Palette::parse(&duplicate, PaletteFormat::Hex), Err(ResoError::Palette(_))));
+
+    // Bus, bus input, bus output, GPIO and subcircuit colours are taken
+    let mut colors = Palette::default().colors;
+    for reserved in [
+      Rgba([0, 200, 200, 255]), Rgba([1, 128, 128, 255]),
+      Rgba([0x40, 0x80, 0x80, 255]), Rgba([0x80, 0xff, 0xff, 255]),
+      Rgba([0, 0x80, 0, 255]), Rgba([0, 0xff, 0, 255]),
+      Rgba([0x80, 0x80, 0, 255]),
+    ] {
+      colors[3] = reserved;
+      assert!(matches!(Palette::new(&colors), Err(ResoError::Palette(_))), "{:?}", reserved);
+    }
+    colors[3] = Rgba([0, 127, 127, 255]);
+    assert!(Palette::new(&colors).is_ok());
+  }
+}
+
+// eof
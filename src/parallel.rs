@@ -0,0 +1,307 @@
+//! parallel.rs: Simulate 64 independent copies of a circuit at once.
+//!
+//! A ParallelCircuit is a ResoCircuit where every wire holds a u64 instead
+//! of a bool. Each bit is a "lane", and each lane is its own simulation: bit
+//! `k` of every wire is the state of the circuit in lane `k`. Because AND,
+//! XOR and OR work bitwise, one pass over the IncidenceMap simulates all 64
+//! lanes.
+//!
+//! This is for exhaustive testing, e.g. driving the input ports of an adder
+//! with 64 different input assignments and reading all 64 results at once.
+//!
+//! Lanes never interact. Lane `k` behaves exactly like a ResoCircuit with
+//! the same wire state and held inputs as bit `k`.
+//!
+//! Buses aren't simulated: they never affect wires, so lanes only carry
+//! wire state.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::parallel::{ParallelCircuit, counting_lanes};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+//!
+//! // Lane k gets a = bit 0 of k, b = bit 1 of k
+//! let mut pc = ParallelCircuit::from(&rc);
+//! pc.set_input("a", counting_lanes(0, 0)).unwrap();
+//! pc.set_input("b", counting_lanes(1, 0)).unwrap();
+//! pc.iterate();
+//!
+//! for lane in 0..4 {
+//!   let (a, b) = (lane & 1 == 1, lane & 2 == 2);
+//!   assert_eq!(pc.get_output_lane("sum", lane).unwrap(), a ^ b);
+//!   assert_eq!(pc.get_output_lane("carry", lane).unwrap(), a && b);
+//! }
+//! ```
+
+use crate::error::{ResoError};
+use crate::resel::{Resel};
+use crate::incidencemap::{IncidenceMap};
+use crate::ports::{PortDirection};
+use crate::resocircuit::{ResoCircuit};
+
+/// How many lanes a ParallelCircuit simulates
+pub const LANES: usize = 64;
+
+/// Every lane on, or every lane off
+pub fn broadcast(value: bool) -> u64 {
+  if value { u64::MAX } else { 0 }
+}
+
+/// The bit for one lane, or LaneOutOfRange past the last lane
+fn lane_bit(lane: usize) -> Result<u64, ResoError> {
+  if lane < LANES { Ok(1 << lane) } else { Err(ResoError::LaneOutOfRange(lane)) }
+}
+
+/// Lane `k` holds bit `bit` of `offset + k`.
+///
+/// Setting input `i` to `counting_lanes(i, offset)` for every input makes
+/// the lanes count through the assignments `offset..offset+64`, so
+/// `2^n / 64` batches cover every assignment of `n` inputs.
+/// Bits past the top of a usize are 0, and `offset + k` wraps around.
+pub fn counting_lanes(bit: usize, offset: usize) -> u64 {
+  (0..LANES).fold(0, |lanes, lane| {
+    let value = offset.wrapping_add(lane);
+    let set = bit < usize::BITS as usize && (value >> bit) & 1 == 1;
+    lanes | (set as u64) << lane
+  })
+}
+
+/// A named port of a ParallelCircuit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParallelPort {
+  pub direction: PortDirection,
+  pub name: String,
+  pub wire: usize,
+  /// For inputs, the lanes the wire is held at (if it's been set)
+  pub held: Option<u64>,
+}
+
+/// 64 lanes of a ResoCircuit. See the module docs.
+#[derive(Debug, Clone)]
+pub struct ParallelCircuit {
+  pub im: IncidenceMap,
+  /// `logic_is_and[li]` is true for AND, false for XOR
+  pub logic_is_and: Vec<bool>,
+
+  pub wire_state: Vec<u64>,
+  pub ports: Vec<ParallelPort>,
+
+  // Internal state used during .iterate()
+  // (pre-allocated, like in ResoCircuit!)
+  input_state:  Vec<u64>,
+  input_and:    Vec<u64>,
+  input_xor:    Vec<u64>,
+  logic_state:  Vec<u64>,
+  output_state: Vec<u64>,
+}
+
+impl From<&ResoCircuit> for ParallelCircuit {
+  /// Every lane starts with the wire state and held inputs of `rc`
+  fn from(rc: &ResoCircuit) -> ParallelCircuit {
+    let logic_is_and = rc.rm.logic_regions.iter().map(
+      |ri| rc.rm.region_to_resel[*ri] == Resel::AND
+    ).collect();
+
+    let ports = rc.ports.iter().map(|port| ParallelPort {
+      direction: port.direction,
+      name: port.name.clone(),
+      wire: port.wire,
+      held: port.held.map(broadcast),
+    }).collect();
+
+    let num_inputs = rc.im.input_inc_wires.len();
+    ParallelCircuit {
+      im: rc.im.clone(),
+      logic_is_and,
+      wire_state: rc.wire_state.iter().map(|&on| broadcast(on)).collect(),
+      ports,
+      input_state:  vec![0; num_inputs],
+      input_and:    vec![0; num_inputs],
+      input_xor:    vec![0; num_inputs],
+      logic_state:  vec![0; rc.im.logic_inc_inputs.len()],
+      output_state: vec![0; rc.im.output_inc_inputs.len()],
+    }
+  }
+}
+
+impl ParallelCircuit {
+  /// Simulate one iteration in every lane.
+  /// Follows ResoCircuit::iterate exactly, but bitwise.
+  pub fn iterate(&mut self) {
+    // Collect from incident wires: OR, AND and XOR over each input's wires.
+    // (ResoCircuit keeps each input's wire vector; we only need these folds.)
+    for (ii, inc_wires) in self.im.input_inc_wires.iter().enumerate() {
+      let (mut or, mut and, mut xor) = (0, u64::MAX, 0);
+      for wi in inc_wires {
+        or  |= self.wire_state[*wi];
+        and &= self.wire_state[*wi];
+        xor ^= self.wire_state[*wi];
+      }
+      self.input_state[ii] = or;
+      self.input_and[ii]   = and;
+      self.input_xor[ii]   = xor;
+    }
+
+    // Logic state: OR over incident inputs, of AND/XOR over their wires
+    for (li, inc_inputs) in self.im.logic_inc_inputs.iter().enumerate() {
+      let folded = if self.logic_is_and[li] { &self.input_and } else { &self.input_xor };
+      self.logic_state[li] = inc_inputs.iter().fold(0, |acc, ii| acc | folded[*ii]);
+    }
+
+    // Output state: OR over incident inputs and logics
+    for (oi, inc_inputs) in self.im.output_inc_inputs.iter().enumerate() {
+      self.output_state[oi] = inc_inputs.iter()
+        .fold(0, |acc, ii| acc | self.input_state[*ii]);
+    }
+    for (oi, inc_logics) in self.im.output_inc_logics.iter().enumerate() {
+      for li in inc_logics {
+        self.output_state[oi] |= self.logic_state[*li];
+      }
+    }
+
+    // Wire state: OR over incident outputs
+    for (wi, inc_outputs) in self.im.wire_inc_outputs.iter().enumerate() {
+      self.wire_state[wi] = inc_outputs.iter()
+        .fold(0, |acc, oi| acc | self.output_state[*oi]);
+    }
+
+    // Input ports hold their wires
+    for port in &self.ports {
+      if let Some(lanes) = port.held {
+        self.wire_state[port.wire] = lanes
+      }
+    }
+  }
+
+  fn port_index(&self, name: &str) -> Result<usize, ResoError> {
+    self.ports.iter().position(|port| port.name == name)
+      .ok_or_else(|| ResoError::UnknownPort(name.to_string()))
+  }
+
+  /// Drive an input port with a different value in each lane.
+  /// Bit `k` of `lanes` is the value in lane `k`.
+  pub fn set_input(&mut self, name: &str, lanes: u64) -> Result<(), ResoError> {
+    let pi = self.port_index(name)?;
+    let port = &mut self.ports[pi];
+    if port.direction != PortDirection::Input {
+      return Err(ResoError::WrongPortDirection(name.to_string()))
+    }
+    port.held = Some(lanes);
+    self.wire_state[port.wire] = lanes;
+    Ok(())
+  }
+
+  /// Drive an input port in just one lane
+  pub fn set_input_lane(&mut self, name: &str, lane: usize, value: bool) -> Result<(), ResoError> {
+    let bit = lane_bit(lane)?;
+    let port = &self.ports[self.port_index(name)?];
+    let lanes = port.held.unwrap_or(self.wire_state[port.wire]);
+    self.set_input(name, if value { lanes | bit } else { lanes & !bit })
+  }
+
+  /// Read a port in every lane. Bit `k` is the value in lane `k`.
+  pub fn get_output(&self, name: &str) -> Result<u64, ResoError> {
+    let port = &self.ports[self.port_index(name)?];
+    Ok(self.wire_state[port.wire])
+  }
+
+  /// Read a port in just one lane
+  pub fn get_output_lane(&self, name: &str, lane: usize) -> Result<bool, ResoError> {
+    let bit = lane_bit(lane)?;
+    Ok(self.get_output(name)? & bit != 0)
+  }
+
+  /// The wire state of one lane, as a ResoCircuit would have it.
+  /// Panics if `lane` isn't below LANES.
+  pub fn lane_wire_state(&self, lane: usize) -> Vec<bool> {
+    assert!(lane < LANES, "lane {} is out of range", lane);
+    self.wire_state.iter().map(|lanes| lanes & (1 << lane) != 0).collect()
+  }
+}
+
+#[cfg(test)]
+mod parallel_tests {
+  use super::*;
+
+  #[test]
+  fn test_counting_lanes() {
+    assert_eq!(counting_lanes(0, 0), 0xAAAA_AAAA_AAAA_AAAA);
+    assert_eq!(counting_lanes(1, 0), 0xCCCC_CCCC_CCCC_CCCC);
+    assert_eq!(counting_lanes(6, 0), 0);
+    assert_eq!(counting_lanes(6, 64), u64::MAX);
+    assert_eq!(counting_lanes(64, usize::MAX), 0);
+    assert_eq!(counting_lanes(1000, 0), 0);
+    // usize::MAX, then 0, 1, 2, ...
+    assert_eq!(counting_lanes(0, usize::MAX), 0x5555_5555_5555_5555);
+  }
+
+  #[test]
+  fn test_broadcast_matches_scalar_reso_logo() {
+    // With no inputs set, every lane is the same as the scalar simulation
+    let mut rc = ResoCircuit::from_filename("./reso_logo.png").unwrap();
+    let mut pc = ParallelCircuit::from(&rc);
+    for _ in 0..16 {
+      rc.iterate();
+      pc.iterate();
+      for lane in [0, 17, 63] {
+        assert_eq!(pc.lane_wire_state(lane), rc.wire_state);
+      }
+    }
+  }
+
+  #[test]
+  fn test_lanes_match_scalar_halfadder() {
+    // Different inputs in different lanes, checked against the scalar engine
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+
+    let mut pc = ParallelCircuit::from(&rc);
+    pc.set_input("a", counting_lanes(0, 0)).unwrap();
+    pc.set_input("b", counting_lanes(1, 0)).unwrap();
+
+    let mut scalars: Vec<ResoCircuit> = (0..LANES).map(|lane| {
+      let mut rc = rc.clone();
+      rc.set_input("a", lane & 1 == 1).unwrap();
+      rc.set_input("b", lane & 2 == 2).unwrap();
+      rc
+    }).collect();
+
+    for _ in 0..3 {
+      pc.iterate();
+      for (lane, rc) in scalars.iter_mut().enumerate() {
+        rc.iterate();
+        assert_eq!(pc.lane_wire_state(lane), rc.wire_state);
+      }
+    }
+
+    let (a, b) = (counting_lanes(0, 0), counting_lanes(1, 0));
+    assert_eq!(pc.get_output("sum").unwrap(), a ^ b);
+    assert_eq!(pc.get_output("carry").unwrap(), a & b);
+  }
+
+  #[test]
+  fn test_set_input_lane() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    let mut pc = ParallelCircuit::from(&rc);
+
+    pc.set_input("a", 0).unwrap();
+    pc.set_input("b", u64::MAX).unwrap();
+    pc.set_input_lane("a", 5, true).unwrap();
+    pc.iterate();
+    assert_eq!(pc.get_output("carry").unwrap(), 1 << 5);
+    assert!(pc.get_output_lane("carry", 5).unwrap());
+    assert!(!pc.get_output_lane("carry", 4).unwrap());
+
+    assert!(matches!(pc.set_input("sum", 0), Err(ResoError::WrongPortDirection(_))));
+    assert!(matches!(pc.get_output("nope"), Err(ResoError::UnknownPort(_))));
+    assert!(matches!(pc.set_input_lane("a", 64, true), Err(ResoError::LaneOutOfRange(64))));
+    assert!(matches!(pc.get_output_lane("carry", 1000), Err(ResoError::LaneOutOfRange(1000))));
+  }
+}
+
+// eof
@@ -0,0 +1,170 @@
+//! ports.rs: Named external inputs and outputs on wire regions.
+//!
+//! A port names a wire region so a circuit can be driven and read from
+//! outside, like a function. Ports are declared in a sidecar "ports file",
+//! one port per line, naming any one pixel of the wire region:
+//!
+//! ```text
+//! # direction  name   x,y
+//! input        a      0,2
+//! input        b      0,3
+//! output       sum    5,1
+//! output       carry  5,4
+//! ```
+//!
+//! Blank lines and anything after a `#` are ignored.
+//!
+//! - An **input** port holds its wire at whatever value it was last set to,
+//!   overriding the incident outputs on every iteration. Until it is set, it
+//!   behaves like any other wire.
+//! - An **output** port just reads the state of its wire.
+//!
+//! GPIO wires get input ports named `gpio0`, `gpio1`, ... automatically.
+//! See gpio.rs.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+//!
+//! rc.set_input("a", true).unwrap();
+//! rc.set_input("b", true).unwrap();
+//! rc.iterate();
+//! assert_eq!(rc.get_output("sum").unwrap(), false);
+//! assert_eq!(rc.get_output("carry").unwrap(), true);
+//! ```
+
+use crate::error::{ResoError};
+
+/// Whether a port drives its wire or reads it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortDirection {
+  Input,
+  Output,
+}
+
+/// A port as written in a ports file, before it's attached to a circuit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSpec {
+  pub direction: PortDirection,
+  pub name: String,
+  pub x: usize,
+  pub y: usize,
+}
+
+/// Formats as a line of a ports file, e.g. `input a 0,2`
+impl std::fmt::Display for PortSpec {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let direction = match self.direction {
+      PortDirection::Input  => "input",
+      PortDirection::Output => "output",
+    };
+    write!(f, "{} {} {},{}", direction, self.name, self.x, self.y)
+  }
+}
+
+/// A port attached to a circuit.
+///
+/// `wire` is the dense wire index, i.e. `rm.wire_regions[wire]` is the
+/// region index and `rc.wire_state[wire]` is the state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+  pub direction: PortDirection,
+  pub name: String,
+  pub wire: usize,
+  /// For inputs, the value the wire is held at (if it's been set)
+  pub held: Option<bool>,
+}
+
+/// Parse the text of a ports file.
+/// Line numbers in errors count from 1.
+pub fn parse_ports(text: &str) -> Result<Vec<PortSpec>, ResoError> {
+  let mut specs = vec![];
+
+  for (line_idx, line) in text.lines().enumerate() {
+    let line_no = line_idx + 1;
+    let err = |message: &str| ResoError::PortFile {
+      line: line_no, message: message.to_string()
+    };
+
+    let line = line.split('#').next().unwrap_or("");
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.is_empty() {
+      continue
+    }
+    if fields.len() != 3 {
+      return Err(err("expected `direction name x,y`"))
+    }
+
+    let direction = match fields[0] {
+      "input"  | "in"  => PortDirection::Input,
+      "output" | "out" => PortDirection::Output,
+      _ => return Err(err("direction should be `input` or `output`")),
+    };
+
+    let (x, y) = parse_xy(fields[2]).ok_or_else(|| err("coordinate should be `x,y`"))?;
+
+    if specs.iter().any(|spec: &PortSpec| spec.name == fields[1]) {
+      return Err(err("port name used twice"))
+    }
+
+    specs.push(PortSpec { direction, name: fields[1].to_string(), x, y });
+  }
+
+  Ok(specs)
+}
+
+/// Read a ports file
+pub fn load_ports_file(filename: &str) -> Result<Vec<PortSpec>, ResoError> {
+  parse_ports(&std::fs::read_to_string(filename)?)
+}
+
+/// Parse `x,y` into a coordinate
+pub fn parse_xy(xy: &str) -> Option<(usize, usize)> {
+  let (x, y) = xy.split_once(',')?;
+  Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod ports_tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_ports() {
+    let specs = parse_ports(
+      "# a comment\n\ninput a 0,2 # trailing comment\nout sum 5,1\n"
+    ).unwrap();
+    assert_eq!(specs, vec![
+      PortSpec { direction: PortDirection::Input,  name: "a".to_string(),   x: 0, y: 2 },
+      PortSpec { direction: PortDirection::Output, name: "sum".to_string(), x: 5, y: 1 },
+    ]);
+  }
+
+  #[test]
+  fn test_port_spec_roundtrip() {
+    let text = "input a 0,2\noutput sum 5,1\n";
+    let specs = parse_ports(text).unwrap();
+    let lines: Vec<String> = specs.iter().map(|spec| format!("{}\n", spec)).collect();
+    assert_eq!(lines.concat(), text);
+  }
+
+  #[test]
+  fn test_parse_ports_errors() {
+    for (text, bad_line) in [
+      ("input a", 1),
+      ("input a 0,2\nsideways b 1,1", 2),
+      ("input a 0;2", 1),
+      ("input a 0,2\n\noutput a 1,1", 3),
+    ] {
+      match parse_ports(text) {
+        Err(ResoError::PortFile { line, .. }) => assert_eq!(line, bad_line),
+        other => panic!("Expected PortFile error, got {:?}", other),
+      }
+    }
+  }
+}
+
+// eof
@@ -1,8 +1,10 @@
 //! regionmap.rs -- Identify the contiguous regions in a ReselBoard Vec<Vec<Resel>.
 //! 
 //! Exactly what it says on the tin, with two complications:
-//! 1. Resels have 4-neighborhood connectivity, except wires, which have 8.
-//! 2. On and off wires of the same color (e.g. orange) are the same.
+//! 1. Resels have 4-neighborhood connectivity, except wires (and buses),
+//!    which have 8.
+//! 2. On and off wires of the same color (e.g. orange) are the same, and so
+//!    are buses with different values.
 //! 
 //! Key to a RegionMap are region indices. Region 0 corresponds to any 'empty'
 //! Resel. All others start counting from 1.
@@ -10,21 +12,89 @@
 //! For performance, we also maintain implicit list of "dense" class indices
 //! for input resels, output resels, wire resels, and logic resels.
 //! 
+//! There are two labelers, which give the same regions, numbered the same
+//! way: in order of each region's first resel, scanning x then y.
+//! 
+//! - `RegionMap::union_find` (the default, used by `From`): Two-pass
+//!   connected-component labeling over a flat union-find array. The first
+//!   pass unions each resel with its "forward" neighbors, the second pass
+//!   resolves each resel's root to a region. No per-pixel allocations, and
+//!   `region_to_xys` lists each region's coordinates in scan order.
+//! - `RegionMap::flood_fill`: The original stack-based flood fill. Kept to
+//!   check and benchmark against (`cargo bench`); `region_to_xys` lists
+//!   coordinates in the order the fill reached them.
+//! 
+//! A wire region drawn with both on and off resels (e.g. `oOOOO`) is
+//! "mixed". Its state is set by the ReselBoard's MixedWirePolicy (by default
+//! its first resel's, as it always was), and it's listed in `mixed_wires`.
+//! Under MixedWirePolicy::Error, `RegionMap::try_from_board` fails instead;
+//! `From` can't, so it keeps the first resel's state.
+//! A bus region drawn with different values always takes its first resel's.
+//! 
 //! TODO:
 //! - Example code in docs
-//! - Region with mixed on/off wires should be classed as "on".
+//! - Make MixedWirePolicy::AnyOn the default? Mixed regions "should" be on.
 //! - add `width`, `height` to `RegionMap`? Or even a whole `ReselBoard`?
 //! - Ensure sorted ordering on all outputs?
 //! 
 //! - region mapper should probably return something like Result<Option<T>, E>
 //! - Consider: This implements connected component labeling. Publish a generic version?
 
+use crate::error::{ResoError};
 use crate::resel::{Resel};
 use crate::reselboard::{
   ReselBoard,
+  Topology,
+  MixedWirePolicy,
+  delta_to_neighbor,
   get_neighbors
 };
 
+/// A wire region drawn with both on and off resels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixedWire {
+  pub region: usize,
+  /// The region's first resel
+  pub x: usize,
+  pub y: usize,
+  /// How many resels are on, and how many off
+  pub on: usize,
+  pub off: usize,
+}
+
+/// Find the wire regions with both on and off resels on `board`
+pub fn find_mixed_wires(
+  board: &[Vec<Resel>], region_to_xys: &[Vec<(usize, usize)>], wire_regions: &[usize]
+) -> Vec<MixedWire> {
+  let mut mixed_wires = vec![];
+  for ri in wire_regions {
+    let xys = &region_to_xys[*ri];
+    let on = xys.iter().filter(|(x, y)| board[*x][*y].wire_state() == Some(true)).count();
+    if on != 0 && on != xys.len() {
+      let (x, y) = xys[0];
+      mixed_wires.push(MixedWire { region: *ri, x, y, on, off: xys.len() - on });
+    }
+  }
+  mixed_wires
+}
+
+/// Set the resel of each mixed wire region by `policy`
+fn resolve_mixed_wires(
+  policy: MixedWirePolicy, mixed_wires: &[MixedWire], region_to_resel: &mut [Resel]
+) {
+  for mixed in mixed_wires {
+    let on = match policy {
+      MixedWirePolicy::AnyOn => true,
+      MixedWirePolicy::Majority => mixed.on >= mixed.off,
+      // Error keeps the first resel too: `RegionMap::try_from_board` is
+      // the one that fails
+      MixedWirePolicy::FirstPixel | MixedWirePolicy::Error => continue,
+    };
+    let resel = &mut region_to_resel[mixed.region];
+    *resel = resel.with_wire_state(on).unwrap();
+  }
+}
+
 /// RegionMap -- Mapping between contiguous regions and their coordinates.
 /// 
 /// A region is just a contiguous blob of resels. Regions can be one resel
@@ -41,6 +111,8 @@ use crate::reselboard::{
 ///   - `input_regions` for any input region
 ///   - `output_regions` for any output region
 ///   - `logic_regions` for And and Xor regions
+///   - `bus_regions`, `bus_input_regions` and `bus_output_regions` for buses
+///   - `subcircuit_regions` for subcircuit placeholders
 /// - The `reverse_dense` index gives you the dense index value for any `region_index`.
 ///   - This is hard to wrap your mind around; look at the tests for examples.
 #[derive(Debug, Clone)]
@@ -48,6 +120,7 @@ pub struct RegionMap {
   pub xy_to_region: Vec<Vec<usize>>,            // [x][y] -> i
   pub width: usize,
   pub height: usize,
+  pub topology: Topology,                       // same as the ReselBoard's
 
   pub region_to_xys: Vec<Vec<(usize, usize)>>,  // [i] -> [(x,y),...]
   pub region_to_resel:  Vec<Resel>,             // [Resel::Empty, Resel::And, ...]
@@ -59,6 +132,10 @@ pub struct RegionMap {
   pub input_regions:    Vec<usize>,
   pub logic_regions:    Vec<usize>,
   pub output_regions:   Vec<usize>,
+  pub bus_regions:        Vec<usize>,
+  pub bus_input_regions:  Vec<usize>,
+  pub bus_output_regions: Vec<usize>,
+  pub subcircuit_regions: Vec<usize>,
 
   /* reverse dense index
   e.g. given region_index ri, what is the dense index?
@@ -67,9 +144,10 @@ pub struct RegionMap {
   O(n): wire_regions.iter().position(|&wire_ri| wire_ri == ri)
   O(1): reverse_dense[ri]
   */
-  pub reverse_dense: Vec<usize>
+  pub reverse_dense: Vec<usize>,
 
-  
+  /// Wire regions drawn with both on and off resels. See MixedWirePolicy.
+  pub mixed_wires: Vec<MixedWire>,
 }
 
 impl RegionMap {
@@ -80,13 +158,12 @@ impl RegionMap {
     for (x,y) in &self.region_to_xys[region] {
       for (nx, ny) in get_neighbors(
         vec![(1,0),(0,1),(-1,0),(0,-1)], // adjacencies are only orthogonal, wire or not
-        *x, *y, self.width, self.height
+        *x, *y, self.width, self.height, self.topology
       ) {
         let neighbor_region = self.xy_to_region[nx][ny];
-        if (
-          region != neighbor_region
+        if region != neighbor_region
           && !adjacent_regions.contains(&neighbor_region)
-        ) {
+        {
           adjacent_regions.push(neighbor_region)
         }
       }
@@ -95,6 +172,250 @@ impl RegionMap {
     adjacent_regions.sort();
     adjacent_regions
   }
+
+  /// The first resel of a region (scanning x, then y), or None for regions
+  /// inlined from a subcircuit, which aren't on the board
+  pub fn first_xy(&self, region: usize) -> Option<(usize, usize)> {
+    self.region_to_xys[region].first().copied()
+  }
+
+  /// Add a region of class `resel` with no resels on the board, e.g. one
+  /// inlined from a subcircuit. Returns its region index.
+  pub fn add_region(&mut self, resel: Resel) -> usize {
+    let region = self.region_to_resel.len();
+    self.region_to_xys.push(vec![]);
+    self.region_to_resel.push(resel);
+    let dense = if resel.is_wire() {
+      &mut self.wire_regions
+    } else if resel.is_input() {
+      &mut self.input_regions
+    } else if resel.is_logic() {
+      &mut self.logic_regions
+    } else if resel.is_output() {
+      &mut self.output_regions
+    } else if resel.is_bus() {
+      &mut self.bus_regions
+    } else if resel.is_bus_input() {
+      &mut self.bus_input_regions
+    } else if resel.is_bus_output() {
+      &mut self.bus_output_regions
+    } else {
+      &mut self.subcircuit_regions
+    };
+    self.reverse_dense.push(dense.len());
+    dense.push(region);
+    region
+  }
+
+  /// The smallest and largest (x, y) of a region's resels, as (x0, y0, x1, y1).
+  /// A region wrapping around the board spans it.
+  pub fn bounding_box(&self, region: usize) -> (usize, usize, usize, usize) {
+    self.region_to_xys[region].iter().fold(
+      (usize::MAX, usize::MAX, 0, 0),
+      |(x0, y0, x1, y1), (x, y)| (x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y))
+    )
+  }
+
+  /// Label regions like `RegionMap::from`, but fail if there are mixed
+  /// on/off wire regions and the board's policy is MixedWirePolicy::Error
+  pub fn try_from_board(rb: &ReselBoard) -> Result<RegionMap, ResoError> {
+    let rm = RegionMap::from(rb);
+    match rm.mixed_wires.first() {
+      Some(mixed) if rb.mixed_wire_policy == MixedWirePolicy::Error => Err(ResoError::MixedWire {
+        x: mixed.x, y: mixed.y, on: mixed.on, off: mixed.off
+      }),
+      _ => Ok(rm),
+    }
+  }
+
+  /// Label regions with the original stack-based flood fill
+  pub fn flood_fill(rb: &ReselBoard) -> RegionMap {
+    region_map_from_reselboard(rb)
+  }
+
+  /// Label regions with two-pass union-find connected-component labeling
+  pub fn union_find(rb: &ReselBoard) -> RegionMap {
+    region_map_union_find(rb)
+  }
+}
+
+/// Half of each neighborhood: every pair of neighbors (p, q) has either q
+/// as a forward neighbor of p, or p as a forward neighbor of q.
+/// The backward neighbors are the other half, i.e. the resels scanned earlier.
+const FORWARD_DELTAS_4: [(isize, isize); 2] = [(1,0), (0,1)];
+const FORWARD_DELTAS_8: [(isize, isize); 4] = [(1,0), (0,1), (1,1), (1,-1)];
+
+fn forward_deltas(resel: Resel) -> &'static [(isize, isize)] {
+  if resel.is_wire() || resel.is_bus() { &FORWARD_DELTAS_8 } else { &FORWARD_DELTAS_4 }
+}
+
+/// Find the root of label `i`, halving the path as we go
+fn find_root(parent: &mut [u32], mut i: u32) -> u32 {
+  while parent[i as usize] != i {
+    parent[i as usize] = parent[parent[i as usize] as usize];
+    i = parent[i as usize];
+  }
+  i
+}
+
+/// Union the sets of labels `a` and `b`. The smaller label becomes the
+/// root, so a root is always the label of its region's first resel.
+fn union(parent: &mut [u32], a: u32, b: u32) {
+  let (root_a, root_b) = (find_root(parent, a), find_root(parent, b));
+  if root_a < root_b {
+    parent[root_b as usize] = root_a
+  } else if root_b < root_a {
+    parent[root_a as usize] = root_b
+  }
+}
+
+/// Two-pass union-find connected-component labeling.
+/// Gives the same regions and region indices as `region_map_from_reselboard`.
+fn region_map_union_find(
+  rb: &ReselBoard,
+) -> RegionMap {
+  let (width, height) = (rb.width, rb.height);
+
+  // Provisional label of each resel, flat and column-major like rb.board
+  // (index = x*height + y). Label 0 is empty; `parent` is over labels.
+  // u32 labels keep these small, so they stay in cache.
+  let mut labels: Vec<u32> = vec![0; width*height];
+  let mut parent: Vec<u32> = vec![0];
+
+  // First pass: Each resel takes the label of its same() backward
+  // neighbors (unioning them if they differ), or a new label.
+  for x in 0..width { for y in 0..height {
+    let resel = rb.board[x][y];
+    if resel == Resel::Empty {
+      continue
+    }
+    let mut label = 0;
+    for (dx, dy) in forward_deltas(resel) {
+      // Backward neighbor, without wrapping. The wrap is handled below.
+      let Some((nx, ny)) = delta_to_neighbor(x, y, -dx, -dy, width, height, false)
+        else { continue };
+      if rb.board[nx][ny].same(resel) {
+        let neighbor_label = labels[nx*height + ny];
+        if label == 0 {
+          label = neighbor_label
+        } else {
+          union(&mut parent, label, neighbor_label)
+        }
+      }
+    }
+    if label == 0 {
+      label = parent.len() as u32;
+      parent.push(label);
+    }
+    labels[x*height + y] = label;
+  }}
+
+  // Wrapping: Neighbors across the edges of the torus (or cylinder) are
+  // forward neighbors of a resel in the last column, or the first or last row.
+  // (With a bounded topology, these are only neighbors already unioned.)
+  let topology = rb.topology;
+  for x in 0..width { for y in 0..height {
+    if x != width - 1 && y != 0 && y != height - 1 {
+      continue
+    }
+    let resel = rb.board[x][y];
+    if resel == Resel::Empty {
+      continue
+    }
+    for (dx, dy) in forward_deltas(resel) {
+      if let Some((nx, ny)) = topology.neighbor(x, y, *dx, *dy, width, height) {
+        if rb.board[nx][ny].same(resel) {
+          union(&mut parent, labels[x*height + y], labels[nx*height + ny]);
+        }
+      }
+    }
+  }}
+
+  // Every label's parent is a smaller label, so resolving in order flattens
+  for label in 1..parent.len() {
+    parent[label] = parent[parent[label] as usize];
+  }
+
+  // Region mapping data
+  let mut xy_to_region:  Vec<Vec<usize>> = vec![vec![0; height]; width];
+  let mut region_to_xys: Vec<Vec<(usize, usize)>> = vec![vec![]];
+  let mut region_to_resel: Vec<Resel> = vec![Resel::Empty];
+
+  // Dense class indices
+  let mut wire_regions: Vec<usize> = vec![];
+  let mut input_regions: Vec<usize> = vec![];
+  let mut logic_regions: Vec<usize> = vec![];
+  let mut output_regions: Vec<usize> = vec![];
+  let mut bus_regions: Vec<usize> = vec![];
+  let mut bus_input_regions: Vec<usize> = vec![];
+  let mut bus_output_regions: Vec<usize> = vec![];
+  let mut subcircuit_regions: Vec<usize> = vec![];
+  let mut reverse_dense: Vec<usize> = vec![0];
+
+  // Second pass: Roots come first in scan order, so they get new regions,
+  // and every other label takes the region of its (already seen) root.
+  let mut label_to_region: Vec<usize> = vec![0; parent.len()];
+  for x in 0..width { for y in 0..height {
+    let root = parent[labels[x*height + y] as usize] as usize;
+
+    let region_idx = if root == 0 {
+      0
+    } else if label_to_region[root] == 0 {
+      let resel = rb.board[x][y];
+      let region_idx = region_to_xys.len();
+      label_to_region[root] = region_idx;
+      region_to_xys.push(Vec::new());
+      region_to_resel.push(resel);
+
+      let dense = if resel.is_wire() {
+        &mut wire_regions
+      } else if resel.is_input() {
+        &mut input_regions
+      } else if resel.is_logic() {
+        &mut logic_regions
+      } else if resel.is_output() {
+        &mut output_regions
+      } else if resel.is_bus() {
+        &mut bus_regions
+      } else if resel.is_bus_input() {
+        &mut bus_input_regions
+      } else if resel.is_bus_output() {
+        &mut bus_output_regions
+      } else {
+        &mut subcircuit_regions
+      };
+      reverse_dense.push(dense.len());
+      dense.push(region_idx);
+      region_idx
+    } else {
+      label_to_region[root]
+    };
+
+    xy_to_region[x][y] = region_idx;
+    region_to_xys[region_idx].push((x, y));
+  }}
+
+  let mixed_wires = find_mixed_wires(&rb.board, &region_to_xys, &wire_regions);
+  resolve_mixed_wires(rb.mixed_wire_policy, &mixed_wires, &mut region_to_resel);
+
+  RegionMap {
+    xy_to_region,
+    width,
+    height,
+    topology: rb.topology,
+    region_to_xys,
+    region_to_resel,
+    wire_regions,
+    input_regions,
+    logic_regions,
+    output_regions,
+    bus_regions,
+    bus_input_regions,
+    bus_output_regions,
+    subcircuit_regions,
+    reverse_dense,
+    mixed_wires,
+  }
 }
 
 
@@ -107,11 +428,11 @@ fn region_map_from_reselboard(
   let (width, height) = (rb.width, rb.height);
 
   // visited and region_idx: Memory used only when compiling
-  let mut visited:       Vec<Vec<bool>>  = vec![vec![false; height as usize]; width as usize];
+  let mut visited:       Vec<Vec<bool>>  = vec![vec![false; height]; width];
   let mut region_idx:    usize = 0;
   
   // Region mapping data
-  let mut xy_to_region:  Vec<Vec<usize>> = vec![vec![0; height as usize]; width as usize];
+  let mut xy_to_region:  Vec<Vec<usize>> = vec![vec![0; height]; width];
   let mut region_to_xys: Vec<Vec<(usize, usize)>> = vec![vec![]];
   let mut region_to_resel: Vec<Resel> = vec![Resel::Empty];
 
@@ -120,6 +441,10 @@ fn region_map_from_reselboard(
   let mut input_regions: Vec<usize> = vec![];
   let mut logic_regions: Vec<usize> = vec![];
   let mut output_regions: Vec<usize> = vec![];
+  let mut bus_regions: Vec<usize> = vec![];
+  let mut bus_input_regions: Vec<usize> = vec![];
+  let mut bus_output_regions: Vec<usize> = vec![];
+  let mut subcircuit_regions: Vec<usize> = vec![];
 
   // Reverse dense index ties region_index and the dense indices above
   let mut reverse_dense: Vec<usize> = vec![0];
@@ -154,13 +479,28 @@ fn region_map_from_reselboard(
         reverse_dense.push(output_regions.len());
         output_regions.push(region_idx)
       }
+      if resel.is_bus() {
+        reverse_dense.push(bus_regions.len());
+        bus_regions.push(region_idx)
+      }
+      if resel.is_bus_input() {
+        reverse_dense.push(bus_input_regions.len());
+        bus_input_regions.push(region_idx)
+      }
+      if resel.is_bus_output() {
+        reverse_dense.push(bus_output_regions.len());
+        bus_output_regions.push(region_idx)
+      }
+      if resel.is_subcircuit() {
+        reverse_dense.push(subcircuit_regions.len());
+        subcircuit_regions.push(region_idx)
+      }
 
       // Neighbors only holds unvisited Resels of the .same() color
       let mut neighbors: Vec<(usize, usize)> = vec![(x,y)];
 
-      while !neighbors.is_empty() {
+      while let Some((x, y)) = neighbors.pop() {
         // Record new pixel in our region
-        let (x, y) = neighbors.pop().unwrap();
         xy_to_region[x][y] = region_idx;
         region_to_xys[region_idx].push((x,y));
         visited[x][y] = true;
@@ -179,31 +519,40 @@ fn region_map_from_reselboard(
     } // Start recording a new region!
   }}} // for each x, y, if unvisited,
   // Returns
+  let mixed_wires = find_mixed_wires(&rb.board, &region_to_xys, &wire_regions);
+  resolve_mixed_wires(rb.mixed_wire_policy, &mixed_wires, &mut region_to_resel);
+
   RegionMap {
     xy_to_region,
     width,
     height,
+    topology: rb.topology,
     region_to_xys,
     region_to_resel,
     wire_regions,
     input_regions,
     logic_regions,
     output_regions,
-    reverse_dense
+    bus_regions,
+    bus_input_regions,
+    bus_output_regions,
+    subcircuit_regions,
+    reverse_dense,
+    mixed_wires,
   }
 }
 
 impl From<ReselBoard> for RegionMap {
   /// rm = RegionMap::from(rb.clone());
   fn from (rb: ReselBoard) -> RegionMap {
-    region_map_from_reselboard(&rb)
+    region_map_union_find(&rb)
   }
 }
 
 impl From<&ReselBoard> for RegionMap {
   /// rm = RegionMap::from(rb.clone());
   fn from (rb: &ReselBoard) -> RegionMap {
-    region_map_from_reselboard(rb)
+    region_map_union_find(rb)
   }
 }
 
@@ -242,7 +591,7 @@ mod reselboard_tests {
 
       let (width, height) = (rb.board.len(), rb.board[0].len());
       let n_regions = rm.region_to_xys.len();
-      let mut accounted_xy:       Vec<Vec<bool>>  = vec![vec![false; height as usize]; width as usize];
+      let mut accounted_xy:       Vec<Vec<bool>>  = vec![vec![false; height]; width];
       let mut accounted_region: Vec<bool> = vec![false; n_regions];
 
       assert!(n_regions >= 1);
@@ -268,11 +617,11 @@ mod reselboard_tests {
             assert_eq!(
               region_idx,
               { // Get appropriate dense index
-                if resel_by_region.is_wire()   { &rm.wire_regions } else
-                if resel_by_region.is_input()  { &rm.input_regions } else
-                if resel_by_region.is_logic()  { &rm.logic_regions } else
-                if resel_by_region.is_output() { &rm.output_regions } else
-                { panic!("This should not be possible to reach!") }
+                if resel_by_region.is_wire()        { &rm.wire_regions }
+                else if resel_by_region.is_input()  { &rm.input_regions }
+                else if resel_by_region.is_logic()  { &rm.logic_regions }
+                else if resel_by_region.is_output() { &rm.output_regions }
+                else { panic!("This should not be possible to reach!") }
               }[rm.reverse_dense[region_idx]]
             )
           }
@@ -280,9 +629,7 @@ mod reselboard_tests {
       }
 
       // Now, each `x,y` should be accounted for
-      for x in 0..width{ for y in 0..height {
-        assert!(accounted_xy[x][y]);
-      }}
+      assert!(accounted_xy.iter().flatten().all(|&accounted| accounted));
 
       // Account for each region_idx in the dense indices
       for region_iterator in [
@@ -299,9 +646,7 @@ mod reselboard_tests {
         }
       
       // Now, each region_idx should be accounted for
-      for region_idx in 0..n_regions {
-        assert!(accounted_region[region_idx])
-      }
+      assert!(accounted_region.iter().all(|&accounted| accounted));
       
       // Test undirected adjacency
       for region_idx in 0..n_regions {
@@ -341,9 +686,9 @@ mod reselboard_tests {
       rm.region_to_xys,
       vec![
         vec![(0,3), (1,4), (2,0)],
-        vec![(0,0), (0,4), (2,4), (1,1)],
+        vec![(0,0), (0,4), (1,1), (2,4)],
         vec![(0,1)],
-        vec![(0,2),(2,2),(2,3),(1,3),(1,2)],
+        vec![(0,2),(1,2),(1,3),(2,2),(2,3)],
         vec![(1,0)],
         vec![(2,1)]
       ]
@@ -364,6 +709,13 @@ mod reselboard_tests {
     assert_eq!(rm.output_regions, vec![]);
     assert_eq!(rm.reverse_dense,  vec![0,0,1,2,0,1]);
 
+    // The flood fill numbers regions the same way, but lists each region's
+    // coordinates in the order it reached them
+    let ff = RegionMap::flood_fill(&rb);
+    assert_eq!(ff.xy_to_region, rm.xy_to_region);
+    assert_eq!(ff.region_to_xys[1], vec![(0,0), (0,4), (2,4), (1,1)]);
+    assert_eq!(ff.region_to_xys[3], vec![(0,2), (2,2), (2,3), (1,3), (1,2)]);
+
     // test get adjacent regions
 
     assert_eq!(
@@ -425,7 +777,7 @@ mod reselboard_tests {
       vec![
         vec![(0,1)],
         vec![(0,0), (1,0), (2,0), (3,0)],
-        vec![(0,2), (4,1), (3,1), (2,2), (4,0)],
+        vec![(0,2), (2,2), (3,1), (4,0), (4,1)],
         vec![(1,1), (1,2)],
         vec![(2,1)],
         vec![(3,2), (4,2)]
@@ -592,6 +944,131 @@ mod reselboard_tests {
     );
   }
 
+  #[test]
+  fn test_union_find_matches_flood_fill() {
+    let mut filenames: Vec<String> = std::fs::read_dir("./src/testing").unwrap()
+      .map(|entry| entry.unwrap().path().to_str().unwrap().to_string())
+      .filter(|filename| filename.ends_with(".png"))
+      .collect();
+    filenames.push("./reso_logo.png".to_string());
+
+    for filename in filenames {
+      let rb = ReselBoard::from(load_image_from_filename(&filename).unwrap());
+      let (uf, ff) = (RegionMap::union_find(&rb), RegionMap::flood_fill(&rb));
+
+      assert_eq!(uf.xy_to_region, ff.xy_to_region, "{}", filename);
+      assert_eq!(uf.region_to_resel, ff.region_to_resel, "{}", filename);
+      assert_eq!(uf.wire_regions, ff.wire_regions, "{}", filename);
+      assert_eq!(uf.input_regions, ff.input_regions, "{}", filename);
+      assert_eq!(uf.logic_regions, ff.logic_regions, "{}", filename);
+      assert_eq!(uf.output_regions, ff.output_regions, "{}", filename);
+      assert_eq!(uf.reverse_dense, ff.reverse_dense, "{}", filename);
+
+      // Same coordinates; union_find lists them in scan order
+      for (uf_xys, ff_xys) in uf.region_to_xys.iter().zip(&ff.region_to_xys) {
+        let mut ff_xys = ff_xys.clone();
+        ff_xys.sort();
+        assert_eq!(*uf_xys, ff_xys, "{}", filename);
+      }
+    }
+  }
+
+  #[test]
+  fn test_union_find_wraps() {
+    // Diagonal wire across the corner of the torus, and an input touching
+    // another diagonally across the top/bottom edge. Inputs don't connect
+    // diagonally.
+    let rb = ReselBoard::from_text("o+ \n   \n+ o", &Default::default()).unwrap();
+    let rm = RegionMap::union_find(&rb);
+    assert_eq!(rm.xy_to_region[0][0], rm.xy_to_region[2][2]);
+    assert_ne!(rm.xy_to_region[1][0], rm.xy_to_region[0][2]);
+    assert_eq!(rm.wire_regions.len(), 1);
+    assert_eq!(rm.input_regions.len(), 2);
+
+    // Inputs side by side across the left/right edge are one region
+    let rb = ReselBoard::from_text("   \n+ +\n   ", &Default::default()).unwrap();
+    let rm = RegionMap::union_find(&rb);
+    assert_eq!(rm.xy_to_region[0][1], rm.xy_to_region[2][1]);
+    assert_eq!(rm.input_regions.len(), 1);
+    assert_eq!(rm.region_to_xys[rm.xy_to_region[0][1]], vec![(0,1), (2,1)]);
+  }
+
+  #[test]
+  fn test_topologies() {
+    // Wires touching across the left/right edge, and an input touching a
+    // wire across the top/bottom edge
+    let rb = ReselBoard::from_text("o o\n   \n  +", &Default::default()).unwrap();
+    for (topology, wires_joined, input_adjacent) in [
+      (Topology::Torus,     true,  true),
+      (Topology::Bounded,   false, false),
+      (Topology::HCylinder, true,  false),
+      (Topology::VCylinder, false, true),
+    ] {
+      let rb = rb.clone().with_topology(topology);
+      for rm in [RegionMap::union_find(&rb), RegionMap::flood_fill(&rb)] {
+        assert_eq!(rm.topology, topology);
+        assert_eq!(rm.wire_regions.len(), if wires_joined { 1 } else { 2 }, "{}", topology);
+        let (input, wire) = (rm.xy_to_region[2][2], rm.xy_to_region[2][0]);
+        assert_eq!(rm.get_adjacent_regions(input).contains(&wire), input_adjacent, "{}", topology);
+      }
+    }
+  }
+
+  #[test]
+  fn test_mixed_wire_policy() {
+    use crate::reselboard::{TextOptions};
+
+    // Three wires: one on, two off; two on, one off; first on, rest off
+    let text = "ooO\n   \noOO\n   \nOoo";
+    for (policy, expected) in [
+      (MixedWirePolicy::AnyOn,      [true, true, true]),
+      (MixedWirePolicy::Majority,   [false, true, false]),
+      (MixedWirePolicy::FirstPixel, [false, false, true]),
+      (MixedWirePolicy::Error,      [false, false, true]),
+    ] {
+      let rb = ReselBoard::from_text(text, &TextOptions::default()).unwrap()
+        .with_topology(Topology::Bounded)
+        .with_mixed_wire_policy(policy);
+      for rm in [RegionMap::union_find(&rb), RegionMap::flood_fill(&rb)] {
+        let states: Vec<bool> = rm.wire_regions.iter()
+          .map(|ri| rm.region_to_resel[*ri].wire_state().unwrap())
+          .collect();
+        assert_eq!(states, expected, "{}", policy);
+        assert_eq!(rm.mixed_wires, vec![
+          MixedWire { region: 1, x: 0, y: 0, on: 1, off: 2 },
+          MixedWire { region: 2, x: 0, y: 2, on: 2, off: 1 },
+          MixedWire { region: 3, x: 0, y: 4, on: 1, off: 2 },
+        ]);
+      }
+      let checked = RegionMap::try_from_board(&rb);
+      if policy == MixedWirePolicy::Error {
+        assert!(matches!(checked, Err(ResoError::MixedWire { x: 0, y: 0, on: 1, off: 2 })));
+      } else {
+        assert_eq!(checked.unwrap().xy_to_region, RegionMap::from(&rb).xy_to_region);
+      }
+    }
+  }
+
+  #[test]
+  fn test_bus_regions() {
+    use crate::reselboard::{TextOptions};
+
+    // One bus of three resels with different values, touching diagonally,
+    // a bus input and output, and a wire the bus doesn't join
+    let rb = ReselBoard::from_text("#⠁o\n ⠂{\n}  ", &TextOptions::strict()).unwrap()
+      .with_topology(Topology::Bounded);
+    for rm in [RegionMap::union_find(&rb), RegionMap::flood_fill(&rb)] {
+      assert_eq!(rm.bus_regions, vec![1]);
+      assert_eq!(rm.bus_output_regions, vec![2]);
+      assert_eq!(rm.wire_regions, vec![3]);
+      assert_eq!(rm.bus_input_regions, vec![4]);
+      assert_eq!(rm.region_to_xys[1].len(), 3);
+      assert_eq!(rm.region_to_resel[1], Resel::Bus(0));
+      assert_eq!(rm.reverse_dense, vec![0, 0, 0, 0, 0]);
+      assert_eq!(rm.get_adjacent_regions(4), vec![0, 1, 3]);
+    }
+  }
+
   // todo: We could use more tests for more examples.
   // todo: The above tests could be made more robust; too fragile to ordering
 }
@@ -25,10 +25,41 @@
 //! | Bright teal    | XOR logic node        | ```#00ff80```  | `(  0, 255, 128)` | `^`   |
 //! | Dark purple    | Input (wire to node)  | ```#400080```  | `( 64,   0, 128)` | `+`   |
 //! | Bright purple  | Output (node to wire) | ```#8000ff```  | `(128,   0, 255)` | `=`   |
+//!
+//! Buses carry a `u8` instead of on/off, in the reserved cyan range:
+//!
+//! | Color          | Meaning                  | Hex code          | RGB                         | ASCII |
+//! | -------------- | ------------------------ | ---               | --------------------------- | ----- |
+//! | Dark to bright cyan | Bus with value `v`  | ```#008080```..   | `(v%2, 128+v/2, 128+v/2)`   | `#`, `⠁`..`⣿` |
+//! | Grey cyan      | Bus input                | ```#408080```     | `( 64, 128, 128)`           | `{`   |
+//! | Light cyan     | Bus output               | ```#80ffff```     | `(128, 255, 255)`           | `}`   |
+//!
+//! So a bus with value 0 is dark cyan, and brighter buses carry bigger values,
+//! up to bright cyan `#00ffff` (254) and `#01ffff` (255). In text, value 0 is
+//! `#` and every other value is the Braille pattern with those bits as dots,
+//! U+2800 + v.
+//!
+//! GPIO wires are wires driven from outside (see gpio.rs), in the reserved
+//! green range:
+//!
+//! | Color          | Meaning                  | Hex code          | RGB                         | ASCII |
+//! | -------------- | ------------------------ | ---               | --------------------------- | ----- |
+//! | Dark green     | GPIO wire (off)          | ```#008000```     | `(  0, 128,   0)`           | `g`   |
+//! | Bright green   | GPIO wire (on)           | ```#00ff00```     | `(  0, 255,   0)`           | `G`   |
+//!
+//! A subcircuit is a placeholder for another circuit, inlined by
+//! subcircuit.rs, in the reserved yellow range:
+//!
+//! | Color          | Meaning                  | Hex code          | RGB                         | ASCII |
+//! | -------------- | ------------------------ | ---               | --------------------------- | ----- |
+//! | Dark yellow    | Subcircuit               | ```#808000```     | `(128, 128,   0)`           | `@`   |
 //! 
 //! Example:
 //! 
 //! ```rust
+//! use reso::resel::{Resel};
+//! use image::{Rgba};
+//! 
 //! // Convert to a Resel
 //! let (r, g, b) = (  0, 255, 128);
 //! let rgba = Rgba([r, g, b, 255]);
@@ -57,6 +88,7 @@ use image::{Rgba};
 
 
 /// Enum of all the classes a resel can have
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Resel {
   WireOrangeOff,
@@ -69,10 +101,21 @@ pub enum Resel {
   XOR, 
   Input,
   Output,
-  Empty
+  Empty,
+  /// A bus wire, carrying a value
+  Bus(u8),
+  BusInput,
+  BusOutput,
+  /// A wire driven from outside. See gpio.rs
+  GpioOff,
+  GpioOn,
+  /// Where another circuit is inlined. See subcircuit.rs
+  Subcircuit,
 }
 
-// Palettes must be kept in the same order as one another
+// Palettes must be kept in the same order as one another.
+// Buses, GPIO wires and subcircuits aren't in the palettes; see bus_rgb()
+// and bus_str().
 
 /// Palette of Resels for easy iteration
 pub const PALETTE_RESEL: [Resel; 11] = [
@@ -148,7 +191,12 @@ impl From<(u8, u8, u8)> for Resel {
       (  0, 255, 128) => Resel::XOR,
       ( 64,   0, 128) => Resel::Input,
       (128,   0, 255) => Resel::Output,
-      _               => Resel::Empty,
+      ( 64, 128, 128) => Resel::BusInput,
+      (128, 255, 255) => Resel::BusOutput,
+      (  0, 128,   0) => Resel::GpioOff,
+      (  0, 255,   0) => Resel::GpioOn,
+      (128, 128,   0) => Resel::Subcircuit,
+      _               => bus_value_from_rgb(rgb).map_or(Resel::Empty, Resel::Bus),
     }
   }
 }
@@ -167,6 +215,12 @@ impl From<Rgba<u8>> for Resel {
       Rgba([  0, 255, 128, 255]) => Resel::XOR,
       Rgba([ 64,   0, 128, 255]) => Resel::Input,
       Rgba([128,   0, 255, 255]) => Resel::Output,
+      Rgba([ 64, 128, 128, 255]) => Resel::BusInput,
+      Rgba([128, 255, 255, 255]) => Resel::BusOutput,
+      Rgba([  0, 128,   0, 255]) => Resel::GpioOff,
+      Rgba([  0, 255,   0, 255]) => Resel::GpioOn,
+      Rgba([128, 128,   0, 255]) => Resel::Subcircuit,
+      Rgba([r, g, b, 255]) => bus_value_from_rgb((r, g, b)).map_or(Resel::Empty, Resel::Bus),
       _ => Resel::Empty,
     }
   }
@@ -186,7 +240,12 @@ impl From<&str> for Resel {
       "^" => Resel::XOR            ,
       "+" => Resel::Input          ,
       "=" => Resel::Output         ,
-       _  => Resel::Empty          ,
+      "{" => Resel::BusInput       ,
+      "}" => Resel::BusOutput      ,
+      "g" => Resel::GpioOff        ,
+      "G" => Resel::GpioOn         ,
+      "@" => Resel::Subcircuit     ,
+       _  => bus_value_from_str(c).map_or(Resel::Empty, Resel::Bus),
     }
   }
 }
@@ -205,7 +264,13 @@ impl From<Resel> for (u8, u8, u8) {
       Resel::XOR             => (  0, 255, 128),
       Resel::Input           => ( 64,   0, 128),
       Resel::Output          => (128,   0, 255),
-      Resel::Empty           => (0,     0,   0)
+      Resel::Empty           => (0,     0,   0),
+      Resel::Bus(value)      => bus_rgb(value),
+      Resel::BusInput        => ( 64, 128, 128),
+      Resel::BusOutput       => (128, 255, 255),
+      Resel::GpioOff         => (  0, 128,   0),
+      Resel::GpioOn          => (  0, 255,   0),
+      Resel::Subcircuit      => (128, 128,   0),
     }
   }
 }
@@ -223,7 +288,16 @@ impl From<Resel> for Rgba<u8> {
       Resel::XOR             => Rgba([  0, 255, 128, 255]),
       Resel::Input           => Rgba([ 64,   0, 128, 255]),
       Resel::Output          => Rgba([128,   0, 255, 255]),
-      Resel::Empty           => Rgba([0,     0,   0, 255])
+      Resel::Empty           => Rgba([0,     0,   0, 255]),
+      Resel::Bus(value)      => {
+        let (r, g, b) = bus_rgb(value);
+        Rgba([r, g, b, 255])
+      },
+      Resel::BusInput        => Rgba([ 64, 128, 128, 255]),
+      Resel::BusOutput       => Rgba([128, 255, 255, 255]),
+      Resel::GpioOff         => Rgba([  0, 128,   0, 255]),
+      Resel::GpioOn          => Rgba([  0, 255,   0, 255]),
+      Resel::Subcircuit      => Rgba([128, 128,   0, 255]),
     }
   }
 }
@@ -242,13 +316,90 @@ impl From<Resel> for &str {
       Resel::Input           => "+",
       Resel::Output          => "=",
       Resel::Empty           => " ",
+      Resel::Bus(value)      => bus_str(value),
+      Resel::BusInput        => "{",
+      Resel::BusOutput       => "}",
+      Resel::GpioOff         => "g",
+      Resel::GpioOn          => "G",
+      Resel::Subcircuit      => "@",
     }
   }
 }
 
+/// The colour of a bus carrying `value`: dark cyan `#008080` for 0, up to
+/// bright cyan. Green and blue hold the top seven bits, red the lowest.
+pub fn bus_rgb(value: u8) -> (u8, u8, u8) {
+  (value % 2, 128 + value / 2, 128 + value / 2)
+}
+
+/// The value of a bus drawn in `rgb`, or None if it's not a bus colour
+pub fn bus_value_from_rgb((r, g, b): (u8, u8, u8)) -> Option<u8> {
+  if r <= 1 && g == b && g >= 128 {
+    Some((g - 128) * 2 + r)
+  } else {
+    None
+  }
+}
+
+/// Text for a bus with value 0. Other values are Braille patterns.
+const BUS_ZERO_STR: &str = "#";
+const BRAILLE_BLANK: u32 = 0x2800;
+
+/// The text of a bus carrying `value`: `#` for 0, otherwise the Braille
+/// pattern U+2800 + value, whose dots are its bits
+pub fn bus_str(value: u8) -> &'static str {
+  static BUS_STRS: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+  if value == 0 {
+    return BUS_ZERO_STR
+  }
+  &BUS_STRS.get_or_init(|| (0..=255u32).map(
+    |value| char::from_u32(BRAILLE_BLANK + value).unwrap().to_string()
+  ).collect())[value as usize]
+}
+
+/// The value of a bus written as `c`, or None if it's not a bus.
+/// The blank Braille pattern is read as 0, like `#`.
+pub fn bus_value_from_str(c: &str) -> Option<u8> {
+  if c == BUS_ZERO_STR {
+    return Some(0)
+  }
+  let mut chars = c.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) => (c as u32).checked_sub(BRAILLE_BLANK)
+      .filter(|value| *value <= 255)
+      .map(|value| value as u8),
+    _ => None,
+  }
+}
+
+
+/// Parse a hex colour like `#ff8000`, `ff8000`, or `#ff800080` (with alpha).
+/// Alpha defaults to 255.
+pub fn rgba_from_hex(hex: &str) -> Option<Rgba<u8>> {
+  let hex = hex.trim().trim_start_matches('#');
+  if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+    return None
+  }
+  let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx+2], 16).ok();
+  Some(Rgba([
+    channel(0)?,
+    channel(2)?,
+    channel(4)?,
+    if hex.len() == 8 { channel(6)? } else { 255 },
+  ]))
+}
 
 impl Resel {
 
+  /// Like `Resel::from(&str)`, but returns None for strings not in
+  /// PALETTE_STR, instead of Resel::Empty.
+  pub fn try_from_str(c: &str) -> Option<Resel> {
+    match Resel::from(c) {
+      Resel::Empty => PALETTE_STR.iter().position(|&s| s == c).map(|idx| PALETTE_RESEL[idx]),
+      resel => Some(resel),
+    }
+  }
+
   /// Check if one Resel is the same as the other.
   /// Useful because WireOrangeOn is the "same" as WireOrangeOff
   pub fn same(self, other: Resel) -> bool {
@@ -261,6 +412,11 @@ impl Resel {
       ) | (
         Resel::WireLimeOff | Resel::WireLimeOn,
         Resel::WireLimeOn | Resel::WireLimeOff
+      ) | (
+        Resel::GpioOff | Resel::GpioOn,
+        Resel::GpioOn  | Resel::GpioOff
+      ) | (
+        Resel::Bus(_), Resel::Bus(_)
       ) => { true },
       (_, _) => { 
         // All other cases: Match true if resels are equal
@@ -271,20 +427,17 @@ impl Resel {
 
 
   pub fn is_wire(&self) -> bool {
-    match self {
+    matches!(
+      self,
       Resel::WireOrangeOff   | Resel::WireOrangeOn   |
       Resel::WireSapphireOff | Resel::WireSapphireOn |
-      Resel::WireLimeOff     | Resel::WireLimeOn
-        => true,
-      _ => false
-    }
+      Resel::WireLimeOff     | Resel::WireLimeOn     |
+      Resel::GpioOff         | Resel::GpioOn
+    )
   }
 
   pub fn is_logic(&self) -> bool {
-    match self {
-      Resel::AND | Resel::XOR => true,
-      _ => false
-    }
+    matches!(self, Resel::AND | Resel::XOR)
   }
 
   pub fn wire_state(&self) -> Option<bool> {
@@ -292,32 +445,94 @@ impl Resel {
       Resel::WireOrangeOn
       | Resel::WireSapphireOn
       | Resel::WireLimeOn
+      | Resel::GpioOn
       => Some(true),
       Resel::WireOrangeOff
       | Resel::WireSapphireOff
       | Resel::WireLimeOff
+      | Resel::GpioOff
       => Some(false),
       _ => None
     }
   }
 
+  /// The same wire, but on or off. None if not a wire.
+  pub fn with_wire_state(self, on: bool) -> Option<Resel> {
+    match (self, on) {
+      (Resel::WireOrangeOn   | Resel::WireOrangeOff,   true)  => Some(Resel::WireOrangeOn),
+      (Resel::WireOrangeOn   | Resel::WireOrangeOff,   false) => Some(Resel::WireOrangeOff),
+      (Resel::WireSapphireOn | Resel::WireSapphireOff, true)  => Some(Resel::WireSapphireOn),
+      (Resel::WireSapphireOn | Resel::WireSapphireOff, false) => Some(Resel::WireSapphireOff),
+      (Resel::WireLimeOn     | Resel::WireLimeOff,     true)  => Some(Resel::WireLimeOn),
+      (Resel::WireLimeOn     | Resel::WireLimeOff,     false) => Some(Resel::WireLimeOff),
+      (Resel::GpioOn         | Resel::GpioOff,         true)  => Some(Resel::GpioOn),
+      (Resel::GpioOn         | Resel::GpioOff,         false) => Some(Resel::GpioOff),
+      _ => None
+    }
+  }
+
+  /// The wire's colour, e.g. "orange", for naming wires. None if not a wire.
+  pub fn wire_colour_name(&self) -> Option<&'static str> {
+    match self {
+      Resel::WireOrangeOn   | Resel::WireOrangeOff   => Some("orange"),
+      Resel::WireSapphireOn | Resel::WireSapphireOff => Some("sapphire"),
+      Resel::WireLimeOn     | Resel::WireLimeOff     => Some("lime"),
+      Resel::GpioOn         | Resel::GpioOff         => Some("gpio"),
+      _ => None
+    }
+  }
+
+  pub fn is_gpio(&self) -> bool {
+    matches!(self, Resel::GpioOff | Resel::GpioOn)
+  }
+
+  pub fn is_subcircuit(&self) -> bool {
+    *self == Resel::Subcircuit
+  }
+
+  /// True for resels drawn in their own reserved colours in every palette,
+  /// i.e. buses, GPIO wires and subcircuits
+  pub fn is_reserved(&self) -> bool {
+    matches!(
+      self,
+      Resel::Bus(_) | Resel::BusInput | Resel::BusOutput
+        | Resel::GpioOff | Resel::GpioOn | Resel::Subcircuit
+    )
+  }
+
+  pub fn is_bus(&self) -> bool {
+    matches!(self, Resel::Bus(_))
+  }
+
+  /// The value a bus carries. None if not a bus.
+  pub fn bus_value(&self) -> Option<u8> {
+    match self {
+      Resel::Bus(value) => Some(*value),
+      _ => None
+    }
+  }
+
   pub fn is_empty(&self)  -> bool { *self == Resel::Empty }
   pub fn is_input(&self)  -> bool { *self == Resel::Input }
   pub fn is_output(&self) -> bool { *self == Resel::Output }
   pub fn is_io(&self)     -> bool { self.is_input() || self.is_output() }
+  pub fn is_bus_input(&self)  -> bool { *self == Resel::BusInput }
+  pub fn is_bus_output(&self) -> bool { *self == Resel::BusOutput }
 
 
   /// resel.delta_neighbors() returns the relative (x,y) of neighboring cells.
   /// Used in the region mapper to find contiguous regions.
   /// 
-  /// Wire neighborhoods are orthogonal and diagonal, meaning wire resels
-  /// touching on the sides or corners are part of the same region.
+  /// Wire (and bus) neighborhoods are orthogonal and diagonal, meaning wire
+  /// resels touching on the sides or corners are part of the same region.
   /// All others are only orthogonally contiguous. 
   pub fn delta_neighbors(&self) -> Vec<(isize, isize)> {
     match self {
       Resel::WireOrangeOff   | Resel::WireOrangeOn   |
       Resel::WireSapphireOff | Resel::WireSapphireOn |
-      Resel::WireLimeOff     | Resel::WireLimeOn
+      Resel::WireLimeOff     | Resel::WireLimeOn     |
+      Resel::GpioOff         | Resel::GpioOn         |
+      Resel::Bus(_)
         => vec![(1,0),(1,1),(0,1),(-1,1),(-1,0),(-1,-1),(0,-1),(1,-1)],
       Resel::Empty => Vec::new(),
       _ => vec![(1,0),(0,1),(-1,0),(0,-1)],
@@ -435,6 +650,85 @@ mod resel_conversion_tests {
     }
   }
   
+  #[test]
+  fn test_try_from_str() {
+    for (resel, cc) in PALETTE_RESEL.iter().zip(PALETTE_STR) {
+      assert_eq!(Resel::try_from_str(cc), Some(*resel));
+    }
+    assert_eq!(Resel::try_from_str("x"), None);
+    assert_eq!(Resel::from("x"), Resel::Empty);
+  }
+
+  #[test]
+  fn test_bus_conversions() {
+    for value in 0..=255u8 {
+      let resel = Resel::Bus(value);
+      let (r, g, b): (u8, u8, u8) = resel.into();
+      let cc: &str = resel.into();
+      assert_eq!(Resel::from((r, g, b)), resel);
+      assert_eq!(Resel::from(Rgba([r, g, b, 255])), resel);
+      assert_eq!(Resel::from(cc), resel);
+      assert_eq!(Resel::try_from_str(cc), Some(resel));
+      assert!(!PALETTE_U8U8U8.contains(&(r, g, b)));
+    }
+    assert_eq!(<(u8, u8, u8)>::from(Resel::Bus(0)), (0, 128, 128));
+    assert_eq!(<(u8, u8, u8)>::from(Resel::Bus(254)), (0, 255, 255));
+    assert_eq!(<&str>::from(Resel::Bus(0b1001)), "⠉");
+    assert_eq!(Resel::from("\u{2800}"), Resel::Bus(0));
+    assert_eq!(Resel::from((2, 200, 200)), Resel::Empty);
+    assert_eq!(Resel::from((0, 127, 127)), Resel::Empty);
+
+    for (resel, cc) in [(Resel::BusInput, "{"), (Resel::BusOutput, "}")] {
+      let rgba: Rgba<u8> = resel.into();
+      assert_eq!(Resel::from(rgba), resel);
+      assert_eq!(Resel::from(cc), resel);
+      assert_eq!(<&str>::from(resel), cc);
+    }
+    assert!(Resel::Bus(1).same(Resel::Bus(200)));
+    assert!(!Resel::Bus(1).same(Resel::BusInput));
+    assert_eq!(Resel::Bus(7).delta_neighbors().len(), 8);
+  }
+
+  #[test]
+  fn test_gpio_conversions() {
+    for (resel, rgb, cc, on) in [
+      (Resel::GpioOff, (0, 128, 0), "g", false),
+      (Resel::GpioOn,  (0, 255, 0), "G", true),
+    ] {
+      assert_eq!(Resel::from(rgb), resel);
+      assert_eq!(<(u8, u8, u8)>::from(resel), rgb);
+      assert_eq!(Resel::from(cc), resel);
+      assert_eq!(<&str>::from(resel), cc);
+      assert!(resel.is_wire() && resel.is_gpio() && resel.is_reserved());
+      assert_eq!(resel.wire_state(), Some(on));
+      assert_eq!(resel.with_wire_state(!on).map(|r| r.wire_state()), Some(Some(!on)));
+      assert_eq!(resel.wire_colour_name(), Some("gpio"));
+    }
+    assert_eq!(Resel::WireSapphireOff.wire_colour_name(), Some("sapphire"));
+    assert_eq!(Resel::AND.wire_colour_name(), None);
+    assert!(Resel::GpioOn.same(Resel::GpioOff));
+    assert!(!Resel::GpioOn.same(Resel::WireLimeOn));
+
+    assert_eq!(Resel::from((128, 128, 0)), Resel::Subcircuit);
+    assert_eq!(Resel::from("@"), Resel::Subcircuit);
+    assert_eq!(<&str>::from(Resel::Subcircuit), "@");
+    assert!(Resel::Subcircuit.is_reserved() && !Resel::Subcircuit.is_wire());
+  }
+
+  #[test]
+  fn test_rgba_from_hex() {
+    for (hex, expected) in [
+      ("#ff8000",   Some(Rgba([255, 128, 0, 255]))),
+      ("ff8000",    Some(Rgba([255, 128, 0, 255]))),
+      ("#FF800080", Some(Rgba([255, 128, 0, 128]))),
+      ("#ff80",     None),
+      ("#gg8000",   None),
+      ("",          None),
+    ] {
+      assert_eq!(rgba_from_hex(hex), expected);
+    }
+  }
+
   #[test]
   fn test_same() {
     for (resel1, resel2) in [
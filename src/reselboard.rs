@@ -9,10 +9,19 @@
 //! Example:
 //! 
 //! ```rust
-//! let reselboard = image_to_reselboard(
-//!   load_image_from_filename("some_file.png").unwrap()
+//! use reso::{ReselBoard, load_image_from_filename};
+//! 
+//! let reselboard = ReselBoard::from(
+//!   load_image_from_filename("./src/testing/test_06.png").unwrap()
 //! );
 //! 
+//! // Or, with errors:
+//! let reselboard = ReselBoard::from_filename("./src/testing/test_06.png");
+//! assert!(reselboard.is_ok());
+//! 
+//! // Text circuits use the characters in PALETTE_STR
+//! let reselboard = ReselBoard::from_filename("./src/testing/test_half_adder.txt").unwrap();
+//! print!("{}", reselboard.to_text());
 //! ```
 
 /*
@@ -31,7 +40,116 @@ TODOs:
 */
 
 use crate::resel::{Resel};
-use image::{Rgba, DynamicImage, GenericImageView};
+use crate::palette::{Palette, ColorMatching, SnapReport};
+use crate::error::{ResoError};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba, RgbaImage};
+use std::path::{Path};
+
+/// How the edges of a board connect to each other.
+///
+/// Reso boards are traditionally a torus: a wire at the right edge touches
+/// the left edge, and one at the top touches the bottom. The other
+/// topologies turn some or all of that wrapping off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+  /// Left meets right and top meets bottom
+  #[default]
+  Torus,
+  /// Nothing wraps; resels at the edges just have fewer neighbors
+  Bounded,
+  /// Left meets right, but top and bottom are edges
+  HCylinder,
+  /// Top meets bottom, but left and right are edges
+  VCylinder,
+}
+
+/// Names of each topology, as used by FromStr, Display, and the CLI
+pub const TOPOLOGY_NAMES: [(Topology, &str); 4] = [
+  (Topology::Torus,     "torus"),
+  (Topology::Bounded,   "bounded"),
+  (Topology::HCylinder, "h-cylinder"),
+  (Topology::VCylinder, "v-cylinder"),
+];
+
+impl Topology {
+  /// True if the left and right edges meet
+  pub fn wraps_x(self) -> bool {
+    matches!(self, Topology::Torus | Topology::HCylinder)
+  }
+
+  /// True if the top and bottom edges meet
+  pub fn wraps_y(self) -> bool {
+    matches!(self, Topology::Torus | Topology::VCylinder)
+  }
+
+  /// (x+dx, y+dy), wrapped along the axes that wrap.
+  /// None if it falls off an edge that doesn't.
+  pub fn neighbor(
+    self,
+    x: usize, y: usize,
+    dx: isize, dy: isize,
+    width: usize, height: usize,
+  ) -> Option<(usize, usize)> {
+    Some((
+      offset_along_axis(x, dx, width, self.wraps_x())?,
+      offset_along_axis(y, dy, height, self.wraps_y())?,
+    ))
+  }
+}
+
+impl std::str::FromStr for Topology {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Topology, String> {
+    TOPOLOGY_NAMES.iter().find(|(_, n)| *n == name).map(|(topology, _)| *topology)
+      .ok_or_else(|| format!("unknown topology `{}`", name))
+  }
+}
+
+impl std::fmt::Display for Topology {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let (_, name) = TOPOLOGY_NAMES.iter().find(|(topology, _)| topology == self).unwrap();
+    write!(f, "{}", name)
+  }
+}
+
+/// How to set the state of a wire region drawn with both on and off resels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MixedWirePolicy {
+  /// On if any resel is on
+  AnyOn,
+  /// On if at least half the resels are on
+  Majority,
+  /// Whatever the region's first resel (scanning x, then y) is
+  #[default]
+  FirstPixel,
+  /// Like FirstPixel, but `ResoCircuit::compile` fails instead
+  Error,
+}
+
+/// Each MixedWirePolicy and its name, e.g. for the command line
+pub const MIXED_WIRE_POLICY_NAMES: [(MixedWirePolicy, &str); 4] = [
+  (MixedWirePolicy::AnyOn,      "any-on"),
+  (MixedWirePolicy::Majority,   "majority"),
+  (MixedWirePolicy::FirstPixel, "first-pixel"),
+  (MixedWirePolicy::Error,      "error"),
+];
+
+impl std::str::FromStr for MixedWirePolicy {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<MixedWirePolicy, String> {
+    MIXED_WIRE_POLICY_NAMES.iter().find(|(_, n)| *n == name).map(|(policy, _)| *policy)
+      .ok_or_else(|| format!("unknown mixed wire policy `{}`", name))
+  }
+}
+
+impl std::fmt::Display for MixedWirePolicy {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let (_, name) = MIXED_WIRE_POLICY_NAMES.iter().find(|(policy, _)| policy == self).unwrap();
+    write!(f, "{}", name)
+  }
+}
 
 /// Utility over Vec<Vec<Resel>>, i.e. grid of Resel
 #[derive(Clone, Debug)]
@@ -39,7 +157,16 @@ pub struct ReselBoard {
   pub board: Vec<Vec<Resel>>,
   pub image: Option<DynamicImage>,
   pub width: usize,
-  pub height: usize
+  pub height: usize,
+  /// Which edges wrap around. Torus by default.
+  pub topology: Topology,
+  /// Colours of each resel class, for reading and drawing images. RESO-12 by default.
+  pub palette: Palette,
+  /// How many pixels across each resel was in the original image, if it
+  /// was downscaled with `with_scale`. 1 otherwise.
+  pub scale: usize,
+  /// How to set the state of wire regions drawn both on and off. First-pixel by default.
+  pub mixed_wire_policy: MixedWirePolicy,
 }
 
 /// Consume an image and return a ReselBoard
@@ -51,68 +178,238 @@ fn image_to_reselboard(image: DynamicImage) -> ReselBoard {
     image: Some(image),
     width: width as usize,
     height: height as usize,
+    topology: Topology::default(),
+    palette: Palette::default(),
+    scale: 1,
+    mixed_wire_policy: MixedWirePolicy::default(),
   }
 }
 
 /// Consume a Vec<Vec<Resel>> and return a ReselBoard
 /// (todo: optionally instantiate ReselBoard.image along with this)
-fn vecvecresel_to_reselboard(board: Vec<Vec<Resel>>) -> ReselBoard {
-  // todo: check board is a grid, at least one whole pixel
+fn vecvecresel_to_reselboard(board: Vec<Vec<Resel>>) -> Result<ReselBoard, ResoError> {
   // consider using 'grid'
+  let (width, height) = check_vecvecresel(&board)?;
+  Ok(ReselBoard {
+    board,
+    image: None, // todo: Optionally generate from ReselBoard
+    width,
+    height,
+    topology: Topology::default(),
+    palette: Palette::default(),
+    scale: 1,
+    mixed_wire_policy: MixedWirePolicy::default(),
+  })
+}
+
+/// Check a Vec<Vec<Resel>> is a grid with at least one resel.
+/// Returns (width, height) if so.
+pub fn check_vecvecresel(board: &[Vec<Resel>]) -> Result<(usize, usize), ResoError> {
   let width = board.len();
+  if width == 0 || board[0].is_empty() {
+    return Err(ResoError::EmptyBoard)
+  }
   let height = board[0].len();
-  ReselBoard {
-    board: board,
-    image: None, // todo: Optionally generate from ReselBoard
-    width: width,
-    height: height
+  for (x, column) in board.iter().enumerate() {
+    if column.len() != height {
+      return Err(ResoError::RaggedBoard { x, len: column.len(), height })
+    }
   }
+  Ok((width, height))
+}
+
+/// What to do with text lines shorter than the longest line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RaggedLines {
+  /// Pad short lines with Resel::Empty
+  #[default]
+  Pad,
+  /// Return ResoError::RaggedLine
+  Error,
+}
+
+/// What to do with characters that aren't in PALETTE_STR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownChars {
+  /// Treat them as Resel::Empty, like `Resel::from(&str)` does
+  #[default]
+  Empty,
+  /// Return ResoError::UnknownChar
+  Error,
+}
+
+/// Options for reading a text circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextOptions {
+  pub ragged_lines:  RaggedLines,
+  pub unknown_chars: UnknownChars,
+}
+
+impl TextOptions {
+  /// Error on ragged lines and unknown characters
+  pub fn strict() -> TextOptions {
+    TextOptions {
+      ragged_lines:  RaggedLines::Error,
+      unknown_chars: UnknownChars::Error,
+    }
+  }
+}
+
+/// Instantiate Vec<Vec<Resel>> from text, one line per row.
+/// Line and column numbers in errors count from 1, like a text editor.
+pub fn text_to_vecvecresel(
+  text: &str, options: &TextOptions
+) -> Result<Vec<Vec<Resel>>, ResoError> {
+  let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+  let height = lines.len();
+  let width  = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+  let mut board = vec![vec![Resel::Empty; height]; width];
+  for (y, line) in lines.iter().enumerate() {
+    if line.len() != width && options.ragged_lines == RaggedLines::Error {
+      return Err(ResoError::RaggedLine { line: y+1, len: line.len(), width })
+    }
+    for (x, c) in line.iter().enumerate() {
+      board[x][y] = match Resel::try_from_str(c.encode_utf8(&mut [0; 4])) {
+        Some(resel) => resel,
+        None => match options.unknown_chars {
+          UnknownChars::Empty => Resel::Empty,
+          UnknownChars::Error => {
+            return Err(ResoError::UnknownChar { line: y+1, column: x+1, c: *c })
+          }
+        }
+      };
+    }
+  }
+  Ok(board)
+}
+
+/// Instantiate text from &Vec<Vec<Resel>>, one line per row.
+/// Every line is `width` characters long and ends with a newline.
+pub fn vecvecresel_to_text(board: &[Vec<Resel>]) -> String {
+  let width  = board.len();
+  let height = board.first().map_or(0, |column| column.len());
+  let mut text = String::with_capacity((width + 1) * height);
+  for y in 0..height {
+    for column in board {
+      text.push_str(<&str>::from(column[y]));
+    }
+    text.push('\n');
+  }
+  text
+}
+
+/// True if `filename` ends in `.txt`, meaning it should be read as text
+pub fn is_text_filename(filename: &str) -> bool {
+  Path::new(filename).extension()
+    .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
 }
 
 /// Helper function to load images from &str
 pub fn load_image_from_filename(filename: &str) -> Option<DynamicImage> {
-  match image::open(filename) {
-    Ok(img) => Some(img),
-    Err(_)  => None
-  }
+  image::open(filename).ok()
 }
 
 /// Helper function to load images from String
 pub fn load_image_from_filename_string(filename: String) -> Option<DynamicImage> {
-  match image::open(filename) {
-    Ok(img) => Some(img),
-    Err(_)  => None
-  }
+  image::open(filename).ok()
 }
 
-/// Instantiate Vec<Vec<Resel>> from &DynamicImage
-pub fn image_to_vecvecresel(img: &DynamicImage) -> Vec<Vec<Resel>> {
+/// Instantiate Vec<Vec<Resel>> from &DynamicImage, turning each pixel
+/// into a resel with `to_resel`
+pub fn image_to_vecvecresel_with(
+  img: &DynamicImage, mut to_resel: impl FnMut(Rgba<u8>) -> Resel
+) -> Vec<Vec<Resel>> {
   let (width, height) = img.dimensions();
   let mut reselboard = vec![vec![Resel::Empty; height as usize]; width as usize];
   for x in 0..width {
     for y in 0..height {
-      let pixel = img.get_pixel(x, y);
-      let resel = Resel::from(pixel);
-      reselboard[x as usize][y as usize] = resel;
+      reselboard[x as usize][y as usize] = to_resel(img.get_pixel(x, y));
     }
   }
   reselboard
 }
 
-/// Instantiate DynamicImage from &Vec<Vec<Resel>>;
-/*
-// todo another time; DynamicImage is hard to work with, can't figure this out
-pub fn vecvecresel_to_image(board: &Vec<Vec<Resel>>) -> DynamicImage {
-  // todo: Another place where we'd benefit from using 'grid'. Needs checks
-  let width = board.len();
-  let height = board[0].len();
+/// Instantiate Vec<Vec<Resel>> from &DynamicImage
+pub fn image_to_vecvecresel(img: &DynamicImage) -> Vec<Vec<Resel>> {
+  image_to_vecvecresel_with(img, Resel::from)
+}
 
-  let as_pixels: Vec<Vec<Rgba<u8>>> = board.into_iter()
-    .map(|row| {row.into_iter()
-      .map(|resel: &Resel| <Rgba<u8>>::from(*resel)).collect()
-    }).collect();
+/// Instantiate Vec<Vec<Resel>> from &DynamicImage drawn in `palette`
+pub fn image_to_vecvecresel_with_palette(
+  img: &DynamicImage, palette: &Palette
+) -> Vec<Vec<Resel>> {
+  image_to_vecvecresel_with(img, |pixel| palette.to_resel(pixel))
+}
+
+/// Instantiate Vec<Vec<Resel>> from &DynamicImage drawn (roughly) in
+/// `palette`, snapping pixels to it as `matching` allows.
+/// Also returns how many pixels were snapped.
+pub fn image_to_vecvecresel_matching(
+  img: &DynamicImage, palette: &Palette, matching: &ColorMatching
+) -> (Vec<Vec<Resel>>, SnapReport) {
+  let mut report = SnapReport::default();
+  let reselboard = image_to_vecvecresel_with(img, |pixel| {
+    let matched = palette.match_color(pixel, matching);
+    report.record(matched);
+    matched.resel()
+  });
+  (reselboard, report)
+}
+
+/// The largest integer scale the board is drawn at, i.e. the largest `s`
+/// dividing the width and height where every `s` x `s` block is one resel.
+/// 1 if the board isn't upscaled.
+pub fn detect_scale(board: &[Vec<Resel>]) -> usize {
+  let (width, height) = (board.len(), board.first().map_or(0, Vec::len));
+  // Every edge between two different resels must fall on a block edge
+  let mut scale = gcd(width, height);
+  for x in 0..width {
+    for y in 0..height {
+      if scale == 1 {
+        return 1
+      }
+      if x > 0 && board[x][y] != board[x-1][y] {
+        scale = gcd(scale, x);
+      }
+      if y > 0 && board[x][y] != board[x][y-1] {
+        scale = gcd(scale, y);
+      }
+    }
+  }
+  scale.max(1)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+  if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Collapse each `scale` x `scale` block of the board into one resel.
+/// Takes the top-left resel of each block; see `detect_scale`.
+pub fn downscale_vecvecresel(board: &[Vec<Resel>], scale: usize) -> Vec<Vec<Resel>> {
+  board.iter().step_by(scale)
+    .map(|column| column.iter().step_by(scale).copied().collect())
+    .collect()
+}
+
+/// Instantiate DynamicImage from &Vec<Vec<Resel>>.
+/// Empty resels are drawn as `empty`, since Resel::Empty has no one colour.
+pub fn vecvecresel_to_image(board: &[Vec<Resel>], empty: Rgba<u8>) -> DynamicImage {
+  vecvecresel_to_image_with_palette(board, empty, &Palette::default())
+}
+
+/// Like `vecvecresel_to_image`, but drawn in `palette`
+pub fn vecvecresel_to_image_with_palette(
+  board: &[Vec<Resel>], empty: Rgba<u8>, palette: &Palette
+) -> DynamicImage {
+  let width  = board.len();
+  let height = board.first().map_or(0, |column| column.len());
+
+  DynamicImage::ImageRgba8(RgbaImage::from_fn(
+    width as u32, height as u32,
+    |x, y| palette.to_rgba(board[x as usize][y as usize]).unwrap_or(empty)
+  ))
 }
-*/
 
 impl From<DynamicImage> for ReselBoard {
   fn from(image: DynamicImage) -> Self {
@@ -121,25 +418,181 @@ impl From<DynamicImage> for ReselBoard {
 }
 
 impl From<Vec<Vec<Resel>>> for ReselBoard {
+  /// Panics if `board` is empty or not a grid. See `ReselBoard::new`.
   fn from(board: Vec<Vec<Resel>>) -> Self {
-    vecvecresel_to_reselboard(board)
+    vecvecresel_to_reselboard(board).unwrap()
   }
 }
 
 impl ReselBoard {
+  /// Consume a Vec<Vec<Resel>> and return a ReselBoard,
+  /// or an error if it is empty or not a grid.
+  pub fn new(board: Vec<Vec<Resel>>) -> Result<ReselBoard, ResoError> {
+    vecvecresel_to_reselboard(board)
+  }
+
+  /// Load a circuit file and return a ReselBoard.
+  /// `.txt` files are read as text with default TextOptions, and
+  /// anything else is read as an image.
+  pub fn from_filename(filename: &str) -> Result<ReselBoard, ResoError> {
+    if is_text_filename(filename) {
+      ReselBoard::from_text_filename(filename, &TextOptions::default())
+    } else {
+      Ok(image_to_reselboard(image::open(filename)?))
+    }
+  }
+
+  /// Read a text circuit and return a ReselBoard
+  pub fn from_text(text: &str, options: &TextOptions) -> Result<ReselBoard, ResoError> {
+    vecvecresel_to_reselboard(text_to_vecvecresel(text, options)?)
+  }
+
+  /// Load a text circuit file and return a ReselBoard
+  pub fn from_text_filename(
+    filename: &str, options: &TextOptions
+  ) -> Result<ReselBoard, ResoError> {
+    ReselBoard::from_text(&std::fs::read_to_string(filename)?, options)
+  }
+
+  /// Dump the board to text, one line per row
+  pub fn to_text(&self) -> String {
+    vecvecresel_to_text(&self.board)
+  }
+
+  /// Save the board as a text circuit
+  pub fn save_text(&self, filename: &str) -> Result<(), ResoError> {
+    Ok(std::fs::write(filename, self.to_text())?)
+  }
+
+  /// Use a different topology, e.g.
+  /// `ReselBoard::from_filename(f)?.with_topology(Topology::Bounded)`
+  pub fn with_topology(mut self, topology: Topology) -> ReselBoard {
+    self.topology = topology;
+    self
+  }
+
+  /// Use a different policy for mixed on/off wire regions, e.g.
+  /// `ReselBoard::from_filename(f)?.with_mixed_wire_policy(MixedWirePolicy::Error)`.
+  /// Error is only enforced by the fallible constructors, `ResoCircuit::compile`
+  /// and `RegionMap::try_from_board`; `From` treats it like FirstPixel.
+  pub fn with_mixed_wire_policy(mut self, policy: MixedWirePolicy) -> ReselBoard {
+    self.mixed_wire_policy = policy;
+    self
+  }
+
+  /// Use a different palette, e.g.
+  /// `ReselBoard::from_filename(f)?.with_palette(palette)`.
+  /// If the board has an image, its resels are re-read with the new palette.
+  pub fn with_palette(mut self, palette: Palette) -> ReselBoard {
+    self.palette = palette;
+    if let Some(image) = &self.image {
+      self.board = image_to_vecvecresel_with_palette(image, &palette);
+    }
+    self
+  }
+
+  /// The largest integer scale the board is drawn at. See `detect_scale`.
+  pub fn detect_scale(&self) -> usize {
+    detect_scale(&self.board)
+  }
+
+  /// Collapse each `scale` x `scale` block of resels (and pixels, if there's
+  /// an image) into one, e.g. for circuits shared upscaled 8x.
+  /// Fails unless every block is a single resel.
+  pub fn with_scale(mut self, scale: usize) -> Result<ReselBoard, ResoError> {
+    if scale == 0 {
+      return Err(ResoError::Scale("scale must be at least 1".to_string()))
+    }
+    if !self.width.is_multiple_of(scale) || !self.height.is_multiple_of(scale) {
+      return Err(ResoError::Scale(format!(
+        "{}x{} is not a multiple of {}", self.width, self.height, scale
+      )))
+    }
+    if !self.detect_scale().is_multiple_of(scale) {
+      return Err(ResoError::Scale(format!(
+        "not every {}x{} block is a single resel", scale, scale
+      )))
+    }
+
+    self.board = downscale_vecvecresel(&self.board, scale);
+    self.width /= scale;
+    self.height /= scale;
+    self.scale *= scale;
+    if let Some(image) = &self.image {
+      let step = scale as u32;
+      self.image = Some(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+        self.width as u32, self.height as u32,
+        |x, y| image.get_pixel(x * step, y * step)
+      )));
+    }
+    Ok(self)
+  }
+
+  /// Re-read the resels from the image with tolerant colour matching, e.g.
+  /// for JPEGs. Returns how many pixels were snapped to the palette, or
+  /// None if the board has no image.
+  /// The image itself is left as it is.
+  pub fn snap_image(&mut self, matching: &ColorMatching) -> Option<SnapReport> {
+    let image = self.image.as_ref()?;
+    let (board, report) = image_to_vecvecresel_matching(image, &self.palette, matching);
+    self.board = board;
+    Some(report)
+  }
+
   /// For a given (x,y) coordinate, return the absolute neighbor coordinates
-  /// Wraps around the width and height of the board, and takes into account
-  /// the Resel-specific neighborhoods. (8 for wires, 4 for others)
+  /// Wraps around the width and height of the board (as the topology allows),
+  /// and takes into account the Resel-specific neighborhoods.
+  /// (8 for wires, 4 for others)
   pub fn get_neighbors(&self, x: usize, y:usize) -> Vec<(usize, usize)> {
     get_neighbors(
       self.board[x][y].delta_neighbors(),
       x,
       y,
       self.width,
-      self.height
+      self.height,
+      self.topology
     )
   }
 
+  /// Render the board to a new image in its palette.
+  /// Empty resels are drawn as `empty`.
+  pub fn to_image(&self, empty: Rgba<u8>) -> DynamicImage {
+    vecvecresel_to_image_with_palette(&self.board, empty, &self.palette)
+  }
+
+  /// Replace `image` with a render of the board, e.g. for boards loaded
+  /// from text, so they have an image to update and save.
+  pub fn render_image(&mut self, empty: Rgba<u8>) {
+    self.image = Some(self.to_image(empty))
+  }
+
+  /// Copy `stamp` onto the board with its top-left corner at (x,y),
+  /// overwriting everything under it, empty resels included.
+  /// If the board has an image, the pixels are redrawn in the board's
+  /// palette, with empty resels drawn as `empty`.
+  /// Fails without changing anything if the stamp doesn't fit.
+  pub fn stamp(
+    &mut self, x: usize, y: usize, stamp: &ReselBoard, empty: Rgba<u8>
+  ) -> Result<(), ResoError> {
+    let (Some(right), Some(bottom)) = (x.checked_add(stamp.width), y.checked_add(stamp.height)) else {
+      return Err(ResoError::OutOfBounds { x, y })
+    };
+    if right > self.width || bottom > self.height {
+      return Err(ResoError::OutOfBounds { x: right - 1, y: bottom - 1 })
+    }
+
+    for (sx, column) in stamp.board.iter().enumerate() {
+      for (sy, resel) in column.iter().enumerate() {
+        self.board[x + sx][y + sy] = *resel;
+        if let Some(image) = &mut self.image {
+          let rgba = self.palette.to_rgba(*resel).unwrap_or(empty);
+          image.put_pixel((x + sx) as u32, (y + sy) as u32, rgba);
+        }
+      }
+    }
+    Ok(())
+  }
+
   /// Replace `image` with None, erasing the reference and saving memory
   pub fn dump_image_to_save_memory(&mut self) {
     self.image = None
@@ -156,37 +609,36 @@ pub fn delta_to_neighbor(
   width: usize, height: usize,
   wrap: bool
 ) -> Option<(usize, usize)> {
+  let topology = if wrap { Topology::Torus } else { Topology::Bounded };
+  topology.neighbor(x, y, dx, dy, width, height)
+}
+
+/// `x + dx` along one axis of length `len`, wrapping if `wrap`
+fn offset_along_axis(x: usize, dx: isize, len: usize, wrap: bool) -> Option<usize> {
   // todo: handle overflows, write tests for that
   let ax = x as isize + dx;
-  let ay = y as isize + dy;
-  if wrap { // wrap: No "out-of-bounds" to consider, just return (x+dx)%width
-    Some(
-      (
-        ((ax + width as isize) as usize % width),
-        ((ay + height as isize) as usize % height),
-      )
-    )
-  } else { // No wrap; check (ax,ay) within bounds
-    if (ax >= width as isize) || (ax < 0) || (ay >= height as isize) || (ay < 0)
-    { // Out of bounds, return None
-      None
-    } else { // Within bounds, just return (ax, ay)
-      Some((ax as usize, ay as usize))
-    }
+  if wrap { // wrap: No "out-of-bounds" to consider, just return (x+dx)%len
+    Some(ax.rem_euclid(len as isize) as usize)
+  } else if ax < 0 || ax >= len as isize { // No wrap, and out of bounds
+    None
+  } else {
+    Some(ax as usize)
   }
 }
 
 /// Get a list of absolute coordinates of neighbors
-/// Given deltas, the pixel they apply to, and the board width/height 
-/// Conveneince wrapping over delta_to_neighbor.
+/// Given deltas, the pixel they apply to, the board width/height, and
+/// which edges wrap.
+/// Conveneince wrapping over Topology::neighbor.
 pub fn get_neighbors(
-  deltas: Vec<(isize, isize)>, x: usize, y: usize, width: usize, height: usize
+  deltas: Vec<(isize, isize)>, x: usize, y: usize, width: usize, height: usize,
+  topology: Topology
 ) -> Vec<(usize, usize)> {
   // Given resel class + x,y + width,height, get the neighborhood of (x,y) coordinates
 
   deltas
     .into_iter()
-    .filter_map(|(dx, dy)| delta_to_neighbor(x, y, dx, dy, width, height, true))
+    .filter_map(|(dx, dy)| topology.neighbor(x, y, dx, dy, width, height))
     .collect()
 }
 
@@ -207,6 +659,114 @@ mod reselboard_tests {
     assert!(load_image_from_filename("./src/testing/test_01_new-palette.png").is_some())
   }
 
+  #[test]
+  fn reselboard_from_filename() {
+    assert!(ReselBoard::from_filename("./src/testing/test_01_new-palette.png").is_ok());
+    assert!(matches!(
+      ReselBoard::from_filename("this_does_not_exist.png"),
+      Err(ResoError::Image(_))
+    ));
+  }
+
+  #[test]
+  fn reselboard_stamp() {
+    let empty = Rgba([0, 0, 0, 255]);
+    let mut rb = ReselBoard::from_text("&&&&\n&&&&\n&&&&", &TextOptions::strict()).unwrap();
+    rb.render_image(empty);
+    let stamp = ReselBoard::from_text("o \n+O", &TextOptions::strict()).unwrap();
+
+    rb.stamp(2, 1, &stamp, empty).unwrap();
+    assert_eq!(rb.to_text(), "&&&&\n&&o \n&&+O\n");
+    let image = rb.image.as_ref().unwrap();
+    assert_eq!(image.get_pixel(3, 1), empty);
+    assert_eq!(Some(image.get_pixel(3, 2)), Palette::default().to_rgba(Resel::WireOrangeOn));
+
+    // Doesn't fit
+    assert!(matches!(rb.stamp(3, 0, &stamp, empty), Err(ResoError::OutOfBounds { x: 4, y: 1 })));
+    assert!(matches!(
+      rb.stamp(usize::MAX, 0, &stamp, empty), Err(ResoError::OutOfBounds { x: usize::MAX, y: 0 })
+    ));
+    assert_eq!(rb.to_text(), "&&&&\n&&o \n&&+O\n");
+  }
+
+  #[test]
+  fn reselboard_new_checks_grid() {
+    assert!(matches!(ReselBoard::new(vec![]), Err(ResoError::EmptyBoard)));
+    assert!(matches!(ReselBoard::new(vec![vec![]]), Err(ResoError::EmptyBoard)));
+    assert!(matches!(
+      ReselBoard::new(vec![vec![Resel::AND; 3], vec![Resel::XOR; 2]]),
+      Err(ResoError::RaggedBoard { x: 1, len: 2, height: 3 })
+    ));
+
+    let rb = ReselBoard::new(vec![vec![Resel::AND; 3]; 2]).unwrap();
+    assert_eq!((rb.width, rb.height), (2, 3));
+  }
+
+  #[test]
+  fn text_roundtrip_half_adder() {
+    let from_image = ReselBoard::from_filename("./src/testing/test_half_adder.png").unwrap();
+    let from_text  = ReselBoard::from_filename("./src/testing/test_half_adder.txt").unwrap();
+    assert_eq!(from_image.board, from_text.board);
+    assert_eq!(
+      from_text.to_text(),
+      std::fs::read_to_string("./src/testing/test_half_adder.txt").unwrap()
+    );
+  }
+
+  #[test]
+  fn text_options() {
+    let text = "o+&\nO+\nx";
+
+    // Defaults: pad ragged lines, unknown characters are empty
+    let rb = ReselBoard::from_text(text, &TextOptions::default()).unwrap();
+    assert_eq!((rb.width, rb.height), (3, 3));
+    assert_eq!(rb.board[0], vec![Resel::WireOrangeOff, Resel::WireOrangeOn, Resel::Empty]);
+    assert_eq!(rb.board[2], vec![Resel::AND, Resel::Empty, Resel::Empty]);
+    assert_eq!(rb.to_text(), "o+&\nO+ \n   \n");
+
+    assert!(matches!(
+      ReselBoard::from_text(text, &TextOptions::strict()),
+      Err(ResoError::RaggedLine { line: 2, len: 2, width: 3 })
+    ));
+    assert!(matches!(
+      ReselBoard::from_text("o+&\nx+&", &TextOptions::strict()),
+      Err(ResoError::UnknownChar { line: 2, column: 1, c: 'x' })
+    ));
+    assert!(matches!(
+      ReselBoard::from_text("", &TextOptions::default()),
+      Err(ResoError::EmptyBoard)
+    ));
+  }
+
+  #[test]
+  fn render_image_roundtrip() {
+    for filename in [
+      "./src/testing/test_half_adder.png",
+      "./src/testing/test_half_adder_01.png",
+      "./reso_logo.png",
+    ] {
+      let image = load_image_from_filename(filename).unwrap();
+      let rb = ReselBoard::from(image.clone());
+      let rendered = rb.to_image(Rgba([0, 0, 0, 255]));
+      assert_eq!(rendered.dimensions(), image.dimensions());
+      assert_eq!(image_to_vecvecresel(&rendered), rb.board);
+    }
+  }
+
+  #[test]
+  fn render_image_empty_color() {
+    let mut rb = ReselBoard::from_text("o \n&^", &TextOptions::default()).unwrap();
+    assert!(rb.image.is_none());
+    rb.render_image(Rgba([255, 255, 255, 0]));
+
+    let image = rb.image.as_ref().unwrap();
+    assert_eq!(image.dimensions(), (2, 2));
+    assert_eq!(image.get_pixel(0, 0), <Rgba<u8>>::from(Resel::WireOrangeOff));
+    assert_eq!(image.get_pixel(1, 0), Rgba([255, 255, 255, 0]));
+    assert_eq!(image.get_pixel(0, 1), <Rgba<u8>>::from(Resel::AND));
+    assert_eq!(image.get_pixel(1, 1), <Rgba<u8>>::from(Resel::XOR));
+  }
+
   #[test]
   fn load_and_convert_image_test_01() {
     let img = load_image_from_filename("./src/testing/test_01_new-palette.png").unwrap();
@@ -354,7 +914,7 @@ mod reselboard_tests {
       ),
     ] {
       let neighbors_1: HashSet<(usize, usize)> = get_neighbors(
-        resel.delta_neighbors(), x, y, width, height
+        resel.delta_neighbors(), x, y, width, height, Topology::Torus
       ).into_iter().collect();
 
       let neighbors_2: HashSet<(usize, usize)> = neighbors.into_iter().collect();
@@ -362,6 +922,95 @@ mod reselboard_tests {
 
     }
   }
+
+  #[test]
+  fn test_topology_neighbor() {
+    // From the top-left corner of a 10x10 board, going up-left
+    for (topology, expected) in [
+      (Topology::Torus,     Some((9,9))),
+      (Topology::Bounded,   None),
+      (Topology::HCylinder, None),
+      (Topology::VCylinder, None),
+    ] {
+      assert_eq!(topology.neighbor(0, 0, -1, -1, 10, 10), expected);
+    }
+    // Going just left, or just up
+    assert_eq!(Topology::HCylinder.neighbor(0, 5, -1, 0, 10, 10), Some((9,5)));
+    assert_eq!(Topology::HCylinder.neighbor(5, 0, 0, -1, 10, 10), None);
+    assert_eq!(Topology::VCylinder.neighbor(0, 5, -1, 0, 10, 10), None);
+    assert_eq!(Topology::VCylinder.neighbor(5, 0, 0, -1, 10, 10), Some((5,9)));
+
+    for (topology, name) in TOPOLOGY_NAMES {
+      assert_eq!(name.parse::<Topology>(), Ok(topology));
+      assert_eq!(topology.to_string(), name);
+    }
+    assert!("sphere".parse::<Topology>().is_err());
+  }
+
+  #[test]
+  fn test_mixed_wire_policy_names() {
+    for (policy, name) in MIXED_WIRE_POLICY_NAMES {
+      assert_eq!(name.parse::<MixedWirePolicy>(), Ok(policy));
+      assert_eq!(policy.to_string(), name);
+    }
+    assert!("some-on".parse::<MixedWirePolicy>().is_err());
+  }
+
+  #[test]
+  fn test_scale() {
+    let original = ReselBoard::from_filename("./src/testing/test_half_adder.png").unwrap();
+    assert_eq!(original.detect_scale(), 1);
+
+    let upscaled = DynamicImage::ImageRgba8(
+      crate::animation::upscale_image(original.image.as_ref().unwrap(), 6)
+    );
+    let rb = image_to_reselboard(upscaled.clone());
+    assert_eq!(rb.detect_scale(), 6);
+
+    // Downscaling by 6, or by 2 then 3, gets the original back
+    for scales in [vec![6], vec![2, 3]] {
+      let rb = scales.iter().fold(rb.clone(), |rb, scale| rb.with_scale(*scale).unwrap());
+      assert_eq!(rb.board, original.board);
+      assert_eq!((rb.width, rb.height, rb.scale), (original.width, original.height, 6));
+      assert_eq!(rb.image.unwrap().to_rgba8(), original.image.as_ref().unwrap().to_rgba8());
+    }
+
+    assert!(matches!(rb.clone().with_scale(0), Err(ResoError::Scale(_))));
+    assert!(matches!(rb.clone().with_scale(4), Err(ResoError::Scale(_))));
+    assert!(matches!(original.clone().with_scale(original.width), Err(ResoError::Scale(_))));
+    assert_eq!(original.clone().with_scale(1).unwrap().board, original.board);
+  }
+
+  #[test]
+  fn test_snap_image() {
+    // Drift every palette pixel a little, like a JPEG would
+    let original = ReselBoard::from_filename("./src/testing/test_half_adder.png").unwrap();
+    let image = original.image.as_ref().unwrap();
+    let (width, height) = image.dimensions();
+    let drifted = RgbaImage::from_fn(width, height, |x, y| {
+      let mut pixel = image.get_pixel(x, y);
+      if Resel::from(pixel) != Resel::Empty {
+        let drift = ((x * 7 + y * 13) % 11) as i32 - 5;
+        for c in 0..3 {
+          pixel[c] = (pixel[c] as i32 + drift).clamp(0, 255) as u8;
+        }
+      }
+      pixel
+    });
+
+    let mut rb = image_to_reselboard(DynamicImage::ImageRgba8(drifted));
+    assert_ne!(rb.board, original.board);
+
+    let report = rb.snap_image(&ColorMatching { tolerance: 16, ..Default::default() }).unwrap();
+    assert_eq!(rb.board, original.board);
+    assert_eq!(report.exact + report.snapped + report.unmatched, (width * height) as usize);
+    assert!(report.snapped > 0);
+
+    // Without a tolerance nothing snaps, and boards without images can't
+    let report = rb.snap_image(&ColorMatching::default()).unwrap();
+    assert_eq!(report.snapped, 0);
+    assert_eq!(ReselBoard::new(vec![vec![Resel::AND]]).unwrap().snap_image(&ColorMatching::default()), None);
+  }
 }
 
 // eof
\ No newline at end of file
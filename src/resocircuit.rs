@@ -2,6 +2,8 @@
 //! 
 //! Example:
 //! ```rust
+//! use reso::{ReselBoard, ResoCircuit, load_image_from_filename};
+//! 
 //! let mut rc = ResoCircuit::from(
 //!   ReselBoard::from(
 //!     load_image_from_filename(
@@ -17,14 +19,13 @@
 
 use image::{DynamicImage, Rgba, GenericImage};
 
+use crate::error::{ResoError};
 use crate::resel::{Resel};
-use crate::reselboard::{
-  ReselBoard,
-  load_image_from_filename,
-  load_image_from_filename_string
-};
+use crate::reselboard::{ReselBoard};
 use crate::regionmap::{RegionMap};
 use crate::incidencemap::{IncidenceMap};
+use crate::ports::{Port, PortDirection, PortSpec, load_ports_file};
+use crate::serialize::{is_compiled_filename};
 
 /*
 - maintains to iterate:
@@ -74,17 +75,44 @@ pub struct ResoCircuit {
   pub im: IncidenceMap,
 
   pub wire_state:   Vec<bool>,
+  /// The value on each bus, in the order of rm.bus_regions
+  pub bus_state:    Vec<u8>,
+
+  /// Named external inputs/outputs. See ports.rs
+  pub ports: Vec<Port>,
 
   // Internal state used during .iterate()
   // (pre-allocated!)
   input_state:  Vec<Vec<bool>>,
   logic_state:  Vec<bool>,
   output_state: Vec<bool>,
+  bus_input_state:  Vec<Vec<u8>>,
+  logic_bus_state:  Vec<u8>,
+  bus_output_state: Vec<u8>,
 }
 
 impl From<ReselBoard> for ResoCircuit {
+  /// Compile a board. MixedWirePolicy::Error is treated like FirstPixel
+  /// here; use `ResoCircuit::compile` to have it fail.
   fn from (rb: ReselBoard) -> ResoCircuit {
     let rm = RegionMap::from(&rb);
+    ResoCircuit::from_region_map(rb, rm)
+  }
+}
+
+// todo: impl from image, str vec?
+
+impl ResoCircuit{
+
+  /// Compile a ReselBoard, like `ResoCircuit::from`, but fail if there are
+  /// mixed on/off wire regions and the board's policy is MixedWirePolicy::Error
+  pub fn compile(rb: ReselBoard) -> Result<ResoCircuit, ResoError> {
+    let rm = RegionMap::try_from_board(&rb)?;
+    Ok(ResoCircuit::from_region_map(rb, rm))
+  }
+
+  /// Finish compiling `rb`, already labelled as `rm`
+  fn from_region_map(rb: ReselBoard, rm: RegionMap) -> ResoCircuit {
     let im = IncidenceMap::from(&rm);
 
     /* get state of all the wires
@@ -95,16 +123,10 @@ impl From<ReselBoard> for ResoCircuit {
       for region in rm.wire_regions
     ]
 
-    regionmap currently takes wire region state as the
-    first pixel (on or off) it encounters.
-
-    this means something like `oOOOO` would be recorded
-    as WireOrangeOff, because `o` would be encountered first.
-
-    it _should_ update the entire region state on the subsequent
-    `O` but we don't.
-
-    this won't be a problem for nicely-defined circuits
+    a region with mixed on/off resels like `oOOOO` has already
+    been resolved to on or off by rb.mixed_wire_policy.
+    (MixedWirePolicy::Error leaves it as the first resel, `o`;
+    see RegionMap::try_from_board.)
     */
     let wire_state: Vec<bool> = rm.wire_regions.iter().map(
       |region| {
@@ -113,6 +135,16 @@ impl From<ReselBoard> for ResoCircuit {
       }
     ).collect();
 
+    ResoCircuit::from_parts(rb, rm, im, wire_state)
+  }
+
+  /// Assemble a circuit from an already-compiled RegionMap and IncidenceMap,
+  /// e.g. when deserializing. Doesn't check they match `rb`!
+  /// Buses start with the values they're drawn with, and GPIO wires get
+  /// their ports (see gpio.rs).
+  pub fn from_parts(
+    rb: ReselBoard, rm: RegionMap, im: IncidenceMap, wire_state: Vec<bool>
+  ) -> ResoCircuit {
     /* prepare to allocate state for all the inputs
     input_state = Vec<Vec<bool>> of false
       in same shape as im.input_inc_wires
@@ -128,22 +160,42 @@ impl From<ReselBoard> for ResoCircuit {
     let logic_state  = vec![false; rm.logic_regions.len()];
     let output_state = vec![false; rm.output_regions.len()];
 
-    ResoCircuit{
-      rb: rb,
-      rm: rm,
-      im: im,
+    let bus_state: Vec<u8> = rm.bus_regions.iter().map(
+      |region| rm.region_to_resel[*region].bus_value().unwrap()
+    ).collect();
+    let bus_input_state: Vec<Vec<u8>> = im.bus_input_inc_buses
+      .iter().map(|inc_buses| vec![0; inc_buses.len()]).collect();
+    let logic_bus_state  = vec![0; rm.logic_regions.len()];
+    let bus_output_state = vec![0; rm.bus_output_regions.len()];
+
+    let mut rc = ResoCircuit{
+      rb,
+      rm,
+      im,
+
+      wire_state,
+      bus_state,
+      ports: vec![],
+      input_state,
+      logic_state,
+      output_state,
+      bus_input_state,
+      logic_bus_state,
+      bus_output_state,
+    };
+    rc.add_gpio_ports();
+    rc
+  }
 
-      wire_state: wire_state,
-      input_state: input_state,
-      logic_state: logic_state,
-      output_state: output_state,
+  /// Load and compile a circuit from a file. Compiled `.resoc` circuits
+  /// are loaded as-is, anything else goes through `ReselBoard::from_filename`.
+  pub fn from_filename(filename: &str) -> Result<ResoCircuit, ResoError> {
+    if is_compiled_filename(filename) {
+      ResoCircuit::from_compiled_filename(filename)
+    } else {
+      ResoCircuit::compile(ReselBoard::from_filename(filename)?)
     }
   }
-}
-
-// todo: impl from image, str vec, file?
-
-impl ResoCircuit{
 
   /// Convenience function: Reset input_state, logic_state, output_state between iterations
   fn reset_intermediate_state(&mut self) {
@@ -191,17 +243,15 @@ impl ResoCircuit{
         // ii = input_index
         
         if self.rm.region_to_resel[lri] == Resel::AND {
-          self.logic_state[li] = (
-            self.logic_state[li] || self.input_state[*ii].iter().fold(
-              true, |acc, &x| acc && x // AND over inputs incident wires
-            )
-          );
+          self.logic_state[li] =
+            self.logic_state[li] || self.input_state[*ii].iter().all(
+              |&x| x // AND over inputs incident wires
+            );
         } else if self.rm.region_to_resel[lri] == Resel::XOR {
-          self.logic_state[li] = (
+          self.logic_state[li] =
             self.logic_state[li] || self.input_state[*ii].iter().fold(
               false, |acc, &x| acc ^ x // XOR over inputs incident wires
-            )
-          );
+            );
         } else {
           panic!(
             "rc.rm.region_to_resel[lri={}]={:?} is not logic?!?",
@@ -215,11 +265,10 @@ impl ResoCircuit{
     // Collect output state from incident inputs
     for (oi, inc_inputs) in self.im.output_inc_inputs.iter().enumerate() {
       for ii in inc_inputs.iter() {
-        self.output_state[oi] = (
-          self.output_state[oi] || self.input_state[*ii].iter().fold(
-            false, |acc, &x| acc || x // OR over input incident wires
+        self.output_state[oi] =
+          self.output_state[oi] || self.input_state[*ii].iter().any(
+            |&x| x // OR over input incident wires
           )
-        )
       }
     }
 
@@ -238,8 +287,153 @@ impl ResoCircuit{
       }
     }
 
+    // Input ports hold their wires, whatever the incident outputs say
+    self.apply_held_inputs();
+
     // Cleanup
-    self.reset_intermediate_state()
+    self.reset_intermediate_state();
+
+    // Buses never touch wires, so they can go after
+    self.iterate_buses()
+  }
+
+  /// Simulate one iteration of the buses only. (Part of `iterate()`.)
+  ///
+  /// Buses work like wires, bit by bit: bus inputs pass on every incident
+  /// bus, logic takes the bitwise AND or XOR of each bus input's buses, and
+  /// bus outputs and buses take the bitwise OR of everything incident.
+  pub fn iterate_buses(&mut self) {
+    for (bii, inc_buses) in self.im.bus_input_inc_buses.iter().enumerate() {
+      for (inc_bi, bi) in inc_buses.iter().enumerate() {
+        self.bus_input_state[bii][inc_bi] = self.bus_state[*bi]
+      }
+    }
+
+    for (li, inc_bus_inputs) in self.im.logic_inc_bus_inputs.iter().enumerate() {
+      let is_and = self.rm.region_to_resel[self.rm.logic_regions[li]] == Resel::AND;
+      for bii in inc_bus_inputs {
+        let values = self.bus_input_state[*bii].iter();
+        self.logic_bus_state[li] |= if is_and {
+          values.fold(u8::MAX, |acc, value| acc & value)
+        } else {
+          values.fold(0, |acc, value| acc ^ value)
+        };
+      }
+    }
+
+    for (boi, inc_bus_inputs) in self.im.bus_output_inc_bus_inputs.iter().enumerate() {
+      for bii in inc_bus_inputs {
+        self.bus_output_state[boi] |= self.bus_input_state[*bii].iter().fold(0, |acc, value| acc | value)
+      }
+    }
+    for (boi, inc_logics) in self.im.bus_output_inc_logics.iter().enumerate() {
+      for li in inc_logics {
+        self.bus_output_state[boi] |= self.logic_bus_state[*li]
+      }
+    }
+
+    for (bi, inc_bus_outputs) in self.im.bus_inc_bus_outputs.iter().enumerate() {
+      self.bus_state[bi] = inc_bus_outputs.iter()
+        .fold(0, |acc, boi| acc | self.bus_output_state[*boi]);
+    }
+
+    // Cleanup
+    self.logic_bus_state.fill(0);
+    self.bus_output_state.fill(0);
+  }
+
+  /// Set each held input port's wire to its held value
+  fn apply_held_inputs(&mut self) {
+    for port in &self.ports {
+      if let Some(value) = port.held {
+        self.wire_state[port.wire] = value
+      }
+    }
+  }
+
+  /// Get the dense wire index of the wire region at (x,y)
+  pub fn wire_at(&self, x: usize, y: usize) -> Result<usize, ResoError> {
+    if x >= self.rm.width || y >= self.rm.height {
+      return Err(ResoError::OutOfBounds { x, y })
+    }
+    let ri = self.rm.xy_to_region[x][y];
+    if !self.rm.region_to_resel[ri].is_wire() {
+      return Err(ResoError::NotAWire { x, y })
+    }
+    Ok(self.rm.reverse_dense[ri])
+  }
+
+  /// Get the dense bus index of the bus region at (x,y)
+  pub fn bus_at(&self, x: usize, y: usize) -> Result<usize, ResoError> {
+    if x >= self.rm.width || y >= self.rm.height {
+      return Err(ResoError::OutOfBounds { x, y })
+    }
+    let ri = self.rm.xy_to_region[x][y];
+    if !self.rm.region_to_resel[ri].is_bus() {
+      return Err(ResoError::NotABus { x, y })
+    }
+    Ok(self.rm.reverse_dense[ri])
+  }
+
+  /// Attach a port to the wire region under (spec.x, spec.y)
+  pub fn add_port(&mut self, spec: &PortSpec) -> Result<(), ResoError> {
+    let wire = self.wire_at(spec.x, spec.y)?;
+    self.ports.retain(|port| port.name != spec.name);
+    self.ports.push(Port {
+      direction: spec.direction,
+      name: spec.name.clone(),
+      wire,
+      held: None,
+    });
+    Ok(())
+  }
+
+  /// Attach every port in a ports file
+  pub fn load_ports(&mut self, filename: &str) -> Result<(), ResoError> {
+    for spec in load_ports_file(filename)? {
+      self.add_port(&spec)?;
+    }
+    Ok(())
+  }
+
+  /// Find a port by name
+  pub fn port(&self, name: &str) -> Result<&Port, ResoError> {
+    self.ports.iter().find(|port| port.name == name)
+      .ok_or_else(|| ResoError::UnknownPort(name.to_string()))
+  }
+
+  /// Drive an input port. Its wire takes `value` now, and is held at
+  /// `value` after every iteration until set again.
+  pub fn set_input(&mut self, name: &str, value: bool) -> Result<(), ResoError> {
+    let port = self.ports.iter_mut().find(|port| port.name == name)
+      .ok_or_else(|| ResoError::UnknownPort(name.to_string()))?;
+    if port.direction != PortDirection::Input {
+      return Err(ResoError::WrongPortDirection(name.to_string()))
+    }
+    port.held = Some(value);
+    self.wire_state[port.wire] = value;
+    Ok(())
+  }
+
+  /// Stop driving an input port; its wire is updated like any other again
+  pub fn release_input(&mut self, name: &str) -> Result<(), ResoError> {
+    let port = self.ports.iter_mut().find(|port| port.name == name)
+      .ok_or_else(|| ResoError::UnknownPort(name.to_string()))?;
+    port.held = None;
+    Ok(())
+  }
+
+  /// Read the wire state of a port. (Any port can be read, input or output.)
+  pub fn get_output(&self, name: &str) -> Result<bool, ResoError> {
+    Ok(self.wire_state[self.port(name)?.wire])
+  }
+
+  /// Names and values of every output port, in the order they were added
+  pub fn outputs(&self) -> Vec<(&str, bool)> {
+    self.ports.iter()
+      .filter(|port| port.direction == PortDirection::Output)
+      .map(|port| (port.name.as_str(), self.wire_state[port.wire]))
+      .collect()
   }
 
 
@@ -248,9 +442,36 @@ impl ResoCircuit{
     self.rb.image.as_ref()
   }
 
+  /// Get the Resel a wire region should be drawn as, given its wire_state
+  pub fn wire_resel(&self, wi: usize) -> Resel {
+    let on = self.wire_state[wi];
+    match self.rm.region_to_resel[self.rm.wire_regions[wi]] {
+      Resel::WireOrangeOn | Resel::WireOrangeOff => {
+        if on {Resel::WireOrangeOn} else {Resel::WireOrangeOff}
+      },
+      Resel::WireSapphireOn | Resel::WireSapphireOff => {
+        if on {Resel::WireSapphireOn} else {Resel::WireSapphireOff}
+      },
+      Resel::WireLimeOn | Resel::WireLimeOff => {
+        if on {Resel::WireLimeOn} else {Resel::WireLimeOff}
+      },
+      Resel::GpioOn | Resel::GpioOff => {
+        if on {Resel::GpioOn} else {Resel::GpioOff}
+      },
+      _ => {
+        panic!("Oh no, ResoCircuit found a wire_region pointing to something not a wire. This shouldn't be possible.");
+      }
+    }
+  }
+
+  /// Get the Resel a bus region should be drawn as, given its bus_state
+  pub fn bus_resel(&self, bi: usize) -> Resel {
+    Resel::Bus(self.bus_state[bi])
+  }
+
   /// Update the pixels stored in the image, if it exists
   pub fn update_pixels(&mut self) {
-    if self.rb.image == None {
+    if self.rb.image.is_none() {
       // Return early if no image
       return
     }
@@ -258,36 +479,54 @@ impl ResoCircuit{
     // Let's iterate over every wire region and update its pixels
     for (wi, ri) in self.rm.wire_regions.iter().enumerate() {
       // First, get the Resel class we want to update to.
-      // (todo: This should be less ugly)
-      let update_to_resel = match self.rm.region_to_resel[*ri] {
-        Resel::WireOrangeOn | Resel::WireOrangeOff => {
-          if self.wire_state[wi] {Resel::WireOrangeOn} else {Resel::WireOrangeOff}
-        },
-        Resel::WireSapphireOn | Resel::WireSapphireOff => {
-          if self.wire_state[wi] {Resel::WireSapphireOn} else {Resel::WireSapphireOff}
-        },
-        Resel::WireLimeOn | Resel::WireLimeOff => {
-          if self.wire_state[wi] {Resel::WireLimeOn} else {Resel::WireLimeOff}
-        },
-        _ => {
-          panic!("Oh no, ResoCircuit.update_pixels() found a wire_region pointing to something not a wire. This shouldn't be possible.");
-        }
-      };
-      let update_to_pixel = <Rgba<u8>>::from(update_to_resel);
+      let update_to_pixel = self.rb.palette.to_rgba(self.wire_resel(wi)).unwrap();
 
       // Now, let's update all the pixels in the region
       for (x,y) in &self.rm.region_to_xys[*ri] {
         self.rb.image.as_mut().unwrap().put_pixel(*x as u32, *y as u32, update_to_pixel);
       }
     }
+
+    // ... and every bus region
+    for (bi, ri) in self.rm.bus_regions.iter().enumerate() {
+      let update_to_pixel = self.rb.palette.to_rgba(self.bus_resel(bi)).unwrap();
+      for (x,y) in &self.rm.region_to_xys[*ri] {
+        self.rb.image.as_mut().unwrap().put_pixel(*x as u32, *y as u32, update_to_pixel);
+      }
+    }
+  }
+
+  /// Render rb.image from the current state, replacing any existing image.
+  /// Lets circuits built from text or a Vec<Vec<Resel>> produce frames.
+  pub fn render_image(&mut self, empty: Rgba<u8>) {
+    self.update_board();
+    self.rb.render_image(empty);
+  }
+
+  /// Update the resels stored in the board, e.g. before `rb.to_text()`
+  pub fn update_board(&mut self) {
+    for (wi, ri) in self.rm.wire_regions.iter().enumerate() {
+      let update_to_resel = self.wire_resel(wi);
+      for (x,y) in &self.rm.region_to_xys[*ri] {
+        self.rb.board[*x][*y] = update_to_resel;
+      }
+    }
+    for (bi, ri) in self.rm.bus_regions.iter().enumerate() {
+      let update_to_resel = self.bus_resel(bi);
+      for (x,y) in &self.rm.region_to_xys[*ri] {
+        self.rb.board[*x][*y] = update_to_resel;
+      }
+    }
   }
 }
 
 #[cfg(test)]
 mod resocircuit_tests {
-  use crate::reselboard::load_image_from_filename_string;
-
-use super::*;
+  use super::*;
+  use crate::reselboard::{
+    load_image_from_filename,
+    load_image_from_filename_string,
+  };
 
   #[test]
   fn test_iterate_halfadder() {
@@ -339,6 +578,123 @@ use super::*;
     ); 
   }
 
+  #[test]
+  fn test_update_board_halfadder_text() {
+    // The text circuit should step the same way as the image
+    let mut rc_txt = ResoCircuit::from_filename("./src/testing/test_half_adder.txt").unwrap();
+    let mut rc_png = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    for _ in 0..4 {
+      rc_txt.iterate();
+      rc_png.iterate();
+      rc_txt.update_board();
+      assert_eq!(rc_txt.wire_state, rc_png.wire_state);
+    }
+    assert_eq!(
+      rc_txt.rb.to_text(),
+      "        \n    =lll\nooo+^   \nsss+&   \n    =lll\n        \n"
+    );
+  }
+
+  #[test]
+  fn test_render_image_halfadder_text() {
+    // Rendered from its resels alone, as text circuits are, the half adder
+    // should step like the image does
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder_01.png").unwrap();
+    let mut rc_rendered = ResoCircuit::from(
+      ReselBoard::from(rc.rb.board.clone())
+    );
+    rc_rendered.render_image(Rgba([0, 0, 0, 255]));
+    for _ in 0..2 {
+      rc.iterate();
+      rc.update_pixels();
+      rc_rendered.iterate();
+      rc_rendered.update_pixels();
+    }
+    assert_eq!(
+      rc_rendered.get_image().unwrap().to_rgba8(),
+      rc.get_image().unwrap().to_rgba8(),
+    );
+  }
+
+  #[test]
+  fn test_ports_halfadder() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+      rc.set_input("a", a).unwrap();
+      rc.set_input("b", b).unwrap();
+      // Inputs stay held, so the outputs settle and stay put
+      for _ in 0..3 {
+        rc.iterate();
+        assert_eq!(rc.get_output("a").unwrap(), a);
+        assert_eq!(rc.get_output("b").unwrap(), b);
+        assert_eq!(rc.outputs(), vec![("sum", a ^ b), ("carry", a && b)]);
+      }
+    }
+
+    // Released inputs go back to being driven by (no) outputs
+    rc.release_input("a").unwrap();
+    rc.iterate();
+    assert!(!rc.get_output("a").unwrap());
+    assert!(rc.get_output("b").unwrap());
+  }
+
+  #[test]
+  fn test_ports_errors() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+
+    assert!(matches!(rc.set_input("nope", true), Err(ResoError::UnknownPort(_))));
+    assert!(matches!(rc.set_input("sum", true), Err(ResoError::WrongPortDirection(_))));
+    assert!(matches!(rc.get_output("nope"), Err(ResoError::UnknownPort(_))));
+
+    let spec = |x, y| PortSpec { direction: PortDirection::Input, name: "c".to_string(), x, y };
+    assert!(matches!(rc.add_port(&spec(3, 2)), Err(ResoError::NotAWire { x: 3, y: 2 })));
+    assert!(matches!(rc.add_port(&spec(0, 0)), Err(ResoError::NotAWire { x: 0, y: 0 })));
+    assert!(matches!(rc.add_port(&spec(99, 0)), Err(ResoError::OutOfBounds { x: 99, y: 0 })));
+  }
+
+  #[test]
+  fn test_compile_mixed_wires() {
+    use crate::reselboard::{MixedWirePolicy};
+    // test_06 has a mixed orange wire at (0,2)
+    let rb = ReselBoard::from_filename("./src/testing/test_06.png").unwrap();
+    assert!(ResoCircuit::compile(rb.clone()).is_ok());
+    assert!(matches!(
+      ResoCircuit::compile(rb.with_mixed_wire_policy(MixedWirePolicy::Error)),
+      Err(ResoError::MixedWire { x: 0, y: 2, .. })
+    ));
+  }
+
+  #[test]
+  fn test_iterate_buses() {
+    use crate::reselboard::{TextOptions, Topology};
+    let compile = |text: &str| ResoCircuit::from(
+      ReselBoard::from_text(text, &TextOptions::strict()).unwrap().with_topology(Topology::Bounded)
+    );
+
+    // 12 and 10 through one bus input into AND or XOR, out to a third bus.
+    // Nothing drives the first two, so they go to 0.
+    for (logic, expected) in [('&', 8), ('^', 6)] {
+      let mut rc = compile(&format!("⠌   \n{{{}}}⠀\n⠊   ", logic));
+      let out = rc.bus_at(3, 1).unwrap();
+      rc.iterate();
+      assert_eq!(rc.bus_state[out], expected);
+      assert_eq!(rc.bus_state.iter().filter(|value| **value == 0).count(), 2);
+      rc.update_board();
+      assert_eq!(rc.rb.board[3][1], Resel::Bus(expected));
+    }
+
+    // A bus feeding itself holds its value
+    let mut rc = compile("⠅⠅\n{⠅\n}⠅");
+    for _ in 0..3 {
+      rc.iterate();
+      assert_eq!(rc.bus_state, vec![5]);
+    }
+    assert!(matches!(rc.bus_at(0, 1), Err(ResoError::NotABus { x: 0, y: 1 })));
+  }
+
   #[test]
   fn test_reso_logo() {
     let mut rc = ResoCircuit::from(
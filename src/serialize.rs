@@ -0,0 +1,847 @@
+//! serialize.rs: Save and load compiled ResoCircuits and their state.
+//!
+//! Compiling a big image repeats the flood fill in RegionMap and the
+//! adjacency search in IncidenceMap on every run. A compiled circuit
+//! (`.resoc`) stores the results so they can be loaded directly.
+//!
+//! A state snapshot (`.resos`) stores just the wire state and held input
+//! ports of a circuit, so a long simulation can be stopped and resumed.
+//!
+//! Both are little-endian binary formats, starting with a magic string and a
+//! format version. Any change to the layout below must bump the version.
+//!
+//! `.resoc`, version 1:
+//!
+//! | Field            | Encoding                                             |
+//! | ---------------- | ---------------------------------------------------- |
+//! | magic            | `RESOC`                                              |
+//! | version          | u32                                                  |
+//! | width, height    | u32, u32                                             |
+//! | topology         | u8, index into TOPOLOGY_NAMES                        |
+//! | palette          | PALETTE_LEN RGBA colours, 4 u8s each                 |
+//! | board            | width*height resels, `[x][y]`                        |
+//! | image            | u8 flag, then (if 1) u32 length + PNG bytes          |
+//! | region_to_resel  | u32 length + resels                                  |
+//! | xy_to_region     | width*height u32s, `[x][y]`                          |
+//! | dense indices    | wire, input, logic, output, bus, bus input, bus output and subcircuit regions; u32 list each |
+//! | reverse_dense    | u32 list                                             |
+//! | incidences       | the ten IncidenceMap lists; u32 list of u32 lists    |
+//! | wire_state       | u32 length + bit-packed bytes                        |
+//! | bus_state        | u32 length + one u8 per bus                          |
+//! | ports            | u32 count + (u8 direction, string name, u32 wire, u8 held) |
+//!
+//! `region_to_xys` is not stored; it's rebuilt from `xy_to_region`, in
+//! column-major order rather than flood fill order. `mixed_wires` is rebuilt
+//! from the board. (Mixed wire regions were already resolved when compiling.)
+//! Regions inlined from subcircuits have no resels, so they're only in the
+//! lists.
+//!
+//! A resel is a u8 index into PALETTE_RESEL, or 11 for a bus input, 12 for
+//! a bus output, 13 for a bus followed by a u8 value, 14 and 15 for a GPIO
+//! wire off and on, and 16 for a subcircuit.
+//!
+//! `.resos`, version 1:
+//!
+//! | Field       | Encoding                                  |
+//! | ----------- | ----------------------------------------- |
+//! | magic       | `RESOS`                                   |
+//! | version     | u32                                       |
+//! | wire_state  | u32 length + bit-packed bytes             |
+//! | held ports  | u32 count + (string name, u8 held)        |
+//! | bus_state   | u32 length + one u8 per bus               |
+//!
+//! `held` is 0 for not held, 1 for held off, and 2 for held on. Strings are
+//! a u32 length and UTF-8 bytes.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! let bytes = rc.to_bytes();
+//! let loaded = ResoCircuit::from_bytes(&bytes).unwrap();
+//! assert_eq!(loaded.wire_state, rc.wire_state);
+//! ```
+
+use std::io::{Cursor};
+
+use crate::error::{ResoError};
+use crate::resel::{Resel, PALETTE_RESEL};
+use crate::palette::{Palette, PALETTE_LEN};
+use crate::reselboard::{ReselBoard, Topology, TOPOLOGY_NAMES};
+use crate::regionmap::{RegionMap, find_mixed_wires};
+use crate::incidencemap::{IncidenceMap};
+use crate::ports::{Port, PortDirection};
+use crate::resocircuit::{ResoCircuit};
+
+pub const CIRCUIT_MAGIC: &[u8] = b"RESOC";
+pub const CIRCUIT_VERSION: u32 = 1;
+pub const STATE_MAGIC: &[u8] = b"RESOS";
+pub const STATE_VERSION: u32 = 1;
+
+// Resel codes after the PALETTE_RESEL indices
+const BUS_INPUT_CODE: u8 = 11;
+const BUS_OUTPUT_CODE: u8 = 12;
+const BUS_CODE: u8 = 13;
+const GPIO_OFF_CODE: u8 = 14;
+const GPIO_ON_CODE: u8 = 15;
+const SUBCIRCUIT_CODE: u8 = 16;
+
+/// True if `filename` ends in `.resoc`, meaning it's a compiled circuit
+pub fn is_compiled_filename(filename: &str) -> bool {
+  std::path::Path::new(filename).extension()
+    .is_some_and(|ext| ext.eq_ignore_ascii_case("resoc"))
+}
+
+fn format_error(message: &str) -> ResoError {
+  ResoError::Format(message.to_string())
+}
+
+/// Appends little-endian values to a Vec<u8>
+struct Writer {
+  bytes: Vec<u8>,
+}
+
+impl Writer {
+  fn u8(&mut self, value: u8) { self.bytes.push(value) }
+
+  fn u32(&mut self, value: usize) {
+    // Sizes and indices are usize in memory, but u32 on disk
+    self.bytes.extend_from_slice(&(value as u32).to_le_bytes())
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) {
+    self.u32(bytes.len());
+    self.bytes.extend_from_slice(bytes);
+  }
+
+  fn string(&mut self, string: &str) { self.bytes(string.as_bytes()) }
+
+  fn usizes(&mut self, values: &[usize]) {
+    self.u32(values.len());
+    for value in values { self.u32(*value) }
+  }
+
+  fn usizess(&mut self, values: &[Vec<usize>]) {
+    self.u32(values.len());
+    for value in values { self.usizes(value) }
+  }
+
+  fn bools(&mut self, values: &[bool]) {
+    self.u32(values.len());
+    let mut packed = vec![0u8; values.len().div_ceil(8)];
+    for (idx, &value) in values.iter().enumerate() {
+      if value { packed[idx / 8] |= 1 << (idx % 8) }
+    }
+    self.bytes.extend_from_slice(&packed);
+  }
+
+  fn held(&mut self, held: Option<bool>) {
+    self.u8(match held { None => 0, Some(false) => 1, Some(true) => 2 })
+  }
+
+  fn resel(&mut self, resel: Resel) {
+    match resel {
+      Resel::BusInput  => self.u8(BUS_INPUT_CODE),
+      Resel::BusOutput => self.u8(BUS_OUTPUT_CODE),
+      Resel::GpioOff   => self.u8(GPIO_OFF_CODE),
+      Resel::GpioOn    => self.u8(GPIO_ON_CODE),
+      Resel::Subcircuit => self.u8(SUBCIRCUIT_CODE),
+      Resel::Bus(value) => {
+        self.u8(BUS_CODE);
+        self.u8(value);
+      },
+      _ => self.u8(PALETTE_RESEL.iter().position(|&r| r == resel).unwrap() as u8),
+    }
+  }
+}
+
+/// Reads little-endian values from a &[u8], failing on truncated input
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], ResoError> {
+    let end = self.position.checked_add(len)
+      .filter(|&end| end <= self.bytes.len())
+      .ok_or_else(|| format_error("unexpected end of file"))?;
+    let taken = &self.bytes[self.position..end];
+    self.position = end;
+    Ok(taken)
+  }
+
+  /// How many bytes are left to read
+  fn remaining(&self) -> usize {
+    self.bytes.len() - self.position
+  }
+
+  fn u8(&mut self) -> Result<u8, ResoError> { Ok(self.take(1)?[0]) }
+
+  fn u32(&mut self) -> Result<usize, ResoError> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+  }
+
+  fn bytes(&mut self) -> Result<&'a [u8], ResoError> {
+    let len = self.u32()?;
+    self.take(len)
+  }
+
+  fn string(&mut self) -> Result<String, ResoError> {
+    String::from_utf8(self.bytes()?.to_vec())
+      .map_err(|_| format_error("string is not UTF-8"))
+  }
+
+  fn usizes(&mut self) -> Result<Vec<usize>, ResoError> {
+    let len = self.u32()?;
+    // Don't trust `len` for preallocating; a corrupt file could claim anything
+    let mut values = Vec::with_capacity(len.min(self.bytes.len() / 4));
+    for _ in 0..len { values.push(self.u32()?) }
+    Ok(values)
+  }
+
+  fn usizess(&mut self) -> Result<Vec<Vec<usize>>, ResoError> {
+    let len = self.u32()?;
+    let mut values = Vec::with_capacity(len.min(self.bytes.len() / 4));
+    for _ in 0..len { values.push(self.usizes()?) }
+    Ok(values)
+  }
+
+  fn bools(&mut self) -> Result<Vec<bool>, ResoError> {
+    let len = self.u32()?;
+    let packed = self.take(len.div_ceil(8))?;
+    Ok((0..len).map(|idx| packed[idx / 8] & (1 << (idx % 8)) != 0).collect())
+  }
+
+  fn resel(&mut self) -> Result<Resel, ResoError> {
+    match self.u8()? {
+      BUS_INPUT_CODE  => Ok(Resel::BusInput),
+      BUS_OUTPUT_CODE => Ok(Resel::BusOutput),
+      BUS_CODE => Ok(Resel::Bus(self.u8()?)),
+      GPIO_OFF_CODE   => Ok(Resel::GpioOff),
+      GPIO_ON_CODE    => Ok(Resel::GpioOn),
+      SUBCIRCUIT_CODE => Ok(Resel::Subcircuit),
+      code => PALETTE_RESEL.get(code as usize).copied()
+        .ok_or_else(|| format_error("unknown resel")),
+    }
+  }
+
+  fn topology(&mut self) -> Result<Topology, ResoError> {
+    TOPOLOGY_NAMES.get(self.u8()? as usize).map(|(topology, _)| *topology)
+      .ok_or_else(|| format_error("unknown topology"))
+  }
+
+  fn palette(&mut self) -> Result<Palette, ResoError> {
+    let colors: Vec<image::Rgba<u8>> = (0..PALETTE_LEN)
+      .map(|_| Ok(image::Rgba([self.u8()?, self.u8()?, self.u8()?, self.u8()?])))
+      .collect::<Result<_, ResoError>>()?;
+    Palette::new(&colors)
+  }
+
+  fn held(&mut self) -> Result<Option<bool>, ResoError> {
+    match self.u8()? {
+      0 => Ok(None),
+      1 => Ok(Some(false)),
+      2 => Ok(Some(true)),
+      _ => Err(format_error("bad held value")),
+    }
+  }
+
+  /// Check the magic bytes and the version
+  fn magic_and_version(&mut self, magic: &[u8], version: u32) -> Result<(), ResoError> {
+    if self.take(magic.len()).ok() != Some(magic) {
+      return Err(format_error("wrong magic bytes"))
+    }
+    let found = self.u32()? as u32;
+    if found != version {
+      return Err(ResoError::UnsupportedVersion(found))
+    }
+    Ok(())
+  }
+
+  fn finish(&self) -> Result<(), ResoError> {
+    if self.position != self.bytes.len() {
+      return Err(format_error("trailing bytes at end of file"))
+    }
+    Ok(())
+  }
+}
+
+fn topology_to_u8(topology: Topology) -> u8 {
+  TOPOLOGY_NAMES.iter().position(|(t, _)| *t == topology).unwrap() as u8
+}
+
+/// Check every index in `values` is below `bound`
+fn check_indices(values: &[usize], bound: usize, message: &str) -> Result<(), ResoError> {
+  if values.iter().any(|&value| value >= bound) {
+    return Err(format_error(message))
+  }
+  Ok(())
+}
+
+/// Check every region in `regions` has a resel of the right class
+fn check_classes(
+  regions: &[usize], region_to_resel: &[Resel], is_class: fn(&Resel) -> bool, message: &str
+) -> Result<(), ResoError> {
+  if !regions.iter().all(|&region| is_class(&region_to_resel[region])) {
+    return Err(format_error(message))
+  }
+  Ok(())
+}
+
+impl ResoCircuit {
+  /// Serialize the compiled circuit, its wire state, and its ports
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut w = Writer { bytes: vec![] };
+    w.bytes.extend_from_slice(CIRCUIT_MAGIC);
+    w.u32(CIRCUIT_VERSION as usize);
+
+    // Board and image
+    w.u32(self.rb.width);
+    w.u32(self.rb.height);
+    w.u8(topology_to_u8(self.rb.topology));
+    for color in &self.rb.palette.colors {
+      w.bytes.extend_from_slice(&color.0);
+    }
+    for column in &self.rb.board {
+      for resel in column { w.resel(*resel) }
+    }
+    match &self.rb.image {
+      Some(image) => {
+        let mut png = Cursor::new(vec![]);
+        image.write_to(&mut png, image::ImageOutputFormat::Png)
+          .expect("Writing a PNG to memory should not fail");
+        w.u8(1);
+        w.bytes(png.get_ref());
+      },
+      None => w.u8(0),
+    }
+
+    // RegionMap
+    w.u32(self.rm.region_to_resel.len());
+    for resel in &self.rm.region_to_resel { w.resel(*resel) }
+    for column in &self.rm.xy_to_region {
+      for ri in column { w.u32(*ri) }
+    }
+    w.usizes(&self.rm.wire_regions);
+    w.usizes(&self.rm.input_regions);
+    w.usizes(&self.rm.logic_regions);
+    w.usizes(&self.rm.output_regions);
+    w.usizes(&self.rm.bus_regions);
+    w.usizes(&self.rm.bus_input_regions);
+    w.usizes(&self.rm.bus_output_regions);
+    w.usizes(&self.rm.subcircuit_regions);
+    w.usizes(&self.rm.reverse_dense);
+
+    // IncidenceMap
+    w.usizess(&self.im.input_inc_wires);
+    w.usizess(&self.im.logic_inc_inputs);
+    w.usizess(&self.im.output_inc_inputs);
+    w.usizess(&self.im.output_inc_logics);
+    w.usizess(&self.im.wire_inc_outputs);
+    w.usizess(&self.im.bus_input_inc_buses);
+    w.usizess(&self.im.logic_inc_bus_inputs);
+    w.usizess(&self.im.bus_output_inc_bus_inputs);
+    w.usizess(&self.im.bus_output_inc_logics);
+    w.usizess(&self.im.bus_inc_bus_outputs);
+
+    // State
+    w.bools(&self.wire_state);
+    w.bytes(&self.bus_state);
+    w.u32(self.ports.len());
+    for port in &self.ports {
+      w.u8(match port.direction { PortDirection::Input => 0, PortDirection::Output => 1 });
+      w.string(&port.name);
+      w.u32(port.wire);
+      w.held(port.held);
+    }
+
+    w.bytes
+  }
+
+  /// Deserialize a circuit written by `to_bytes`, without recompiling it
+  pub fn from_bytes(bytes: &[u8]) -> Result<ResoCircuit, ResoError> {
+    let mut r = Reader { bytes, position: 0 };
+    r.magic_and_version(CIRCUIT_MAGIC, CIRCUIT_VERSION)?;
+
+    // Board and image
+    let (width, height) = (r.u32()?, r.u32()?);
+    let topology = r.topology()?;
+    let palette = r.palette()?;
+    // Every resel takes at least a byte, so a board bigger than the rest of
+    // the file is corrupt. Check before allocating anything.
+    if width == 0 || height == 0 {
+      return Err(format_error("board is empty"))
+    }
+    if width.checked_mul(height).is_none_or(|resels| resels > r.remaining()) {
+      return Err(format_error("board is bigger than the file"))
+    }
+    let mut board = Vec::with_capacity(width.min(bytes.len()));
+    for _ in 0..width {
+      let mut column = Vec::with_capacity(height.min(bytes.len()));
+      for _ in 0..height { column.push(r.resel()?) }
+      board.push(column);
+    }
+    let mut rb = ReselBoard::new(board)?.with_topology(topology);
+    rb.palette = palette;
+    if r.u8()? == 1 {
+      let image = image::load_from_memory(r.bytes()?)?;
+      if (image.width() as usize, image.height() as usize) != (width, height) {
+        return Err(format_error("image size doesn't match board"))
+      }
+      rb.image = Some(image);
+    }
+
+    // RegionMap
+    let num_regions = r.u32()?;
+    let mut region_to_resel = Vec::with_capacity(num_regions.min(bytes.len()));
+    for _ in 0..num_regions { region_to_resel.push(r.resel()?) }
+
+    let mut xy_to_region = vec![vec![0; height]; width];
+    let mut region_to_xys = vec![vec![]; num_regions];
+    for (x, column) in xy_to_region.iter_mut().enumerate() {
+      for (y, ri) in column.iter_mut().enumerate() {
+        *ri = r.u32()?;
+        region_to_xys.get_mut(*ri)
+          .ok_or_else(|| format_error("region index out of range"))?
+          .push((x, y));
+      }
+    }
+
+    let wire_regions   = r.usizes()?;
+    let input_regions  = r.usizes()?;
+    let logic_regions  = r.usizes()?;
+    let output_regions = r.usizes()?;
+    let bus_regions        = r.usizes()?;
+    let bus_input_regions  = r.usizes()?;
+    let bus_output_regions = r.usizes()?;
+    let subcircuit_regions = r.usizes()?;
+    let reverse_dense  = r.usizes()?;
+    for regions in [
+      &wire_regions, &input_regions, &logic_regions, &output_regions,
+      &bus_regions, &bus_input_regions, &bus_output_regions, &subcircuit_regions,
+    ] {
+      check_indices(regions, num_regions, "dense index out of range")?;
+    }
+    if reverse_dense.len() != num_regions {
+      return Err(format_error("reverse_dense has the wrong length"))
+    }
+    let classes = [
+      (&wire_regions,   Resel::is_wire as fn(&Resel) -> bool, "wire region isn't a wire"),
+      (&input_regions,  Resel::is_input,  "input region isn't an input"),
+      (&logic_regions,  Resel::is_logic,  "logic region isn't logic"),
+      (&output_regions, Resel::is_output, "output region isn't an output"),
+      (&bus_regions,        Resel::is_bus,        "bus region isn't a bus"),
+      (&bus_input_regions,  Resel::is_bus_input,  "bus input region isn't a bus input"),
+      (&bus_output_regions, Resel::is_bus_output, "bus output region isn't a bus output"),
+      (&subcircuit_regions, Resel::is_subcircuit, "subcircuit region isn't a subcircuit"),
+    ];
+    for (regions, is_class, message) in classes {
+      check_classes(regions, &region_to_resel, is_class, message)?;
+      if regions.iter().enumerate().any(|(i, &ri)| reverse_dense[ri] != i) {
+        return Err(format_error("reverse_dense doesn't match the dense lists"))
+      }
+    }
+    // ...and every region's dense index points back at it
+    for (ri, resel) in region_to_resel.iter().enumerate() {
+      if let Some((regions, _, _)) = classes.iter().find(|(_, is_class, _)| is_class(resel)) {
+        if regions.get(reverse_dense[ri]) != Some(&ri) {
+          return Err(format_error("reverse_dense doesn't match the dense lists"))
+        }
+      }
+    }
+    for (x, column) in xy_to_region.iter().enumerate() {
+      for (y, ri) in column.iter().enumerate() {
+        if !rb.board[x][y].same(region_to_resel[*ri]) {
+          return Err(format_error("xy_to_region doesn't match the board"))
+        }
+      }
+    }
+
+    // IncidenceMap
+    let im = IncidenceMap {
+      input_inc_wires:           r.usizess()?,
+      logic_inc_inputs:          r.usizess()?,
+      output_inc_inputs:         r.usizess()?,
+      output_inc_logics:         r.usizess()?,
+      wire_inc_outputs:          r.usizess()?,
+      bus_input_inc_buses:       r.usizess()?,
+      logic_inc_bus_inputs:      r.usizess()?,
+      bus_output_inc_bus_inputs: r.usizess()?,
+      bus_output_inc_logics:     r.usizess()?,
+      bus_inc_bus_outputs:       r.usizess()?,
+    };
+    for (incidences, len, bound) in [
+      (&im.input_inc_wires,   input_regions.len(),  wire_regions.len()),
+      (&im.logic_inc_inputs,  logic_regions.len(),  input_regions.len()),
+      (&im.output_inc_inputs, output_regions.len(), input_regions.len()),
+      (&im.output_inc_logics, output_regions.len(), logic_regions.len()),
+      (&im.wire_inc_outputs,  wire_regions.len(),   output_regions.len()),
+      (&im.bus_input_inc_buses,       bus_input_regions.len(),  bus_regions.len()),
+      (&im.logic_inc_bus_inputs,      logic_regions.len(),      bus_input_regions.len()),
+      (&im.bus_output_inc_bus_inputs, bus_output_regions.len(), bus_input_regions.len()),
+      (&im.bus_output_inc_logics,     bus_output_regions.len(), logic_regions.len()),
+      (&im.bus_inc_bus_outputs,       bus_regions.len(),        bus_output_regions.len()),
+    ] {
+      if incidences.len() != len {
+        return Err(format_error("incidence list has the wrong length"))
+      }
+      for incident in incidences {
+        check_indices(incident, bound, "incidence out of range")?;
+      }
+    }
+
+    let mixed_wires = find_mixed_wires(&rb.board, &region_to_xys, &wire_regions);
+    let rm = RegionMap {
+      xy_to_region,
+      width,
+      height,
+      topology,
+      region_to_xys,
+      region_to_resel,
+      wire_regions,
+      input_regions,
+      logic_regions,
+      output_regions,
+      bus_regions,
+      bus_input_regions,
+      bus_output_regions,
+      subcircuit_regions,
+      mixed_wires,
+      reverse_dense,
+    };
+
+    // State
+    let wire_state = r.bools()?;
+    if wire_state.len() != rm.wire_regions.len() {
+      return Err(format_error("wire_state has the wrong length"))
+    }
+    let bus_state = r.bytes()?.to_vec();
+    if bus_state.len() != rm.bus_regions.len() {
+      return Err(format_error("bus_state has the wrong length"))
+    }
+    let mut rc = ResoCircuit::from_parts(rb, rm, im, wire_state);
+    rc.bus_state = bus_state;
+
+    // The saved ports include the GPIO ports from_parts just added
+    rc.ports.clear();
+    for _ in 0..r.u32()? {
+      let direction = match r.u8()? {
+        0 => PortDirection::Input,
+        1 => PortDirection::Output,
+        _ => return Err(format_error("bad port direction")),
+      };
+      let name = r.string()?;
+      let wire = r.u32()?;
+      check_indices(&[wire], rc.wire_state.len(), "port wire out of range")?;
+      let held = r.held()?;
+      rc.ports.push(Port { direction, name, wire, held });
+    }
+
+    r.finish()?;
+    Ok(rc)
+  }
+
+  /// Save the compiled circuit, e.g. to `circuit.resoc`
+  pub fn save_compiled(&self, filename: &str) -> Result<(), ResoError> {
+    Ok(std::fs::write(filename, self.to_bytes())?)
+  }
+
+  /// Load a compiled circuit, e.g. from `circuit.resoc`
+  pub fn from_compiled_filename(filename: &str) -> Result<ResoCircuit, ResoError> {
+    ResoCircuit::from_bytes(&std::fs::read(filename)?)
+  }
+
+  /// Serialize the wire and bus state and held input ports
+  pub fn state_to_bytes(&self) -> Vec<u8> {
+    let mut w = Writer { bytes: vec![] };
+    w.bytes.extend_from_slice(STATE_MAGIC);
+    w.u32(STATE_VERSION as usize);
+    w.bools(&self.wire_state);
+    w.u32(self.ports.len());
+    for port in &self.ports {
+      w.string(&port.name);
+      w.held(port.held);
+    }
+    w.bytes(&self.bus_state);
+    w.bytes
+  }
+
+  /// Restore a state written by `state_to_bytes` on the same circuit
+  pub fn load_state_bytes(&mut self, bytes: &[u8]) -> Result<(), ResoError> {
+    let mut r = Reader { bytes, position: 0 };
+    r.magic_and_version(STATE_MAGIC, STATE_VERSION)?;
+
+    let wire_state = r.bools()?;
+    if wire_state.len() != self.wire_state.len() {
+      return Err(format_error("state has a different number of wires than the circuit"))
+    }
+
+    let mut held = vec![];
+    for _ in 0..r.u32()? {
+      let name = r.string()?;
+      self.port(&name)?;
+      held.push((name, r.held()?));
+    }
+    let bus_state = r.bytes()?.to_vec();
+    if bus_state.len() != self.bus_state.len() {
+      return Err(format_error("state has a different number of buses than the circuit"))
+    }
+    r.finish()?;
+
+    // Only change anything once the whole state has been read
+    self.wire_state = wire_state;
+    self.bus_state = bus_state;
+    for (name, value) in held {
+      if let Some(port) = self.ports.iter_mut().find(|port| port.name == name) {
+        port.held = value;
+      }
+    }
+    Ok(())
+  }
+
+  /// Save the wire and bus state and held input ports, e.g. to `circuit.resos`
+  pub fn save_state(&self, filename: &str) -> Result<(), ResoError> {
+    Ok(std::fs::write(filename, self.state_to_bytes())?)
+  }
+
+  /// Load a state snapshot, e.g. from `circuit.resos`
+  pub fn load_state(&mut self, filename: &str) -> Result<(), ResoError> {
+    self.load_state_bytes(&std::fs::read(filename)?)
+  }
+}
+
+#[cfg(test)]
+mod serialize_tests {
+  use super::*;
+  use std::collections::{HashSet};
+
+  /// Check two circuits compiled to the same thing
+  fn assert_same_circuit(a: &ResoCircuit, b: &ResoCircuit) {
+    assert_eq!(a.rb.board, b.rb.board);
+    assert_eq!(a.rb.image, b.rb.image);
+    assert_eq!(a.rb.topology, b.rb.topology);
+    assert_eq!(a.rb.palette, b.rb.palette);
+    assert_eq!(a.rm.topology, b.rm.topology);
+    assert_eq!(a.rm.xy_to_region, b.rm.xy_to_region);
+    assert_eq!(a.rm.region_to_resel, b.rm.region_to_resel);
+    assert_eq!(a.rm.wire_regions, b.rm.wire_regions);
+    assert_eq!(a.rm.input_regions, b.rm.input_regions);
+    assert_eq!(a.rm.logic_regions, b.rm.logic_regions);
+    assert_eq!(a.rm.output_regions, b.rm.output_regions);
+    assert_eq!(a.rm.reverse_dense, b.rm.reverse_dense);
+    for (xys_a, xys_b) in a.rm.region_to_xys.iter().zip(&b.rm.region_to_xys) {
+      // Same coordinates, but not necessarily the same order
+      assert_eq!(
+        xys_a.iter().collect::<HashSet<_>>(),
+        xys_b.iter().collect::<HashSet<_>>()
+      );
+    }
+    assert_eq!(a.im.input_inc_wires, b.im.input_inc_wires);
+    assert_eq!(a.im.logic_inc_inputs, b.im.logic_inc_inputs);
+    assert_eq!(a.im.output_inc_inputs, b.im.output_inc_inputs);
+    assert_eq!(a.im.output_inc_logics, b.im.output_inc_logics);
+    assert_eq!(a.im.wire_inc_outputs, b.im.wire_inc_outputs);
+    assert_eq!(a.rm.bus_regions, b.rm.bus_regions);
+    assert_eq!(a.rm.bus_input_regions, b.rm.bus_input_regions);
+    assert_eq!(a.rm.bus_output_regions, b.rm.bus_output_regions);
+    assert_eq!(a.rm.subcircuit_regions, b.rm.subcircuit_regions);
+    assert_eq!(a.im.bus_input_inc_buses, b.im.bus_input_inc_buses);
+    assert_eq!(a.im.logic_inc_bus_inputs, b.im.logic_inc_bus_inputs);
+    assert_eq!(a.im.bus_output_inc_bus_inputs, b.im.bus_output_inc_bus_inputs);
+    assert_eq!(a.im.bus_output_inc_logics, b.im.bus_output_inc_logics);
+    assert_eq!(a.im.bus_inc_bus_outputs, b.im.bus_inc_bus_outputs);
+    assert_eq!(a.wire_state, b.wire_state);
+    assert_eq!(a.bus_state, b.bus_state);
+    assert_eq!(a.ports, b.ports);
+  }
+
+  #[test]
+  fn test_roundtrip_reso_logo() {
+    let mut rc = ResoCircuit::from_filename("./reso_logo.png").unwrap();
+    let mut loaded = ResoCircuit::from_bytes(&rc.to_bytes()).unwrap();
+    assert_same_circuit(&rc, &loaded);
+
+    // ... and they simulate the same
+    for _ in 0..8 {
+      rc.iterate();
+      rc.update_pixels();
+      loaded.iterate();
+      loaded.update_pixels();
+      assert_eq!(rc.get_image(), loaded.get_image());
+    }
+  }
+
+  #[test]
+  fn test_roundtrip_text_with_ports() {
+    // No image, and some held ports
+    let mut rc = ResoCircuit::from(
+      ReselBoard::from_filename("./src/testing/test_half_adder.txt").unwrap()
+    );
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    rc.set_input("a", true).unwrap();
+    let loaded = ResoCircuit::from_bytes(&rc.to_bytes()).unwrap();
+    assert!(loaded.get_image().is_none());
+    assert_same_circuit(&rc, &loaded);
+  }
+
+  #[test]
+  fn test_roundtrip_topology() {
+    let rc = ResoCircuit::from(
+      ReselBoard::from_filename("./src/testing/test_half_adder.txt").unwrap()
+        .with_topology(Topology::Bounded)
+    );
+    let bytes = rc.to_bytes();
+    assert_same_circuit(&rc, &ResoCircuit::from_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_roundtrip_gpio() {
+    // GPIO ports come back once, still held
+    let rb = ReselBoard::from_text("G+=g", &Default::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let mut rc = ResoCircuit::from(rb);
+    rc.drive_gpios(&[Some(false)]).unwrap();
+    let loaded = ResoCircuit::from_bytes(&rc.to_bytes()).unwrap();
+    assert_same_circuit(&rc, &loaded);
+    assert_eq!(loaded.rb.board[0][0], Resel::GpioOn);
+    assert_eq!(loaded.ports.len(), 2);
+  }
+
+  #[test]
+  fn test_roundtrip_buses() {
+    // A bus driving another through a bus input and output, and a lone bus
+    let text = "⠉⠉⠉\n{  \n}  \n⠉⠉⠉\n   \n#⣿ ";
+    let mut rc = ResoCircuit::from(
+      ReselBoard::from_text(text, &Default::default()).unwrap().with_topology(Topology::Bounded)
+    );
+    rc.iterate();
+    let mut loaded = ResoCircuit::from_bytes(&rc.to_bytes()).unwrap();
+    assert_same_circuit(&rc, &loaded);
+    assert_eq!(loaded.bus_state, vec![0, 9, 0]);
+
+    let state = rc.state_to_bytes();
+    loaded.bus_state = vec![0, 0, 0];
+    loaded.load_state_bytes(&state).unwrap();
+    assert_eq!(loaded.bus_state, rc.bus_state);
+  }
+
+  #[test]
+  fn test_roundtrip_subcircuit() {
+    // Inlined regions aren't on the board, but they come back all the same
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_subcircuit.txt").unwrap();
+    rc.load_subcircuits("./src/testing/test_subcircuit.subcircuits").unwrap();
+    rc.load_ports("./src/testing/test_subcircuit.ports").unwrap();
+    rc.set_input("a", true).unwrap();
+    let mut loaded = ResoCircuit::from_bytes(&rc.to_bytes()).unwrap();
+    assert_same_circuit(&rc, &loaded);
+    assert_eq!(loaded.rm.subcircuit_regions, rc.rm.subcircuit_regions);
+    assert_eq!(loaded.rb.board[2][0], Resel::Subcircuit);
+
+    loaded.iterate();
+    assert!(loaded.get_output("sum").unwrap());
+    assert!(!loaded.get_output("carry").unwrap());
+  }
+
+  #[test]
+  fn test_state_roundtrip() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    rc.set_input("a", true).unwrap();
+    rc.set_input("b", false).unwrap();
+    rc.iterate();
+    let state = rc.state_to_bytes();
+
+    // Resume the state on a freshly compiled copy, and check it continues the same
+    let mut resumed = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    resumed.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    resumed.load_state_bytes(&state).unwrap();
+    assert_eq!(resumed.wire_state, rc.wire_state);
+    assert_eq!(resumed.ports, rc.ports);
+    for _ in 0..3 {
+      rc.iterate();
+      resumed.iterate();
+      assert_eq!(resumed.wire_state, rc.wire_state);
+    }
+
+    // A state from a different circuit doesn't fit
+    let mut logo = ResoCircuit::from_filename("./reso_logo.png").unwrap();
+    assert!(matches!(logo.load_state_bytes(&state), Err(ResoError::Format(_))));
+  }
+
+  #[test]
+  fn test_bad_bytes() {
+    let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    let bytes = rc.to_bytes();
+
+    assert!(matches!(ResoCircuit::from_bytes(b"NOPE"), Err(ResoError::Format(_))));
+    assert!(matches!(
+      ResoCircuit::from_bytes(&bytes[..bytes.len() - 1]),
+      Err(ResoError::Format(_))
+    ));
+
+    let mut future = bytes.clone();
+    future[CIRCUIT_MAGIC.len()] = 99;
+    assert!(matches!(ResoCircuit::from_bytes(&future), Err(ResoError::UnsupportedVersion(99))));
+
+    // Regions of the wrong class, or that don't match the board
+    let mut bad = rc.clone();
+    bad.rm.region_to_resel[bad.rm.wire_regions[0]] = Resel::AND;
+    assert!(matches!(ResoCircuit::from_bytes(&bad.to_bytes()), Err(ResoError::Format(_))));
+    let mut bad = rc.clone();
+    let (x, y) = bad.rm.region_to_xys[bad.rm.logic_regions[0]][0];
+    bad.rb.board[x][y] = Resel::Input;
+    assert!(matches!(ResoCircuit::from_bytes(&bad.to_bytes()), Err(ResoError::Format(_))));
+
+    // Dense indices out of range, or that don't point back at their region
+    let reverse_dense_error = |bad: &ResoCircuit| matches!(
+      ResoCircuit::from_bytes(&bad.to_bytes()),
+      Err(ResoError::Format(message)) if message == "reverse_dense doesn't match the dense lists"
+    );
+    let mut bad = rc.clone();
+    bad.rm.reverse_dense.fill(1000000);
+    assert!(reverse_dense_error(&bad));
+    let mut bad = rc.clone();
+    let (w0, w1) = (bad.rm.wire_regions[0], bad.rm.wire_regions[1]);
+    bad.rm.reverse_dense.swap(w0, w1);
+    assert!(reverse_dense_error(&bad));
+
+    // A board bigger than the file is rejected before it's allocated
+    let size = CIRCUIT_MAGIC.len() + 4;
+    for ((width, height), expected) in [
+      ((u32::MAX, 0), "board is empty"),
+      ((0, 4), "board is empty"),
+      ((0xFFFF, 0xFFFF), "board is bigger than the file"),
+      ((u32::MAX, u32::MAX), "board is bigger than the file"),
+    ] {
+      let mut huge = bytes.clone();
+      huge[size..size + 4].copy_from_slice(&width.to_le_bytes());
+      huge[size + 4..size + 8].copy_from_slice(&height.to_le_bytes());
+      assert!(matches!(
+        ResoCircuit::from_bytes(&huge),
+        Err(ResoError::Format(message)) if message == expected
+      ));
+    }
+
+    // A bus region that isn't a bus would panic in from_parts
+    let rb = ReselBoard::from_text("#+=o", &Default::default()).unwrap()
+      .with_topology(Topology::Bounded);
+    let mut bad = ResoCircuit::from(rb);
+    bad.rm.bus_regions = bad.rm.wire_regions.clone();
+    bad.bus_state = vec![0];
+    assert!(matches!(
+      ResoCircuit::from_bytes(&bad.to_bytes()),
+      Err(ResoError::Format(message)) if message == "bus region isn't a bus"
+    ));
+
+    // Every truncation should be an error, not a panic
+    for len in 0..bytes.len() {
+      assert!(ResoCircuit::from_bytes(&bytes[..len]).is_err());
+    }
+  }
+}
+
+// eof
@@ -0,0 +1,414 @@
+//! subcircuit.rs: Circuits placed inside other circuits.
+//!
+//! A subcircuit region (dark yellow, `@` in text; see resel.rs) is a
+//! placeholder for another circuit. A sidecar "subcircuits file" says which
+//! circuit goes in each one, and which of its ports connect to which of the
+//! parent's wires, one subcircuit per line:
+//!
+//! ```text
+//! # x,y  file                  port=x,y ...
+//! 2,0    test_half_adder.txt   a=0,0 b=0,1 sum=4,0 carry=4,1
+//! ```
+//!
+//! - `x,y` is any resel of the subcircuit region.
+//! - `file` is the child circuit, relative to the subcircuits file. Its
+//!   ports come from its own sidecar ports file (`test_half_adder.ports`),
+//!   and its own subcircuits from its own sidecar subcircuits file
+//!   (`test_half_adder.subcircuits`), if they exist.
+//! - `port=x,y` connects the child's port to the parent's wire at (x, y).
+//!   Ports that aren't listed stay inside the child.
+//!
+//! Children are read with the parent's topology, palette and mixed wire
+//! policy. (Compiled `.resoc` children keep the ones they were compiled
+//! with.)
+//!
+//! Blank lines and anything after a `#` are ignored. Every subcircuit region
+//! needs exactly one line.
+//!
+//! Loading inlines each child: its regions are appended to the parent's
+//! RegionMap and IncidenceMap, and each connected port's wire becomes the
+//! parent wire it's connected to. Inlined regions aren't on the board, so
+//! they have no resels and aren't drawn; the subcircuit region is just a
+//! label. A child's GPIO wires become GPIOs of the parent.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_subcircuit.txt").unwrap();
+//! rc.load_subcircuits("./src/testing/test_subcircuit.subcircuits").unwrap();
+//! rc.load_ports("./src/testing/test_subcircuit.ports").unwrap();
+//!
+//! rc.set_input("a", true).unwrap();
+//! rc.set_input("b", true).unwrap();
+//! rc.iterate();
+//! assert_eq!(rc.get_output("sum").unwrap(), false);
+//! assert_eq!(rc.get_output("carry").unwrap(), true);
+//! ```
+
+use std::path::{Path};
+
+use crate::error::{ResoError};
+use crate::ports::{parse_xy};
+use crate::regionmap::{RegionMap};
+use crate::reselboard::{ReselBoard};
+use crate::resocircuit::{ResoCircuit};
+use crate::serialize::{is_compiled_filename};
+
+/// How deep subcircuits can nest, so a circuit that includes itself fails
+/// instead of recursing forever
+pub const MAX_SUBCIRCUIT_DEPTH: usize = 16;
+
+/// A subcircuit as written in a subcircuits file, before it's loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubcircuitSpec {
+  pub x: usize,
+  pub y: usize,
+  pub filename: String,
+  /// (child port name, parent x, parent y)
+  pub bindings: Vec<(String, usize, usize)>,
+}
+
+/// Parse the text of a subcircuits file.
+/// Line numbers in errors count from 1.
+pub fn parse_subcircuits(text: &str) -> Result<Vec<SubcircuitSpec>, ResoError> {
+  let mut specs = vec![];
+
+  for (line_idx, line) in text.lines().enumerate() {
+    let line_no = line_idx + 1;
+    let err = |message: &str| ResoError::SubcircuitFile {
+      line: line_no, message: message.to_string()
+    };
+
+    let line = line.split('#').next().unwrap_or("");
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.is_empty() {
+      continue
+    }
+    if fields.len() < 2 {
+      return Err(err("expected `x,y file port=x,y ...`"))
+    }
+
+    let (x, y) = parse_xy(fields[0]).ok_or_else(|| err("coordinate should be `x,y`"))?;
+
+    let mut bindings: Vec<(String, usize, usize)> = vec![];
+    for binding in &fields[2..] {
+      let (name, (px, py)) = binding.split_once('=')
+        .and_then(|(name, xy)| Some((name, parse_xy(xy)?)))
+        .ok_or_else(|| err("connection should be `port=x,y`"))?;
+      if bindings.iter().any(|(other, _, _)| other == name) {
+        return Err(err("port connected twice"))
+      }
+      bindings.push((name.to_string(), px, py));
+    }
+
+    specs.push(SubcircuitSpec { x, y, filename: fields[1].to_string(), bindings });
+  }
+
+  Ok(specs)
+}
+
+/// Read a subcircuits file
+pub fn load_subcircuits_file(filename: &str) -> Result<Vec<SubcircuitSpec>, ResoError> {
+  parse_subcircuits(&std::fs::read_to_string(filename)?)
+}
+
+/// Load a child circuit with its sidecar ports and subcircuits files, read
+/// with the same options as `parent`
+fn load_child(filename: &Path, depth: usize, parent: &ReselBoard) -> Result<ResoCircuit, ResoError> {
+  if depth > MAX_SUBCIRCUIT_DEPTH {
+    return Err(ResoError::Subcircuit(format!(
+      "nested more than {} deep at {} (does it include itself?)",
+      MAX_SUBCIRCUIT_DEPTH, filename.display()
+    )))
+  }
+  let name = filename.to_string_lossy();
+  let mut child = if is_compiled_filename(&name) {
+    ResoCircuit::from_compiled_filename(&name)?
+  } else {
+    ResoCircuit::compile(
+      ReselBoard::from_filename(&name)?
+        .with_topology(parent.topology)
+        .with_palette(parent.palette)
+        .with_mixed_wire_policy(parent.mixed_wire_policy)
+    )?
+  };
+  let subcircuits = filename.with_extension("subcircuits");
+  if subcircuits.exists() {
+    child.load_subcircuits_at_depth(&subcircuits, depth + 1)?;
+  }
+  let ports = filename.with_extension("ports");
+  if ports.exists() {
+    child.load_ports(&ports.to_string_lossy())?;
+  }
+  Ok(child)
+}
+
+/// Append the regions of `child` in `regions` to `rm`, returning the new
+/// dense index of each
+fn append_regions(rm: &mut RegionMap, child: &RegionMap, regions: &[usize]) -> Vec<usize> {
+  regions.iter().map(|ri| {
+    let region = rm.add_region(child.region_to_resel[*ri]);
+    rm.reverse_dense[region]
+  }).collect()
+}
+
+/// Map each list in `incidences` through `map`
+fn map_incidences(incidences: &[Vec<usize>], map: &[usize]) -> Vec<Vec<usize>> {
+  incidences.iter().map(|incident| {
+    let mut mapped: Vec<usize> = incident.iter().map(|i| map[*i]).collect();
+    mapped.sort();
+    mapped.dedup();
+    mapped
+  }).collect()
+}
+
+impl ResoCircuit {
+  /// Inline `child` into the subcircuit region at (x, y), connecting each
+  /// of its ports in `bindings` to the parent's wire at (x, y).
+  /// The parent's existing regions, wires and ports keep their indices.
+  pub fn inline(
+    &mut self, x: usize, y: usize, child: &ResoCircuit, bindings: &[(String, usize, usize)]
+  ) -> Result<(), ResoError> {
+    if x >= self.rm.width || y >= self.rm.height {
+      return Err(ResoError::OutOfBounds { x, y })
+    }
+    if !self.rm.region_to_resel[self.rm.xy_to_region[x][y]].is_subcircuit() {
+      return Err(ResoError::NotASubcircuit { x, y })
+    }
+
+    // Child wire -> parent wire, for connected ports
+    let mut bound: Vec<Option<usize>> = vec![None; child.rm.wire_regions.len()];
+    for (name, px, py) in bindings {
+      let child_wire = child.port(name)?.wire;
+      let parent_wire = self.wire_at(*px, *py)?;
+      match bound[child_wire] {
+        Some(other) if other != parent_wire => return Err(ResoError::Subcircuit(format!(
+          "port {:?} is connected to two different wires", name
+        ))),
+        _ => bound[child_wire] = Some(parent_wire),
+      }
+    }
+
+    // Nothing can fail from here on
+    let mut wire_map = vec![];
+    for (cw, ri) in child.rm.wire_regions.iter().enumerate() {
+      wire_map.push(match bound[cw] {
+        Some(parent_wire) => parent_wire,
+        None => {
+          let region = self.rm.add_region(child.rm.region_to_resel[*ri]);
+          self.wire_state.push(child.wire_state[cw]);
+          self.rm.reverse_dense[region]
+        },
+      });
+    }
+    let input_map      = append_regions(&mut self.rm, &child.rm, &child.rm.input_regions);
+    let logic_map      = append_regions(&mut self.rm, &child.rm, &child.rm.logic_regions);
+    let output_map     = append_regions(&mut self.rm, &child.rm, &child.rm.output_regions);
+    let bus_map        = append_regions(&mut self.rm, &child.rm, &child.rm.bus_regions);
+    let bus_input_map  = append_regions(&mut self.rm, &child.rm, &child.rm.bus_input_regions);
+    let bus_output_map = append_regions(&mut self.rm, &child.rm, &child.rm.bus_output_regions);
+    // (The child's own subcircuit regions are only labels, so they're left out)
+
+    let (im, cim) = (&mut self.im, &child.im);
+    im.input_inc_wires.extend(map_incidences(&cim.input_inc_wires, &wire_map));
+    im.logic_inc_inputs.extend(map_incidences(&cim.logic_inc_inputs, &input_map));
+    im.output_inc_inputs.extend(map_incidences(&cim.output_inc_inputs, &input_map));
+    im.output_inc_logics.extend(map_incidences(&cim.output_inc_logics, &logic_map));
+    for (cw, outputs) in map_incidences(&cim.wire_inc_outputs, &output_map).into_iter().enumerate() {
+      match bound[cw] {
+        Some(parent_wire) => {
+          let incident = &mut im.wire_inc_outputs[parent_wire];
+          incident.extend(outputs);
+          incident.sort();
+          incident.dedup();
+        },
+        None => im.wire_inc_outputs.push(outputs),
+      }
+    }
+    im.bus_input_inc_buses.extend(map_incidences(&cim.bus_input_inc_buses, &bus_map));
+    im.logic_inc_bus_inputs.extend(map_incidences(&cim.logic_inc_bus_inputs, &bus_input_map));
+    im.bus_output_inc_bus_inputs.extend(map_incidences(&cim.bus_output_inc_bus_inputs, &bus_input_map));
+    im.bus_output_inc_logics.extend(map_incidences(&cim.bus_output_inc_logics, &logic_map));
+    im.bus_inc_bus_outputs.extend(map_incidences(&cim.bus_inc_bus_outputs, &bus_output_map));
+    let mut bus_state = self.bus_state.clone();
+    bus_state.extend(&child.bus_state);
+
+    // Rebuild to resize the iteration state, keeping the existing ports and
+    // adding any new GPIO ports
+    let mut ports = self.ports.clone();
+    *self = ResoCircuit::from_parts(
+      self.rb.clone(), self.rm.clone(), self.im.clone(), self.wire_state.clone()
+    );
+    self.bus_state = bus_state;
+    for port in self.ports.drain(..) {
+      if !ports.iter().any(|other| other.name == port.name) {
+        ports.push(port);
+      }
+    }
+    self.ports = ports;
+    Ok(())
+  }
+
+  /// Inline every subcircuit listed in a subcircuits file. Every subcircuit
+  /// region needs exactly one entry.
+  pub fn load_subcircuits(&mut self, filename: &str) -> Result<(), ResoError> {
+    self.load_subcircuits_at_depth(Path::new(filename), 1)
+  }
+
+  fn load_subcircuits_at_depth(&mut self, filename: &Path, depth: usize) -> Result<(), ResoError> {
+    let specs = load_subcircuits_file(&filename.to_string_lossy())?;
+    let dir = filename.parent().unwrap_or(Path::new(""));
+
+    // Check every subcircuit region is listed once before loading anything
+    let mut listed = vec![false; self.rm.subcircuit_regions.len()];
+    for spec in &specs {
+      if spec.x >= self.rm.width || spec.y >= self.rm.height {
+        return Err(ResoError::OutOfBounds { x: spec.x, y: spec.y })
+      }
+      let ri = self.rm.xy_to_region[spec.x][spec.y];
+      if !self.rm.region_to_resel[ri].is_subcircuit() {
+        return Err(ResoError::NotASubcircuit { x: spec.x, y: spec.y })
+      }
+      let si = self.rm.reverse_dense[ri];
+      if listed[si] {
+        return Err(ResoError::Subcircuit(format!(
+          "the subcircuit at ({},{}) is listed twice", spec.x, spec.y
+        )))
+      }
+      listed[si] = true;
+    }
+    if let Some(si) = listed.iter().position(|listed| !listed) {
+      let (x, y) = self.rm.first_xy(self.rm.subcircuit_regions[si]).unwrap_or_default();
+      return Err(ResoError::Subcircuit(format!("the subcircuit at ({},{}) isn't listed", x, y)))
+    }
+
+    // Load every child, then inline them into a copy, so a bad file or
+    // connection leaves the circuit as it was
+    let children = specs.iter()
+      .map(|spec| load_child(&dir.join(&spec.filename), depth, &self.rb))
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut rc = self.clone();
+    for (spec, child) in specs.iter().zip(&children) {
+      rc.inline(spec.x, spec.y, child, &spec.bindings)?;
+    }
+    *self = rc;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod subcircuit_tests {
+  use super::*;
+  use crate::reselboard::{MixedWirePolicy, TextOptions, Topology};
+
+  #[test]
+  fn test_parse_subcircuits() {
+    let specs = parse_subcircuits(
+      "# a comment\n\n2,0 adder.txt a=0,0 sum=4,0 # trailing comment\n1,1 blank.png\n"
+    ).unwrap();
+    assert_eq!(specs, vec![
+      SubcircuitSpec {
+        x: 2, y: 0, filename: "adder.txt".to_string(),
+        bindings: vec![("a".to_string(), 0, 0), ("sum".to_string(), 4, 0)],
+      },
+      SubcircuitSpec { x: 1, y: 1, filename: "blank.png".to_string(), bindings: vec![] },
+    ]);
+
+    for (text, bad_line) in [
+      ("2,0", 1),
+      ("2,0 a.txt\n2;0 b.txt", 2),
+      ("2,0 a.txt a=0", 1),
+      ("2,0 a.txt a=0,0 a=1,1", 1),
+    ] {
+      match parse_subcircuits(text) {
+        Err(ResoError::SubcircuitFile { line, .. }) => assert_eq!(line, bad_line),
+        other => panic!("Expected SubcircuitFile error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn test_inline_half_adder() {
+    let text = "o @ l \ns @ o ";
+    let rb = ReselBoard::from_text(text, &TextOptions::strict()).unwrap()
+      .with_topology(Topology::Bounded);
+    let mut rc = ResoCircuit::from(rb);
+    let (num_regions, num_wires) = (rc.rm.region_to_resel.len(), rc.wire_state.len());
+
+    let mut child = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    child.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    let bindings = [("a", 0, 0), ("b", 0, 1), ("sum", 4, 0), ("carry", 4, 1)]
+      .map(|(name, x, y)| (name.to_string(), x, y));
+    rc.inline(2, 0, &child, &bindings).unwrap();
+
+    // The four connected wires are the parent's; the rest of the child is new
+    assert_eq!(rc.wire_state.len(), num_wires);
+    assert_eq!(rc.rm.wire_regions.len(), num_wires);
+    assert_eq!(rc.rm.logic_regions.len(), 2);
+    assert!(rc.rm.region_to_resel.len() > num_regions);
+    assert_eq!(rc.rm.first_xy(num_regions), None);
+
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+      let (wa, wb) = (rc.wire_at(0, 0).unwrap(), rc.wire_at(0, 1).unwrap());
+      rc.wire_state[wa] = a;
+      rc.wire_state[wb] = b;
+      rc.iterate();
+      assert_eq!(rc.wire_state[rc.wire_at(4, 0).unwrap()], a ^ b);
+      assert_eq!(rc.wire_state[rc.wire_at(4, 1).unwrap()], a & b);
+    }
+
+    // Not a subcircuit, and a port connected to two wires
+    assert!(matches!(rc.inline(0, 0, &child, &[]), Err(ResoError::NotASubcircuit { x: 0, y: 0 })));
+    let twice = [("a".to_string(), 0, 0), ("a".to_string(), 0, 1)];
+    assert!(matches!(rc.inline(2, 0, &child, &twice), Err(ResoError::Subcircuit(_))));
+  }
+
+  #[test]
+  fn test_load_subcircuits_errors() {
+    let dir = std::env::temp_dir().join(format!("reso_subcircuit_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, text: &str| {
+      let path = dir.join(name);
+      std::fs::write(&path, text).unwrap();
+      path.to_string_lossy().to_string()
+    };
+
+    // A circuit that includes itself
+    let circuit = write("loop.txt", "@");
+    let manifest = write("loop.subcircuits", "0,0 loop.txt\n");
+    let mut rc = ResoCircuit::from_filename(&circuit).unwrap();
+    assert!(matches!(rc.load_subcircuits(&manifest), Err(ResoError::Subcircuit(_))));
+
+    // Two subcircuits, one missing from the manifest, then one listed twice
+    let circuit = write("two.txt", "@ @ ");
+    let mut rc = ResoCircuit::from_filename(&circuit).unwrap();
+    let manifest = write("two.subcircuits", "0,0 blank.txt\n");
+    assert!(matches!(rc.load_subcircuits(&manifest), Err(ResoError::Subcircuit(_))));
+    let manifest = write("two.subcircuits", "0,0 blank.txt\n0,0 blank.txt\n");
+    assert!(matches!(rc.load_subcircuits(&manifest), Err(ResoError::Subcircuit(_))));
+
+    // The second connection is bad, so the first child isn't inlined either
+    write("wire.txt", "o");
+    let manifest = write("two.subcircuits", "0,0 wire.txt\n2,0 wire.txt nope=0,0\n");
+    let num_regions = rc.rm.region_to_resel.len();
+    assert!(matches!(rc.load_subcircuits(&manifest), Err(ResoError::UnknownPort(_))));
+    assert_eq!(rc.rm.region_to_resel.len(), num_regions);
+
+    // Children are read with the parent's mixed wire policy
+    write("mixed.txt", "oO");
+    let circuit = write("one.txt", "@");
+    let manifest = write("one.subcircuits", "0,0 mixed.txt\n");
+    let mut rc = ResoCircuit::from_filename(&circuit).unwrap();
+    rc.load_subcircuits(&manifest).unwrap();
+    let mut rc = ResoCircuit::compile(
+      ReselBoard::from_filename(&circuit).unwrap().with_mixed_wire_policy(MixedWirePolicy::Error)
+    ).unwrap();
+    assert!(matches!(rc.load_subcircuits(&manifest), Err(ResoError::MixedWire { .. })));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
+
+// eof
@@ -0,0 +1,556 @@
+//! synth.rs: Synthesize a circuit from a boolean expression.
+//!
+//! Expressions have named variables, `0` and `1`, and the operators below,
+//! tightest first, with parentheses for grouping:
+//!
+//! | Operator     | Meaning | Built from                               |
+//! | ------------ | ------- | ---------------------------------------- |
+//! | `!a`, `~a`   | not     | XOR with a constant-on wire              |
+//! | `a & b`      | and     | an input reading both, into an AND       |
+//! | `a ^ b`      | xor     | an input reading both, into an XOR       |
+//! | `a \| b`     | or      | an input reading both, into an output    |
+//!
+//! An expression can be named, like `sum = a ^ b ^ c`; otherwise its output
+//! is named `out`.
+//!
+//! The expression becomes a `Netlist`, with repeated subexpressions shared,
+//! which is laid out on a grid with a column `SLOT` resels wide per signal:
+//!
+//! - Each signal is a vertical sapphire "rail" (constants are lime). The
+//!   variables' rails start on the top edge, and are their input ports.
+//! - Each gate gets `GATE_ROWS` rows. Each operand is copied off its rail
+//!   by an input and output onto an orange "tap", which runs right to the
+//!   gate, crossing the rails in between diagonally.
+//! - The gate's own rail runs down from there. The output's rail runs to the
+//!   bottom edge, and is its output port.
+//!
+//! Every stage adds a step of delay, so the output settles within
+//! `settle_steps` steps of the inputs changing. `Synthesis::verify`
+//! simulates every combination of inputs and checks the output against
+//! the expression.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::synth::{synthesize};
+//!
+//! let synthesis = synthesize("(a & b) ^ c").unwrap();
+//! assert_eq!(synthesis.verify().unwrap(), 8);
+//! print!("{}", synthesis.board.to_text());
+//! for spec in &synthesis.ports {
+//!   println!("{}", spec);
+//! }
+//! ```
+
+use std::collections::{HashMap};
+
+use crate::error::{ResoError};
+use crate::ports::{PortDirection, PortSpec};
+use crate::resel::{Resel};
+use crate::reselboard::{ReselBoard};
+use crate::resocircuit::{ResoCircuit};
+
+/// Columns per signal
+pub const SLOT: usize = 5;
+/// Rows per gate
+pub const GATE_ROWS: usize = 6;
+/// The row of the first gate's first tap, below the constants' holders
+const FIRST_TAP: usize = 3;
+/// Most variables `verify` will try every combination of
+pub const MAX_VERIFY_VARIABLES: usize = 16;
+
+/// A parsed boolean expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Var(String),
+  Const(bool),
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Xor(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+  /// Evaluate with the given variable values. Missing variables are false.
+  pub fn eval(&self, values: &HashMap<String, bool>) -> bool {
+    match self {
+      Expr::Var(name) => values.get(name).copied().unwrap_or(false),
+      Expr::Const(value) => *value,
+      Expr::Not(a) => !a.eval(values),
+      Expr::And(a, b) => a.eval(values) & b.eval(values),
+      Expr::Xor(a, b) => a.eval(values) ^ b.eval(values),
+      Expr::Or(a, b) => a.eval(values) | b.eval(values),
+    }
+  }
+
+  /// The variables, in the order they first appear
+  pub fn variables(&self) -> Vec<String> {
+    let mut names = vec![];
+    self.collect_variables(&mut names);
+    names
+  }
+
+  fn collect_variables(&self, names: &mut Vec<String>) {
+    match self {
+      Expr::Var(name) => if !names.contains(name) {
+        names.push(name.clone())
+      },
+      Expr::Const(_) => {},
+      Expr::Not(a) => a.collect_variables(names),
+      Expr::And(a, b) | Expr::Xor(a, b) | Expr::Or(a, b) => {
+        a.collect_variables(names);
+        b.collect_variables(names);
+      },
+    }
+  }
+}
+
+/// Recursive descent over the characters of an expression
+struct Parser {
+  chars: Vec<char>,
+  pos: usize,
+}
+
+impl Parser {
+  fn err(&self, message: &str) -> ResoError {
+    ResoError::Expression { column: self.pos + 1, message: message.to_string() }
+  }
+
+  /// Skip whitespace and return the next character, without consuming it
+  fn peek(&mut self) -> Option<char> {
+    while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+      self.pos += 1;
+    }
+    self.chars.get(self.pos).copied()
+  }
+
+  /// Parse operands of `next` separated by `op`, left to right
+  fn binary(
+    &mut self, op: char, make: fn(Box<Expr>, Box<Expr>) -> Expr,
+    next: fn(&mut Self) -> Result<Expr, ResoError>,
+  ) -> Result<Expr, ResoError> {
+    let mut expr = next(self)?;
+    while self.peek() == Some(op) {
+      self.pos += 1;
+      expr = make(Box::new(expr), Box::new(next(self)?));
+    }
+    Ok(expr)
+  }
+
+  fn or(&mut self) -> Result<Expr, ResoError> {
+    self.binary('|', Expr::Or, Self::xor)
+  }
+
+  fn xor(&mut self) -> Result<Expr, ResoError> {
+    self.binary('^', Expr::Xor, Self::and)
+  }
+
+  fn and(&mut self) -> Result<Expr, ResoError> {
+    self.binary('&', Expr::And, Self::unary)
+  }
+
+  fn unary(&mut self) -> Result<Expr, ResoError> {
+    match self.peek() {
+      Some('!') | Some('~') => {
+        self.pos += 1;
+        Ok(Expr::Not(Box::new(self.unary()?)))
+      },
+      Some('(') => {
+        self.pos += 1;
+        let expr = self.or()?;
+        if self.peek() != Some(')') {
+          return Err(self.err("expected `)`"))
+        }
+        self.pos += 1;
+        Ok(expr)
+      },
+      Some('0') => { self.pos += 1; Ok(Expr::Const(false)) },
+      Some('1') => { self.pos += 1; Ok(Expr::Const(true)) },
+      Some(c) if c.is_alphabetic() || c == '_' => Ok(Expr::Var(self.name())),
+      Some(_) => Err(self.err("expected a variable, `0`, `1`, `!` or `(`")),
+      None => Err(self.err("unexpected end of expression")),
+    }
+  }
+
+  /// A variable name: letters, digits and underscores, not starting with a digit
+  fn name(&mut self) -> String {
+    let start = self.pos;
+    while self.chars.get(self.pos).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+      self.pos += 1;
+    }
+    self.chars[start..self.pos].iter().collect()
+  }
+}
+
+/// Parse an expression, optionally named like `sum = a ^ b`.
+/// Returns the output name (`out` by default) and the expression.
+/// Columns in errors count from 1.
+pub fn parse_expr(text: &str) -> Result<(String, Expr), ResoError> {
+  let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+
+  let mut output = "out".to_string();
+  if let Some((name, _)) = text.split_once('=') {
+    let name = name.trim();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit())
+      || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+      return Err(parser.err("the output name should be a variable name, like `out = a & b`"))
+    }
+    output = name.to_string();
+    parser.pos = parser.chars.iter().position(|&c| c == '=').unwrap() + 1;
+  }
+
+  let expr = parser.or()?;
+  if parser.peek().is_some() {
+    return Err(parser.err("expected an operator or the end of the expression"))
+  }
+  if expr.variables().contains(&output) {
+    return Err(ResoError::Expression {
+      column: 1, message: format!("output {:?} is also a variable", output)
+    })
+  }
+  Ok((output, expr))
+}
+
+/// A signal in a netlist. Gates refer to earlier nodes by index.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+  Var(String),
+  Const(bool),
+  And(usize, usize),
+  Xor(usize, usize),
+  Or(usize, usize),
+}
+
+/// An expression as Reso primitives, in the order they're laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Netlist {
+  pub nodes: Vec<Node>,
+  /// The node that's the expression's value
+  pub output: usize,
+}
+
+impl Netlist {
+  /// Build the netlist for an expression: its variables first, then the
+  /// constants and gates, each after its operands. NOT becomes XOR with 1.
+  pub fn from_expr(expr: &Expr) -> Netlist {
+    let mut index = HashMap::new();
+    let mut nodes = vec![];
+    for name in expr.variables() {
+      add_node(&mut nodes, &mut index, Node::Var(name));
+    }
+    let output = add_expr(&mut nodes, &mut index, expr);
+    Netlist { nodes, output }
+  }
+
+  /// How many gates there are
+  pub fn num_gates(&self) -> usize {
+    self.nodes.iter().filter(|node| gate_operands(node).is_some()).count()
+  }
+
+  /// How many steps the output takes to settle after the inputs change:
+  /// one for each tap and one for each gate
+  pub fn settle_steps(&self) -> usize {
+    2 * self.num_gates()
+  }
+
+  /// Lay out the netlist on a board. Returns the board and its ports: an
+  /// input for each variable and an output named `output`.
+  pub fn layout(&self, output: &str) -> (ReselBoard, Vec<PortSpec>) {
+    let num_gates = self.num_gates();
+    let width = SLOT * self.nodes.len();
+    let bottom = FIRST_TAP + GATE_ROWS * num_gates;
+    let mut board = vec![vec![Resel::Empty; bottom + 1]; width];
+
+    // Each gate's first tap row, and the rows each rail runs down column 2
+    let mut gate_rows = vec![None; self.nodes.len()];
+    let mut rails = vec![(0, 0); self.nodes.len()];
+    for (slot, t1) in self.nodes.iter().enumerate()
+      .filter(|(_, node)| gate_operands(node).is_some())
+      .map(|(slot, _)| slot)
+      .zip((0..).map(|gate| FIRST_TAP + GATE_ROWS * gate)) {
+      gate_rows[slot] = Some(t1);
+      rails[slot].0 = t1 + 5;
+    }
+    for (slot, node) in self.nodes.iter().enumerate() {
+      if let Some((a, b)) = gate_operands(node) {
+        let t1 = gate_rows[slot].unwrap();
+        rails[a].1 = rails[a].1.max(t1);
+        rails[b].1 = rails[b].1.max(t1 + 3);
+      }
+    }
+    rails[self.output].1 = bottom;
+
+    // Rails
+    for (slot, node) in self.nodes.iter().enumerate() {
+      let x = SLOT * slot;
+      let resel = match node {
+        Node::Const(true) => Resel::WireLimeOn,
+        Node::Const(false) => Resel::WireLimeOff,
+        _ => Resel::WireSapphireOff,
+      };
+      let (start, end) = rails[slot];
+      for cell in &mut board[x + 2][start..=end] {
+        *cell = resel;
+      }
+      if *node == Node::Const(true) {
+        // Hold it on, with an output reading it
+        board[x + 3][0] = Resel::Input;
+        board[x + 3][1] = Resel::Output;
+      }
+    }
+
+    // Taps and gates
+    for (slot, node) in self.nodes.iter().enumerate() {
+      let Some((a, b)) = gate_operands(node) else { continue };
+      let t1 = gate_rows[slot].unwrap();
+      for (operand, t) in [(a, t1), (b, t1 + 3)] {
+        // Copy the operand off its rail...
+        board[SLOT * operand + 3][t] = Resel::Input;
+        board[SLOT * operand + 4][t] = Resel::Output;
+        // ...and run it right, crossing the rails on the way
+        for (crossed, &(start, end)) in rails.iter().enumerate().take(slot).skip(operand + 1) {
+          let x = SLOT * crossed;
+          let crossing = start < t && t < end;
+          for dx in 0..SLOT {
+            if !(crossing && dx == 2) {
+              board[x + dx][t] = Resel::WireOrangeOff;
+            }
+          }
+          if crossing {
+            // The rail steps left a column for a row, and the tap steps
+            // down into it, each joining up diagonally
+            board[x + 1][t + 1] = board[x + 2][t + 1];
+            board[x + 2][t + 1] = Resel::WireOrangeOff;
+          }
+        }
+        board[SLOT * slot][t] = Resel::WireOrangeOff;
+      }
+
+      // The gate: an input reading both taps, into an AND, XOR, or output
+      let x = SLOT * slot;
+      for cell in &mut board[x + 1][t1..=t1 + 3] {
+        *cell = Resel::Input;
+      }
+      let rail = Resel::WireSapphireOff;
+      match node {
+        Node::And(..) | Node::Xor(..) => {
+          board[x + 2][t1 + 1] = if matches!(node, Node::And(..)) { Resel::AND } else { Resel::XOR };
+          board[x + 3][t1 + 1] = Resel::Output;
+        },
+        _ => {
+          board[x + 2][t1 + 1] = Resel::Output;
+          board[x + 3][t1 + 1] = rail;
+        },
+      }
+      // Its rail, jogging back to column 2
+      for cell in &mut board[x + 3][t1 + 2..=t1 + 4] {
+        *cell = rail;
+      }
+    }
+
+    let mut ports: Vec<PortSpec> = self.nodes.iter().enumerate()
+      .filter_map(|(slot, node)| match node {
+        Node::Var(name) => Some(PortSpec {
+          direction: PortDirection::Input, name: name.clone(), x: SLOT * slot + 2, y: 0
+        }),
+        _ => None,
+      })
+      .collect();
+    ports.push(PortSpec {
+      direction: PortDirection::Output, name: output.to_string(),
+      x: SLOT * self.output + 2, y: bottom
+    });
+
+    // The board is a grid by construction
+    (ReselBoard::new(board).unwrap(), ports)
+  }
+}
+
+/// A gate's operands, or None if the node isn't a gate
+fn gate_operands(node: &Node) -> Option<(usize, usize)> {
+  match node {
+    Node::And(a, b) | Node::Xor(a, b) | Node::Or(a, b) => Some((*a, *b)),
+    _ => None,
+  }
+}
+
+/// Add a node, or find the same node already added
+fn add_node(nodes: &mut Vec<Node>, index: &mut HashMap<Node, usize>, node: Node) -> usize {
+  *index.entry(node.clone()).or_insert_with(|| {
+    nodes.push(node);
+    nodes.len() - 1
+  })
+}
+
+fn add_expr(nodes: &mut Vec<Node>, index: &mut HashMap<Node, usize>, expr: &Expr) -> usize {
+  let node = match expr {
+    Expr::Var(name) => Node::Var(name.clone()),
+    Expr::Const(value) => Node::Const(*value),
+    Expr::Not(a) => {
+      let a = add_expr(nodes, index, a);
+      let one = add_node(nodes, index, Node::Const(true));
+      Node::Xor(a, one)
+    },
+    Expr::And(a, b) => Node::And(add_expr(nodes, index, a), add_expr(nodes, index, b)),
+    Expr::Xor(a, b) => Node::Xor(add_expr(nodes, index, a), add_expr(nodes, index, b)),
+    Expr::Or(a, b) => Node::Or(add_expr(nodes, index, a), add_expr(nodes, index, b)),
+  };
+  add_node(nodes, index, node)
+}
+
+/// An expression, synthesized into a circuit
+#[derive(Debug, Clone)]
+pub struct Synthesis {
+  /// The output port's name
+  pub output: String,
+  pub expr: Expr,
+  pub netlist: Netlist,
+  pub board: ReselBoard,
+  /// An input for each variable, on the top edge, and the output, on the bottom edge
+  pub ports: Vec<PortSpec>,
+}
+
+/// Parse an expression and lay it out as a circuit
+pub fn synthesize(text: &str) -> Result<Synthesis, ResoError> {
+  let (output, expr) = parse_expr(text)?;
+  let netlist = Netlist::from_expr(&expr);
+  let (board, ports) = netlist.layout(&output);
+  Ok(Synthesis { output, expr, netlist, board, ports })
+}
+
+impl Synthesis {
+  /// Simulate every combination of inputs, and check the output settles
+  /// to the expression's value within `settle_steps` and stays there.
+  /// Returns how many combinations were checked.
+  pub fn verify(&self) -> Result<usize, ResoError> {
+    let names = self.expr.variables();
+    if names.len() > MAX_VERIFY_VARIABLES {
+      return Err(ResoError::Synth(format!(
+        "can't check {} variables; at most {}", names.len(), MAX_VERIFY_VARIABLES
+      )))
+    }
+
+    let mut rc = ResoCircuit::compile(self.board.clone())?;
+    for spec in &self.ports {
+      rc.add_port(spec)?;
+    }
+    let combinations = 1 << names.len();
+    for bits in 0..combinations {
+      let values: HashMap<String, bool> = names.iter().enumerate()
+        .map(|(i, name)| (name.clone(), bits & (1 << i) != 0))
+        .collect();
+      for (name, value) in &values {
+        rc.set_input(name, *value)?;
+      }
+      for _ in 0..self.netlist.settle_steps() {
+        rc.iterate();
+      }
+      let settled = rc.get_output(&self.output)?;
+      rc.iterate();
+      let expected = self.expr.eval(&values);
+      if settled != expected || rc.get_output(&self.output)? != expected {
+        let mut inputs: Vec<String> = names.iter()
+          .map(|name| format!("{}={}", name, values[name] as u8))
+          .collect();
+        inputs.sort();
+        return Err(ResoError::Synth(format!(
+          "{} should be {} with {}", self.output, expected as u8, inputs.join(" ")
+        )))
+      }
+    }
+    Ok(combinations)
+  }
+}
+
+#[cfg(test)]
+mod synth_tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_expr() {
+    let var = |name: &str| Box::new(Expr::Var(name.to_string()));
+    // & binds tighter than ^, which binds tighter than |
+    assert_eq!(parse_expr("a | b ^ c & !d").unwrap(), ("out".to_string(), Expr::Or(
+      var("a"),
+      Box::new(Expr::Xor(var("b"), Box::new(Expr::And(var("c"), Box::new(Expr::Not(var("d"))))))),
+    )));
+    assert_eq!(
+      parse_expr(" sum = (a_1 ^ 1)").unwrap(),
+      ("sum".to_string(), Expr::Xor(var("a_1"), Box::new(Expr::Const(true))))
+    );
+    assert_eq!(parse_expr("a & b").unwrap().1.variables(), vec!["a", "b"]);
+
+    for (text, bad_column) in [
+      ("a &", 4),
+      ("(a | b", 7),
+      ("a b", 3),
+      ("a + b", 3),
+      ("2x = a", 1),
+      ("a = a & b", 1),
+    ] {
+      match parse_expr(text) {
+        Err(ResoError::Expression { column, .. }) => assert_eq!(column, bad_column, "{}", text),
+        other => panic!("Expected Expression error for {:?}, got {:?}", text, other),
+      }
+    }
+  }
+
+  #[test]
+  fn test_netlist_shares_subexpressions() {
+    let (_, expr) = parse_expr("(a & b) ^ !(a & b)").unwrap();
+    let netlist = Netlist::from_expr(&expr);
+    assert_eq!(netlist.nodes, vec![
+      Node::Var("a".to_string()),
+      Node::Var("b".to_string()),
+      Node::And(0, 1),
+      Node::Const(true),
+      Node::Xor(2, 3),
+      Node::Xor(2, 4),
+    ]);
+    assert_eq!(netlist.output, 5);
+  }
+
+  #[test]
+  fn test_synthesize_verifies() {
+    for text in [
+      "a",
+      "!a",
+      "0",
+      "1 ^ a",
+      "a & b",
+      "a | b",
+      "(a & b) ^ c",
+      "carry = (a & b) | (c & (a ^ b))",
+      "!(a | b) & (c ^ !d) | a & d",
+      "x & y & z & w ^ (x | w)",
+    ] {
+      let synthesis = synthesize(text).unwrap();
+      let rc = ResoCircuit::from(synthesis.board.clone());
+      assert_eq!(rc.lint(), vec![], "{}", text);
+      let variables = synthesis.expr.variables().len();
+      assert_eq!(synthesis.verify().unwrap(), 1 << variables, "{}", text);
+    }
+  }
+
+  #[test]
+  fn test_synthesize_layout() {
+    let synthesis = synthesize("a & b").unwrap();
+    assert_eq!(synthesis.board.to_text(), concat!(
+      "  s    s       \n",
+      "  s    s       \n",
+      "  s    s       \n",
+      "  s+=oosooo+   \n",
+      "      so   +&= \n",
+      "       s   + s \n",
+      "       s+=o+ s \n",
+      "             s \n",
+      "            s  \n",
+      "            s  \n",
+    ));
+    assert_eq!(synthesis.ports.iter().map(|spec| spec.to_string()).collect::<Vec<_>>(), vec![
+      "input a 2,0", "input b 7,0", "output out 12,9",
+    ]);
+  }
+}
+
+// eof
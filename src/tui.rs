@@ -0,0 +1,379 @@
+//! tui.rs: An interactive terminal viewer and step controller.
+//!
+//! `reso tui` draws the board in the terminal with ANSI truecolour, two
+//! columns per resel, and lets you step, run, pause, and reset the
+//! simulation, toggle wires, and inspect the region under the cursor.
+//!
+//! A `Viewer` holds everything but the terminal: the circuit, the cursor, and
+//! whether it's running. It renders to plain lines of ANSI text, so it can be
+//! tested (or driven) without a terminal. `run_tui` drives one with crossterm.
+//!
+//! Toggling a wire with an input port flips the value the port holds. Any
+//! other wire is flipped for the current step only, until its outputs drive
+//! it again: a pulse.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::tui::{Viewer};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+//!
+//! let mut viewer = Viewer::new(rc);
+//! viewer.toggle(0, 2).unwrap(); // hold input `a` on
+//! viewer.step();
+//! assert_eq!(viewer.rc.get_output("sum").unwrap(), true);
+//! assert!(viewer.region_info(0, 2).contains("\"a\""));
+//! ```
+
+use std::io::{Write};
+use std::time::{Duration, Instant};
+
+use crossterm::{cursor, event, execute, queue, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind};
+
+use crate::error::{ResoError};
+use crate::graph::{class_name};
+use crate::ports::{Port, PortDirection};
+use crate::resel::{Resel};
+use crate::resocircuit::{ResoCircuit};
+
+/// Lines below the board: the status line and the key help
+const STATUS_LINES: usize = 2;
+
+const HELP: &str = "space run/pause  s step  r reset  arrows/hjkl move  enter/click toggle  +/- speed  q quit";
+
+/// A circuit being viewed, with a cursor. See the module docs.
+pub struct Viewer {
+  pub rc: ResoCircuit,
+  /// Cursor position on the board
+  pub cursor: (usize, usize),
+  /// Top-left resel of the visible part of the board
+  pub view: (usize, usize),
+  pub running: bool,
+  /// Steps since loading (or the last reset)
+  pub step: usize,
+  /// Delay between steps while running
+  pub delay: Duration,
+
+  // What reset goes back to
+  initial_state: Vec<bool>,
+  initial_buses: Vec<u8>,
+  initial_ports: Vec<Port>,
+}
+
+impl Viewer {
+  pub fn new(rc: ResoCircuit) -> Viewer {
+    Viewer {
+      initial_state: rc.wire_state.clone(),
+      initial_buses: rc.bus_state.clone(),
+      initial_ports: rc.ports.clone(),
+      rc,
+      cursor: (0, 0),
+      view: (0, 0),
+      running: false,
+      step: 0,
+      delay: Duration::from_millis(100),
+    }
+  }
+
+  /// Simulate one step
+  pub fn step(&mut self) {
+    self.rc.iterate();
+    self.step += 1;
+  }
+
+  /// Go back to the wire and bus states and held inputs the viewer started
+  /// with
+  pub fn reset(&mut self) {
+    self.rc.wire_state.clone_from(&self.initial_state);
+    self.rc.bus_state.clone_from(&self.initial_buses);
+    self.rc.ports.clone_from(&self.initial_ports);
+    self.step = 0;
+  }
+
+  /// Toggle the wire at (x,y): flip an input port's held value, or pulse
+  /// any other wire. Returns the wire's new state.
+  pub fn toggle(&mut self, x: usize, y: usize) -> Result<bool, ResoError> {
+    let wi = self.rc.wire_at(x, y)?;
+    let on = !self.rc.wire_state[wi];
+    let port = self.rc.ports.iter()
+      .find(|port| port.wire == wi && port.direction == PortDirection::Input)
+      .map(|port| port.name.clone());
+    match port {
+      Some(name) => self.rc.set_input(&name, on)?,
+      None => self.rc.wire_state[wi] = on,
+    }
+    Ok(on)
+  }
+
+  /// Move the cursor by (dx,dy), staying on the board
+  pub fn move_cursor(&mut self, dx: isize, dy: isize) {
+    let (x, y) = self.cursor;
+    self.cursor = (
+      x.saturating_add_signed(dx).min(self.rc.rm.width - 1),
+      y.saturating_add_signed(dy).min(self.rc.rm.height - 1),
+    );
+  }
+
+  /// The resel drawn at (x,y), with wires and buses in their current state
+  fn resel_at(&self, x: usize, y: usize) -> Resel {
+    let ri = self.rc.rm.xy_to_region[x][y];
+    if self.rc.rm.region_to_resel[ri].is_wire() {
+      self.rc.wire_resel(self.rc.rm.reverse_dense[ri])
+    } else if self.rc.rm.region_to_resel[ri].is_bus() {
+      self.rc.bus_resel(self.rc.rm.reverse_dense[ri])
+    } else {
+      self.rc.rb.board[x][y]
+    }
+  }
+
+  /// A one-line description of the region under (x,y)
+  pub fn region_info(&self, x: usize, y: usize) -> String {
+    let ri = self.rc.rm.xy_to_region[x][y];
+    let resel = self.resel_at(x, y);
+    let mut info = format!("({},{}) {} {}", x, y, class_name(resel), ri);
+    if resel == Resel::Empty {
+      return info
+    }
+
+    if let (Some(on), Some(colour)) = (resel.wire_state(), resel.wire_colour_name()) {
+      info.push_str(&format!(" {} {}", colour, if on { "on" } else { "off" }));
+    }
+    if let Some(value) = resel.bus_value() {
+      info.push_str(&format!(" value {}", value));
+    }
+    let (x0, y0, x1, y1) = self.rc.rm.bounding_box(ri);
+    info.push_str(&format!(
+      ", {} resels in ({},{})-({},{})", self.rc.rm.region_to_xys[ri].len(), x0, y0, x1, y1
+    ));
+    info.push_str(&format!(", {} adjacent", self.rc.rm.get_adjacent_regions(ri).len()));
+
+    if resel.is_wire() {
+      let wi = self.rc.rm.reverse_dense[ri];
+      for port in self.rc.ports.iter().filter(|port| port.wire == wi) {
+        match (port.direction, port.held) {
+          (PortDirection::Input, Some(held)) => info.push_str(
+            &format!(", input \"{}\" held {}", port.name, held as u8)
+          ),
+          (PortDirection::Input, None) => info.push_str(&format!(", input \"{}\"", port.name)),
+          (PortDirection::Output, _) => info.push_str(&format!(", output \"{}\"", port.name)),
+        }
+      }
+    }
+    info
+  }
+
+  /// Scroll the view so the cursor is inside a `cols` by `rows` terminal
+  pub fn fit_view(&mut self, cols: usize, rows: usize) {
+    let (visible_x, visible_y) = (
+      (cols / 2).max(1),
+      rows.saturating_sub(STATUS_LINES).max(1),
+    );
+    let fit = |view: usize, cursor: usize, visible: usize| {
+      if cursor < view {
+        cursor
+      } else if cursor >= view + visible {
+        cursor + 1 - visible
+      } else {
+        view
+      }
+    };
+    self.view = (
+      fit(self.view.0, self.cursor.0, visible_x),
+      fit(self.view.1, self.cursor.1, visible_y),
+    );
+  }
+
+  /// Render the visible board and the status lines for a `cols` by `rows`
+  /// terminal, as lines of ANSI truecolour text
+  pub fn render(&self, cols: usize, rows: usize) -> Vec<String> {
+    let (vx, vy) = self.view;
+    let x_end = (vx + cols / 2).min(self.rc.rm.width);
+    let y_end = (vy + rows.saturating_sub(STATUS_LINES)).min(self.rc.rm.height);
+
+    let mut lines = vec![];
+    for y in vy..y_end {
+      let mut line = String::new();
+      for x in vx..x_end {
+        match self.rc.rb.palette.to_rgba(self.resel_at(x, y)) {
+          Some(rgba) => line.push_str(&format!("\x1b[48;2;{};{};{}m", rgba[0], rgba[1], rgba[2])),
+          None => line.push_str("\x1b[49m"),
+        }
+        line.push_str(if (x, y) == self.cursor { "[]" } else { "  " });
+      }
+      line.push_str("\x1b[0m");
+      lines.push(line);
+    }
+
+    let mut status = format!(
+      "step {}  {}  {}ms  {}",
+      self.step, if self.running { "running" } else { "paused" },
+      self.delay.as_millis(), self.region_info(self.cursor.0, self.cursor.1)
+    );
+    for (name, value) in self.rc.outputs() {
+      status.push_str(&format!("  {}={}", name, value as u8));
+    }
+    for line in [status, HELP.to_string()] {
+      lines.push(line.chars().take(cols).collect());
+    }
+    lines
+  }
+}
+
+/// View `rc` in the terminal until the user quits. See the module docs.
+pub fn run_tui(rc: ResoCircuit, delay: Duration) -> Result<(), ResoError> {
+  let mut viewer = Viewer::new(rc);
+  viewer.delay = delay;
+
+  let mut stdout = std::io::stdout();
+  let _raw = RawTerminal::enter(&mut stdout)?;
+  event_loop(&mut viewer, &mut stdout)
+}
+
+/// The terminal in raw mode on the alternate screen. Dropping it restores
+/// the terminal, even if the event loop fails or panics.
+struct RawTerminal;
+
+impl RawTerminal {
+  fn enter(stdout: &mut impl Write) -> Result<RawTerminal, ResoError> {
+    terminal::enable_raw_mode()?;
+    // From here on, dropping the guard undoes whatever got done
+    let raw = RawTerminal;
+    execute!(stdout, terminal::EnterAlternateScreen, event::EnableMouseCapture, cursor::Hide)?;
+    Ok(raw)
+  }
+}
+
+impl Drop for RawTerminal {
+  fn drop(&mut self) {
+    // Nothing to do about errors here: this is our best shot at restoring it
+    let _ = execute!(
+      std::io::stdout(), cursor::Show, event::DisableMouseCapture, terminal::LeaveAlternateScreen
+    );
+    let _ = terminal::disable_raw_mode();
+  }
+}
+
+fn event_loop(viewer: &mut Viewer, stdout: &mut impl Write) -> Result<(), ResoError> {
+  let mut last_step = Instant::now();
+  loop {
+    let (cols, rows) = terminal::size()?;
+    let (cols, rows) = (cols as usize, rows as usize);
+    viewer.fit_view(cols, rows);
+    queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+    for (row, line) in viewer.render(cols, rows).iter().enumerate() {
+      queue!(stdout, cursor::MoveTo(0, row as u16))?;
+      write!(stdout, "{}", line)?;
+    }
+    stdout.flush()?;
+
+    let timeout = if viewer.running {
+      viewer.delay.saturating_sub(last_step.elapsed())
+    } else {
+      Duration::from_secs(60)
+    };
+    if !event::poll(timeout)? {
+      if viewer.running {
+        viewer.step();
+        last_step = Instant::now();
+      }
+      continue
+    }
+
+    match event::read()? {
+      Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+        KeyCode::Char(' ') => {
+          viewer.running = !viewer.running;
+          last_step = Instant::now();
+        },
+        KeyCode::Char('s') | KeyCode::Char('.') => {
+          viewer.running = false;
+          viewer.step();
+        },
+        KeyCode::Char('r') => viewer.reset(),
+        KeyCode::Left  | KeyCode::Char('h') => viewer.move_cursor(-1, 0),
+        KeyCode::Right | KeyCode::Char('l') => viewer.move_cursor(1, 0),
+        KeyCode::Up    | KeyCode::Char('k') => viewer.move_cursor(0, -1),
+        KeyCode::Down  | KeyCode::Char('j') => viewer.move_cursor(0, 1),
+        KeyCode::Enter | KeyCode::Char('t') => {
+          // Not a wire: nothing to toggle
+          let _ = viewer.toggle(viewer.cursor.0, viewer.cursor.1);
+        },
+        KeyCode::Char('+') | KeyCode::Char('=') => viewer.delay /= 2,
+        KeyCode::Char('-') => viewer.delay = (viewer.delay * 2).max(Duration::from_millis(1)),
+        _ => {},
+      },
+      Event::Mouse(mouse) if mouse.kind == MouseEventKind::Down(MouseButton::Left) => {
+        let (x, y) = (viewer.view.0 + mouse.column as usize / 2, viewer.view.1 + mouse.row as usize);
+        let on_board = (mouse.row as usize) < rows.saturating_sub(STATUS_LINES);
+        if on_board && x < viewer.rc.rm.width && y < viewer.rc.rm.height {
+          viewer.cursor = (x, y);
+          let _ = viewer.toggle(x, y);
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+#[cfg(test)]
+mod tui_tests {
+  use super::*;
+
+  fn half_adder() -> Viewer {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    Viewer::new(rc)
+  }
+
+  #[test]
+  fn test_toggle_step_reset() {
+    let mut viewer = half_adder();
+    assert!(viewer.toggle(0, 2).unwrap());
+    assert!(viewer.toggle(0, 3).unwrap());
+    assert!(viewer.toggle(3, 2).is_err()); // an input, not a wire
+    viewer.step();
+    viewer.step();
+    assert_eq!(viewer.rc.outputs(), vec![("sum", false), ("carry", true)]);
+    assert!(viewer.region_info(0, 2).ends_with("input \"a\" held 1"));
+    assert!(viewer.region_info(5, 4).contains("lime on"));
+
+    viewer.reset();
+    assert_eq!(viewer.step, 0);
+    assert_eq!(viewer.rc.port("a").unwrap().held, None);
+    viewer.step();
+    assert_eq!(viewer.rc.outputs(), vec![("sum", false), ("carry", false)]);
+  }
+
+  #[test]
+  fn test_render_and_scroll() {
+    let mut viewer = half_adder();
+    let (width, height) = (viewer.rc.rm.width, viewer.rc.rm.height);
+
+    // The whole board fits
+    let lines = viewer.render(80, 24);
+    assert_eq!(lines.len(), height + STATUS_LINES);
+    assert_eq!(lines[0].matches("  ").count() + lines[0].matches("[]").count(), width);
+    assert!(lines[0].contains("[]"));
+    assert!(lines[height].starts_with("step 0  paused  100ms  (0,0) empty 0"));
+
+    // A 4x3 window onto the board follows the cursor
+    viewer.cursor = (width - 1, height - 1);
+    viewer.fit_view(8, 3 + STATUS_LINES);
+    assert_eq!(viewer.view, (width - 4, height - 3));
+    let lines = viewer.render(8, 3 + STATUS_LINES);
+    assert_eq!(lines.len(), 3 + STATUS_LINES);
+    assert!(lines[2].ends_with("[]\x1b[0m"));
+    viewer.move_cursor(-100, -100);
+    assert_eq!(viewer.cursor, (0, 0));
+    viewer.fit_view(8, 3 + STATUS_LINES);
+    assert_eq!(viewer.view, (0, 0));
+  }
+}
+
+// eof
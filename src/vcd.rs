@@ -0,0 +1,243 @@
+//! vcd.rs: Trace wire states over time into a Value Change Dump.
+//!
+//! Debugging timing from a folder of PNG frames is slow. A VcdWriter records
+//! the wire state of a circuit after each step into a VCD file, which
+//! waveform viewers like GTKWave can show as one trace per wire.
+//!
+//! Each wire is a 1-bit `wire` variable, named after its port if it has one,
+//! and otherwise `r<region>_<colour>_<x>_<y>` (its region index, colour, and
+//! first resel), e.g. `r3_orange_0_2`. Each bus is an 8-bit `wire` vector
+//! named `r<region>_bus_<x>_<y>`. Regions inlined from subcircuits have no
+//! resels, so their names leave out `_<x>_<y>`. One step is one nanosecond.
+//! Only changes are written, so long, mostly idle simulations stay small.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//! use reso::vcd::{VcdWriter};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+//! rc.set_input("a", true).unwrap();
+//!
+//! let filename = std::env::temp_dir().join("reso_doctest_half_adder.vcd");
+//! let mut vcd = VcdWriter::create(filename.to_str().unwrap(), &rc).unwrap();
+//! vcd.record(0, &rc).unwrap();
+//! for step in 1..=4 {
+//!   rc.iterate();
+//!   vcd.record(step, &rc).unwrap();
+//! }
+//! vcd.finish().unwrap();
+//! ```
+
+use std::fs::{File};
+use std::io::{BufWriter, Write};
+
+use crate::error::{ResoError};
+use crate::resocircuit::{ResoCircuit};
+
+/// The VCD name of each wire of `rc`. See the module docs.
+pub fn wire_names(rc: &ResoCircuit) -> Vec<String> {
+  rc.rm.wire_regions.iter().enumerate().map(|(wi, ri)| {
+    if let Some(port) = rc.ports.iter().find(|port| port.wire == wi) {
+      return port.name.clone()
+    }
+    let colour = rc.rm.region_to_resel[*ri].wire_colour_name().unwrap();
+    match rc.rm.first_xy(*ri) {
+      Some((x, y)) => format!("r{}_{}_{}_{}", ri, colour, x, y),
+      None => format!("r{}_{}", ri, colour),
+    }
+  }).collect()
+}
+
+/// The VCD name of each bus of `rc`. See the module docs.
+pub fn bus_names(rc: &ResoCircuit) -> Vec<String> {
+  rc.rm.bus_regions.iter().map(|ri| {
+    match rc.rm.first_xy(*ri) {
+      Some((x, y)) => format!("r{}_bus_{}_{}", ri, x, y),
+      None => format!("r{}_bus", ri),
+    }
+  }).collect()
+}
+
+/// A short VCD identifier for variable `i`: base 94 over printable ASCII
+fn identifier(mut i: usize) -> String {
+  let mut id = String::new();
+  loop {
+    id.push((b'!' + (i % 94) as u8) as char);
+    i /= 94;
+    if i == 0 {
+      return id
+    }
+  }
+}
+
+/// Streams wire and bus states into a VCD, one step at a time
+pub struct VcdWriter<W: Write> {
+  writer: W,
+  /// Identifiers of the wires, then the buses
+  ids: Vec<String>,
+  /// The wire and bus state written last, or None before the first `record`
+  last: Option<(Vec<bool>, Vec<u8>)>,
+  /// The last time recorded, and the last time written (i.e. with changes)
+  last_time: usize,
+  last_written: usize,
+}
+
+impl VcdWriter<BufWriter<File>> {
+  /// Create `filename` and write the VCD header for `rc`'s wires and buses
+  pub fn create(filename: &str, rc: &ResoCircuit) -> Result<Self, ResoError> {
+    VcdWriter::new(BufWriter::new(File::create(filename)?), rc)
+  }
+}
+
+impl<W: Write> VcdWriter<W> {
+  /// Write the VCD header for `rc`'s wires and buses to `writer`
+  pub fn new(mut writer: W, rc: &ResoCircuit) -> Result<Self, ResoError> {
+    let names = wire_names(rc);
+    let buses = bus_names(rc);
+    let ids: Vec<String> = (0..names.len() + buses.len()).map(identifier).collect();
+
+    writeln!(writer, "$version reso {} $end", env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer, "$timescale 1ns $end")?;
+    writeln!(writer, "$scope module reso $end")?;
+    for (name, id) in names.iter().zip(&ids) {
+      writeln!(writer, "$var wire 1 {} {} $end", id, name)?;
+    }
+    for (name, id) in buses.iter().zip(&ids[names.len()..]) {
+      writeln!(writer, "$var wire 8 {} {} $end", id, name)?;
+    }
+    writeln!(writer, "$upscope $end")?;
+    writeln!(writer, "$enddefinitions $end")?;
+
+    Ok(VcdWriter { writer, ids, last: None, last_time: 0, last_written: 0 })
+  }
+
+  /// Record the wire and bus state of `rc` at `time` (the step). The first
+  /// call dumps everything; later calls only write what changed.
+  pub fn record(&mut self, time: usize, rc: &ResoCircuit) -> Result<(), ResoError> {
+    let (wire_state, bus_state) = (&rc.wire_state, &rc.bus_state);
+    let changed: Vec<usize> = match &self.last {
+      None => (0..wire_state.len() + bus_state.len()).collect(),
+      Some((last_wires, last_buses)) => (0..wire_state.len())
+        .filter(|wi| wire_state[*wi] != last_wires[*wi])
+        .chain((0..bus_state.len())
+          .filter(|bi| bus_state[*bi] != last_buses[*bi])
+          .map(|bi| wire_state.len() + bi))
+        .collect(),
+    };
+
+    let first = self.last.is_none();
+    if first || !changed.is_empty() {
+      writeln!(self.writer, "#{}", time)?;
+      if first {
+        writeln!(self.writer, "$dumpvars")?;
+      }
+      for i in changed {
+        if i < wire_state.len() {
+          writeln!(self.writer, "{}{}", if wire_state[i] { '1' } else { '0' }, self.ids[i])?;
+        } else {
+          writeln!(self.writer, "b{:08b} {}", bus_state[i - wire_state.len()], self.ids[i])?;
+        }
+      }
+      if first {
+        writeln!(self.writer, "$end")?;
+      }
+      self.last = Some((wire_state.clone(), bus_state.clone()));
+      self.last_written = time;
+    }
+    self.last_time = time;
+    Ok(())
+  }
+
+  /// Mark the end of the trace and flush it. Returns the writer.
+  pub fn finish(mut self) -> Result<W, ResoError> {
+    // So viewers show the last values lasting until the last step
+    if self.last_time != self.last_written {
+      writeln!(self.writer, "#{}", self.last_time)?;
+    }
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+}
+
+#[cfg(test)]
+mod vcd_tests {
+  use super::*;
+
+  #[test]
+  fn test_identifier() {
+    assert_eq!(identifier(0), "!");
+    assert_eq!(identifier(93), "~");
+    assert_eq!(identifier(94), "!\"");
+    let ids: std::collections::HashSet<String> = (0..10_000).map(identifier).collect();
+    assert_eq!(ids.len(), 10_000);
+  }
+
+  #[test]
+  fn test_half_adder_vcd() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    rc.set_input("a", true).unwrap();
+    rc.set_input("b", true).unwrap();
+
+    let mut vcd = VcdWriter::new(vec![], &rc).unwrap();
+    vcd.record(0, &rc).unwrap();
+    for step in 1..=3 {
+      rc.iterate();
+      vcd.record(step, &rc).unwrap();
+    }
+    let text = String::from_utf8(vcd.finish().unwrap()).unwrap();
+
+    // Carry comes on after one step, then nothing changes
+    assert_eq!(text, [
+      &format!("$version reso {} $end", env!("CARGO_PKG_VERSION")),
+      "$timescale 1ns $end",
+      "$scope module reso $end",
+      "$var wire 1 ! a $end",
+      "$var wire 1 \" b $end",
+      "$var wire 1 # sum $end",
+      "$var wire 1 $ carry $end",
+      "$upscope $end",
+      "$enddefinitions $end",
+      "#0",
+      "$dumpvars",
+      "1!",
+      "1\"",
+      "0#",
+      "0$",
+      "$end",
+      "#1",
+      "1$",
+      "#3",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn test_bus_vcd() {
+    // A bus driving another through a bus input and output
+    let rb = crate::reselboard::ReselBoard::from_text("⠁{}⠂", &Default::default()).unwrap()
+      .with_topology(crate::reselboard::Topology::Bounded);
+    let mut rc = ResoCircuit::from(rb);
+    let mut vcd = VcdWriter::new(vec![], &rc).unwrap();
+    vcd.record(0, &rc).unwrap();
+    rc.iterate();
+    vcd.record(1, &rc).unwrap();
+    let text = String::from_utf8(vcd.finish().unwrap()).unwrap();
+
+    assert!(text.contains("$var wire 8 ! r1_bus_0_0 $end\n$var wire 8 \" r4_bus_3_0 $end\n"));
+    assert!(text.ends_with("$dumpvars\nb00000001 !\nb00000010 \"\n$end\n#1\nb00000000 !\nb00000001 \"\n"));
+  }
+
+  #[test]
+  fn test_wire_names() {
+    let rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    assert_eq!(wire_names(&rc), vec![
+      "r1_orange_0_2", "r2_sapphire_0_3", "r8_lime_5_1", "r9_lime_5_4"
+    ]);
+  }
+}
+
+// eof
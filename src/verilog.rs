@@ -0,0 +1,479 @@
+//! verilog.rs: Export compiled circuits to structural Verilog.
+//!
+//! A Reso step maps onto one clock cycle:
+//!
+//! - Every wire region is a register (`w3_q`), holding its state between steps.
+//! - Input, logic and output regions are combinational: each input is the OR
+//!   (and, for logic, AND or XOR) of its wires, each logic the OR over its
+//!   inputs, and each output the OR of its inputs and logics.
+//! - On each rising clock edge, every wire register takes the OR of its
+//!   incident outputs. `rst` loads the circuit's current wire state instead.
+//!
+//! So after `n` clock cycles the registers hold what `ResoCircuit::iterate`
+//! would after `n` steps.
+//!
+//! Input ports become two module inputs, `name` and `name_hold`. While
+//! `name_hold` is high the wire reads as `name`, like a held port. Output ports
+//! become module outputs. All wires are also on the `wires` output, wire `i`
+//! (the dense wire index) at bit `i`, for checking against traces.
+//!
+//! Port names must be Verilog identifiers, and can't clash with keywords or
+//! the generated signal names (`w0`, `in0_or`, `logic0`, ...). Circuits with
+//! buses can't be exported yet.
+//!
+//! The testbench from `to_verilog_testbench` resets the module, holds the
+//! input ports as they're held in the ResoCircuit, and checks `wires` against
+//! a trace of `iterate` at every step, printing `PASS` or `FAIL`.
+//!
+//! Example:
+//!
+//! ```rust
+//! use reso::{ResoCircuit};
+//!
+//! let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+//! rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+//! let verilog = rc.to_verilog("half_adder").unwrap();
+//! assert!(verilog.contains("module half_adder ("));
+//! assert!(verilog.contains("output wire carry"));
+//! ```
+
+use std::collections::{HashSet};
+
+use crate::error::{ResoError};
+use crate::ports::{PortDirection};
+use crate::resel::{Resel};
+use crate::resocircuit::{ResoCircuit};
+
+/// A combinational expression over named signals
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Const(bool),
+  /// OR of the signals. False if there are none.
+  Or(Vec<String>),
+  /// AND of the signals. True if there are none.
+  And(Vec<String>),
+  /// XOR of the signals. False if there are none.
+  Xor(Vec<String>),
+  /// `select ? then : otherwise`
+  Mux { select: String, then: String, otherwise: String },
+}
+
+impl Expr {
+  /// Evaluate with `signal` giving the value of each named signal
+  pub fn eval(&self, signal: impl Fn(&str) -> bool) -> bool {
+    match self {
+      Expr::Const(value) => *value,
+      Expr::Or(names)  => names.iter().any(|name| signal(name)),
+      Expr::And(names) => names.iter().all(|name| signal(name)),
+      Expr::Xor(names) => names.iter().fold(false, |acc, name| acc ^ signal(name)),
+      Expr::Mux { select, then, otherwise } => {
+        if signal(select) { signal(then) } else { signal(otherwise) }
+      },
+    }
+  }
+
+  fn to_verilog(&self) -> String {
+    let join = |names: &[String], op: &str, empty: bool| match names.len() {
+      0 => Expr::Const(empty).to_verilog(),
+      _ => names.join(op),
+    };
+    match self {
+      Expr::Const(value) => format!("1'b{}", *value as u8),
+      Expr::Or(names)  => join(names, " | ", false),
+      Expr::And(names) => join(names, " & ", true),
+      Expr::Xor(names) => join(names, " ^ ", false),
+      Expr::Mux { select, then, otherwise } => format!("{} ? {} : {}", select, then, otherwise),
+    }
+  }
+}
+
+/// A circuit as a Verilog module, before it's written out
+#[derive(Debug, Clone)]
+pub struct VerilogModule {
+  pub name: String,
+  /// Input port names, and their (dense) wire indices
+  pub inputs: Vec<(String, usize)>,
+  /// Output port names, and their (dense) wire indices
+  pub outputs: Vec<(String, usize)>,
+  /// Reset value of each wire register
+  pub wire_init: Vec<bool>,
+  /// Combinational signals, in an order they can be evaluated in
+  pub assigns: Vec<(String, Expr)>,
+  /// The next value of each wire register
+  pub next_state: Vec<Expr>,
+}
+
+const VERILOG_KEYWORDS: &[&str] = &[
+  "always", "and", "assign", "automatic", "begin", "buf", "bufif0", "bufif1",
+  "case", "casex", "casez", "cell", "cmos", "config", "deassign", "default",
+  "defparam", "design", "disable", "edge", "else", "end", "endcase",
+  "endconfig", "endfunction", "endgenerate", "endmodule", "endprimitive",
+  "endspecify", "endtable", "endtask", "event", "for", "force", "forever",
+  "fork", "function", "generate", "genvar", "highz0", "highz1", "if",
+  "ifnone", "incdir", "include", "initial", "inout", "input", "instance",
+  "integer", "join", "large", "liblist", "library", "localparam",
+  "macromodule", "medium", "module", "nand", "negedge", "nmos", "nor",
+  "noshowcancelled", "not", "notif0", "notif1", "or", "output", "parameter",
+  "pmos", "posedge", "primitive", "pull0", "pull1", "pulldown", "pullup",
+  "rcmos", "real", "realtime", "reg", "release", "repeat", "rnmos", "rpmos",
+  "rtran", "rtranif0", "rtranif1", "scalared", "signed", "small", "specify",
+  "specparam", "strong0", "strong1", "supply0", "supply1", "table", "task",
+  "time", "tran", "wire",
+];
+
+/// Check `name` is a Verilog identifier that isn't a keyword
+fn check_identifier(name: &str) -> Result<(), ResoError> {
+  let mut chars = name.chars();
+  let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+    && !VERILOG_KEYWORDS.contains(&name);
+  if valid {
+    Ok(())
+  } else {
+    Err(ResoError::Export(format!("`{}` is not a Verilog identifier", name)))
+  }
+}
+
+impl VerilogModule {
+  /// Build the module for `rc`. Fails if `name` or a port name can't be used,
+  /// or if it has buses.
+  pub fn from_circuit(rc: &ResoCircuit, name: &str) -> Result<VerilogModule, ResoError> {
+    let rm = &rc.rm;
+    if !(rm.bus_regions.is_empty() && rm.bus_input_regions.is_empty() && rm.bus_output_regions.is_empty()) {
+      return Err(ResoError::Export("buses aren't supported in Verilog yet".to_string()))
+    }
+    let im = &rc.im;
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    for port in &rc.ports {
+      match port.direction {
+        PortDirection::Input  => inputs.push((port.name.clone(), port.wire)),
+        PortDirection::Output => outputs.push((port.name.clone(), port.wire)),
+      }
+    }
+
+    // Wires as seen this step: the register, unless an input port holds it.
+    // Like iterate, a later port on the same wire wins.
+    let mut assigns = vec![];
+    for wi in 0..im.wire_inc_outputs.len() {
+      let mut seen = format!("w{}_q", wi);
+      let holds: Vec<&(String, usize)> = inputs.iter().filter(|(_, w)| *w == wi).collect();
+      for (k, (port, _)) in holds.iter().enumerate() {
+        let held = if k + 1 == holds.len() { format!("w{}", wi) } else { format!("w{}_h{}", wi, k) };
+        assigns.push((held.clone(), Expr::Mux {
+          select: format!("{}_hold", port), then: port.clone(), otherwise: seen,
+        }));
+        seen = held;
+      }
+      if holds.is_empty() {
+        assigns.push((format!("w{}", wi), Expr::Or(vec![seen])));
+      }
+    }
+
+    let names = |prefix: &str, suffix: &str, indices: &[usize]| -> Vec<String> {
+      indices.iter().map(|i| format!("{}{}{}", prefix, i, suffix)).collect()
+    };
+    for (ii, inc_wires) in im.input_inc_wires.iter().enumerate() {
+      assigns.push((format!("in{}_or", ii),  Expr::Or(names("w", "", inc_wires))));
+      assigns.push((format!("in{}_and", ii), Expr::And(names("w", "", inc_wires))));
+      assigns.push((format!("in{}_xor", ii), Expr::Xor(names("w", "", inc_wires))));
+    }
+    for (li, inc_inputs) in im.logic_inc_inputs.iter().enumerate() {
+      let fold = if rc.rm.region_to_resel[rc.rm.logic_regions[li]] == Resel::AND {
+        "_and"
+      } else {
+        "_xor"
+      };
+      assigns.push((format!("logic{}", li), Expr::Or(names("in", fold, inc_inputs))));
+    }
+    for (oi, inc_inputs) in im.output_inc_inputs.iter().enumerate() {
+      let mut sources = names("in", "_or", inc_inputs);
+      sources.extend(names("logic", "", &im.output_inc_logics[oi]));
+      assigns.push((format!("out{}", oi), Expr::Or(sources)));
+    }
+    let next_state = im.wire_inc_outputs.iter()
+      .map(|inc_outputs| Expr::Or(names("out", "", inc_outputs)))
+      .collect();
+
+    // Every name we declare must be unique, and a valid identifier
+    check_identifier(name)?;
+    let mut declared: HashSet<String> = ["clk", "rst", "wires"].map(String::from).into();
+    declared.extend((0..im.wire_inc_outputs.len()).map(|wi| format!("w{}_q", wi)));
+    declared.extend(assigns.iter().map(|(name, _)| name.clone()));
+    let port_names = inputs.iter().flat_map(|(port, _)| [port.clone(), format!("{}_hold", port)])
+      .chain(outputs.iter().map(|(port, _)| port.clone()));
+    for port in port_names {
+      check_identifier(&port)?;
+      if !declared.insert(port.clone()) {
+        return Err(ResoError::Export(format!("port name `{}` is already used", port)))
+      }
+    }
+
+    Ok(VerilogModule {
+      name: name.to_string(),
+      inputs,
+      outputs,
+      wire_init: rc.wire_state.clone(),
+      assigns,
+      next_state,
+    })
+  }
+
+  /// The `wires` bus as a Verilog literal, wire 0 last
+  fn wires_literal(wires: &[bool]) -> String {
+    let bits: String = wires.iter().rev().map(|on| if *on { '1' } else { '0' }).collect();
+    if bits.is_empty() { "1'b0".to_string() } else { format!("{}'b{}", bits.len(), bits) }
+  }
+
+  fn wires_width(&self) -> String {
+    match self.wire_init.len() {
+      0 | 1 => String::new(),
+      n => format!("[{}:0] ", n - 1),
+    }
+  }
+
+  /// Write the module as Verilog source
+  pub fn to_verilog(&self) -> String {
+    let num_wires = self.wire_init.len();
+    let mut v = format!(
+      "// Generated by reso: {} wires, one clock cycle per step\nmodule {} (\n",
+      num_wires, self.name
+    );
+    let mut ports = vec!["  input  wire clk".to_string(), "  input  wire rst".to_string()];
+    for (port, _) in &self.inputs {
+      ports.push(format!("  input  wire {}", port));
+      ports.push(format!("  input  wire {}_hold", port));
+    }
+    for (port, _) in &self.outputs {
+      ports.push(format!("  output wire {}", port));
+    }
+    ports.push(format!("  output wire {}wires", self.wires_width()));
+    v.push_str(&ports.join(",\n"));
+    v.push_str("\n);\n");
+
+    for wi in 0..num_wires {
+      v.push_str(&format!("  reg  w{}_q;\n", wi));
+    }
+    for (name, _) in &self.assigns {
+      v.push_str(&format!("  wire {};\n", name));
+    }
+    for (name, expr) in &self.assigns {
+      v.push_str(&format!("  assign {} = {};\n", name, expr.to_verilog()));
+    }
+
+    if num_wires > 0 {
+      v.push_str("\n  always @(posedge clk) begin\n    if (rst) begin\n");
+      for (wi, on) in self.wire_init.iter().enumerate() {
+        v.push_str(&format!("      w{}_q <= {};\n", wi, Expr::Const(*on).to_verilog()));
+      }
+      v.push_str("    end else begin\n");
+      for (wi, expr) in self.next_state.iter().enumerate() {
+        v.push_str(&format!("      w{}_q <= {};\n", wi, expr.to_verilog()));
+      }
+      v.push_str("    end\n  end\n\n");
+    }
+
+    for (port, wi) in &self.outputs {
+      v.push_str(&format!("  assign {} = w{};\n", port, wi));
+    }
+    let bus: Vec<String> = (0..num_wires).rev().map(|wi| format!("w{}", wi)).collect();
+    v.push_str(&format!("  assign wires = {};\n", match bus.len() {
+      0 => "1'b0".to_string(),
+      _ => format!("{{{}}}", bus.join(", ")),
+    }));
+    v.push_str("endmodule\n");
+    v
+  }
+
+  /// Write a testbench checking the module's `wires` against `trace`, where
+  /// `trace[t]` is the wire state after `t` steps. `held` gives the value
+  /// each input port is held at, if any.
+  pub fn testbench(&self, trace: &[Vec<bool>], held: &[Option<bool>]) -> String {
+    let steps = trace.len().saturating_sub(1);
+    let width = self.wire_init.len().max(1);
+    let mut v = format!("`timescale 1ns/1ps\nmodule {}_tb;\n", self.name);
+    v.push_str("  reg clk = 1'b0;\n  reg rst = 1'b1;\n");
+    for ((port, _), held) in self.inputs.iter().zip(held) {
+      v.push_str(&format!("  reg {} = 1'b{};\n", port, held.unwrap_or(false) as u8));
+      v.push_str(&format!("  reg {}_hold = 1'b{};\n", port, held.is_some() as u8));
+    }
+    for (port, _) in &self.outputs {
+      v.push_str(&format!("  wire {};\n", port));
+    }
+    v.push_str(&format!("  wire {}wires;\n", self.wires_width()));
+    v.push_str(&format!("  reg [{}:0] expected [0:{}];\n", width - 1, steps));
+    v.push_str("  integer t;\n  integer errors = 0;\n\n");
+
+    let mut connections = vec![".clk(clk)".to_string(), ".rst(rst)".to_string()];
+    for (port, _) in &self.inputs {
+      connections.push(format!(".{0}({0})", port));
+      connections.push(format!(".{0}_hold({0}_hold)", port));
+    }
+    for (port, _) in &self.outputs {
+      connections.push(format!(".{0}({0})", port));
+    }
+    connections.push(".wires(wires)".to_string());
+    v.push_str(&format!("  {} dut ({});\n\n", self.name, connections.join(", ")));
+
+    v.push_str("  initial begin\n");
+    for (t, wires) in trace.iter().enumerate() {
+      v.push_str(&format!("    expected[{}] = {};\n", t, VerilogModule::wires_literal(wires)));
+    }
+    v.push_str(&format!(
+      concat!(
+        "    #1 clk = 1'b1; #1 clk = 1'b0; rst = 1'b0;\n",
+        "    for (t = 0; t <= {}; t = t + 1) begin\n",
+        "      #1;\n",
+        "      if (wires !== expected[t]) begin\n",
+        "        $display(\"step %0d: wires %b, expected %b\", t, wires, expected[t]);\n",
+        "        errors = errors + 1;\n",
+        "      end\n",
+        "      clk = 1'b1; #1 clk = 1'b0;\n",
+        "    end\n",
+        "    if (errors == 0) $display(\"PASS\");\n",
+        "    else $display(\"FAIL: %0d of {} steps differ\", errors);\n",
+        "    $finish;\n",
+        "  end\n",
+        "endmodule\n",
+      ),
+      steps, steps + 1
+    ));
+    v
+  }
+}
+
+impl ResoCircuit {
+  /// This circuit as a Verilog module named `name`. See `reso::verilog`.
+  pub fn to_verilog(&self, name: &str) -> Result<String, ResoError> {
+    Ok(VerilogModule::from_circuit(self, name)?.to_verilog())
+  }
+
+  /// A testbench for `to_verilog(name)`, checking it against `steps` steps
+  /// of `iterate` from the current state
+  pub fn to_verilog_testbench(&self, name: &str, steps: usize) -> Result<String, ResoError> {
+    let module = VerilogModule::from_circuit(self, name)?;
+    let mut rc = self.clone();
+    let mut trace = vec![rc.wire_state.clone()];
+    for _ in 0..steps {
+      rc.iterate();
+      trace.push(rc.wire_state.clone());
+    }
+    let held: Vec<Option<bool>> = self.ports.iter()
+      .filter(|port| port.direction == PortDirection::Input)
+      .map(|port| port.held)
+      .collect();
+    Ok(module.testbench(&trace, &held))
+  }
+}
+
+#[cfg(test)]
+mod verilog_tests {
+  use super::*;
+  use crate::ports::{PortSpec};
+  use std::collections::{HashMap};
+
+  /// Simulate a VerilogModule the way a Verilog simulator would, returning
+  /// the `wires` bus after each of `steps` clock cycles (and after reset)
+  fn simulate(module: &VerilogModule, held: &[Option<bool>], steps: usize) -> Vec<Vec<bool>> {
+    let mut signals: HashMap<String, bool> = HashMap::new();
+    for ((port, _), held) in module.inputs.iter().zip(held) {
+      signals.insert(port.clone(), held.unwrap_or(false));
+      signals.insert(format!("{}_hold", port), held.is_some());
+    }
+    let mut registers = module.wire_init.clone();
+    let mut trace = vec![];
+    for step in 0..=steps {
+      for (wi, on) in registers.iter().enumerate() {
+        signals.insert(format!("w{}_q", wi), *on);
+      }
+      for (name, expr) in &module.assigns {
+        let value = expr.eval(|signal| signals[signal]);
+        signals.insert(name.clone(), value);
+      }
+      trace.push((0..registers.len()).map(|wi| signals[&format!("w{}", wi)]).collect());
+      if step < steps {
+        registers = module.next_state.iter().map(|expr| expr.eval(|signal| signals[signal])).collect();
+      }
+    }
+    trace
+  }
+
+  fn check_against_iterate(mut rc: ResoCircuit, steps: usize) {
+    let module = VerilogModule::from_circuit(&rc, "dut").unwrap();
+    let held: Vec<Option<bool>> = rc.ports.iter()
+      .filter(|port| port.direction == PortDirection::Input)
+      .map(|port| port.held)
+      .collect();
+
+    let trace = simulate(&module, &held, steps);
+    assert_eq!(trace[0], rc.wire_state);
+    for wires in &trace[1..] {
+      rc.iterate();
+      assert_eq!(*wires, rc.wire_state);
+    }
+  }
+
+  #[test]
+  fn test_matches_iterate() {
+    check_against_iterate(ResoCircuit::from_filename("./reso_logo.png").unwrap(), 8);
+    check_against_iterate(ResoCircuit::from_filename("./src/testing/test_clock.txt").unwrap(), 6);
+
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+      let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+      rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+      rc.set_input("a", a).unwrap();
+      rc.set_input("b", b).unwrap();
+      check_against_iterate(rc, 4);
+    }
+  }
+
+  #[test]
+  fn test_half_adder_verilog() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    rc.load_ports("./src/testing/test_half_adder.ports").unwrap();
+    let verilog = rc.to_verilog("half_adder").unwrap();
+    for line in [
+      "module half_adder (",
+      "  input  wire a_hold,",
+      "  output wire [3:0] wires",
+      "  assign w0 = a_hold ? a : w0_q;",
+      "  assign w3 = w3_q;",
+      "  assign logic0 = in0_xor;",
+      "  assign logic1 = in0_and;",
+      "      w2_q <= out0;",
+      "  assign carry = w3;",
+      "  assign wires = {w3, w2, w1, w0};",
+    ] {
+      assert!(verilog.lines().any(|l| l == line), "missing {:?} in\n{}", line, verilog);
+    }
+
+    rc.set_input("a", true).unwrap();
+    let testbench = rc.to_verilog_testbench("half_adder", 2).unwrap();
+    for line in [
+      "  reg a = 1'b1;",
+      "  reg a_hold = 1'b1;",
+      "  reg b_hold = 1'b0;",
+      "    expected[0] = 4'b0001;",
+      "    expected[2] = 4'b0101;",
+    ] {
+      assert!(testbench.lines().any(|l| l == line), "missing {:?} in\n{}", line, testbench);
+    }
+  }
+
+  #[test]
+  fn test_bad_names() {
+    let mut rc = ResoCircuit::from_filename("./src/testing/test_half_adder.png").unwrap();
+    assert!(matches!(rc.to_verilog("module"), Err(ResoError::Export(_))));
+    assert!(matches!(rc.to_verilog("9lives"), Err(ResoError::Export(_))));
+
+    rc.add_port(&PortSpec {
+      direction: PortDirection::Input, name: "w0".to_string(), x: 0, y: 2
+    }).unwrap();
+    assert!(matches!(rc.to_verilog("ok"), Err(ResoError::Export(_))));
+
+    let rb = crate::reselboard::ReselBoard::from_text("⠁{&}⠂", &Default::default()).unwrap();
+    assert!(matches!(ResoCircuit::from(rb).to_verilog("bus"), Err(ResoError::Export(_))));
+  }
+}
+
+// eof